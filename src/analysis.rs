@@ -0,0 +1,1094 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+use indicatif::{ProgressBar, ProgressStyle};
+use object::read::macho::{FatArch, MachOFatFile32};
+use object::read::Object;
+use object::{CompressionFormat, File, FileKind, SectionKind};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+use wholesym::debugid::DebugId;
+use wholesym::samply_symbols::relative_address_base;
+use wholesym::{MultiArchDisambiguator, SymbolManager};
+
+use crate::boundaries::LookupBoundaries;
+use crate::crates::{CrateInfo, CrateMap};
+use crate::data_symbols::DataSymbols;
+use crate::debug_sections::DebugSections;
+use crate::dwarf::DwarfData;
+use crate::sink::RangeSink;
+use crate::strings::StringLiterals;
+use crate::symbol_tables::{namespace, SymbolTables};
+use crate::symbols::{parse_debug_file, SymbolConfig};
+use crate::units::CompileUnits;
+use crate::unwind::UnwindRecords;
+
+/// Options for [`analyze_binary`].
+#[derive(Debug, Clone, Default)]
+pub struct AnalysisOptions {
+    /// Where to look for debug info.
+    pub symbol_config: SymbolConfig,
+    /// For fat (universal) binaries, only analyze the member with this
+    /// architecture, as if it was a standalone binary.
+    pub arch: Option<String>,
+    /// Look up the debug info for every byte individually, instead of once
+    /// per address range from the DWARF line tables.
+    pub brute_force: bool,
+    /// Display a progress bar on stderr while the text sections are looked up.
+    pub show_progress: bool,
+    /// Maps the source paths of code to Rust crates.
+    pub crates: CrateMap,
+    /// Find the string literals of each binary, for
+    /// [`Binary::string_literals`]. This scans all of the code and data, so
+    /// it's off by default.
+    pub find_strings: bool,
+}
+
+/// A binary, or one member of a fat archive.
+#[derive(Debug)]
+pub struct Binary {
+    /// The name of the fat archive member, usually its arch. `None` for thin
+    /// binaries, and for the member selected with [`AnalysisOptions::arch`].
+    pub member_name: Option<String>,
+    /// The offset of the binary in the file.
+    pub file_offset: u64,
+    pub size: u64,
+    /// The relative addresses which the binary's segments occupy in memory.
+    pub relative_address_range: Range<u64>,
+    pub lib_info: wholesym::LibraryInfo,
+    /// `None` unless [`AnalysisOptions::find_strings`] is set.
+    pub string_literals: Option<StringLiterals>,
+}
+
+/// A section of a [`Binary`]. The file offset is relative to the start of
+/// the binary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub file_offset: u64,
+    pub svma: u64,
+    pub size: u64,
+    pub kind: SectionKind,
+    pub name: String,
+    pub is_compressed: bool,
+    /// Same as `size` unless the section is compressed.
+    pub uncompressed_size: u64,
+}
+
+/// A range of bytes in the file which are all attributed to the same things.
+#[derive(Debug, Clone)]
+pub struct AttributedRange {
+    /// The offset of the first byte in the file.
+    pub file_offset: u64,
+    pub size: u64,
+    /// `None` for padding between the members of a fat archive.
+    pub binary: Option<Arc<Binary>>,
+    /// `None` for bytes outside of any section, e.g. headers and padding.
+    pub section: Option<Arc<Section>>,
+    /// The relative address of the first byte, for ranges in text sections.
+    pub relative_address: Option<u32>,
+    /// The symbol and the inline frames, innermost frame first, for ranges
+    /// in text sections. In data sections this is the data symbol, in unwind
+    /// tables the function that the record belongs to, and in DWARF sections
+    /// the compilation unit, with a frame for the source file. In symbol and
+    /// string tables it's the symbol that the entry or the name belongs to.
+    /// `None` if no symbol covers these bytes.
+    pub addr_info: Option<wholesym::AddressInfo>,
+    /// The Rust crate of the outer function's source file. In symbol and
+    /// string tables, the crate or namespace of the symbol's name if the
+    /// symbol has no source file.
+    pub crate_info: Option<CrateInfo>,
+    /// The Rust crate whose compilation unit contains these bytes. For
+    /// generic functions this is the crate that instantiated them, rather than
+    /// the crate that defines them. Same as `crate_info` if the binary has no
+    /// DWARF.
+    pub instantiating_crate: Option<CrateInfo>,
+}
+
+impl AttributedRange {
+    /// The source file of the outer function.
+    pub fn outer_function_path(&self) -> Option<String> {
+        let frames = self.addr_info.as_ref()?.frames.as_ref()?;
+        let file_path = frames.last()?.file_path.as_ref()?;
+        Some(file_path.display_path())
+    }
+}
+
+/// The result of [`analyze_binary`].
+#[derive(Debug, Default)]
+pub struct Analysis {
+    /// Consecutive ranges in file order. Together they cover the whole file,
+    /// or the selected fat archive member if [`AnalysisOptions::arch`] is set.
+    pub ranges: Vec<AttributedRange>,
+    /// The end of the analyzed byte range.
+    pub end_file_offset: u64,
+    /// The problems with the debug info, see [`RangeSink::warning`].
+    pub warnings: Vec<String>,
+}
+
+impl RangeSink for Analysis {
+    fn range(&mut self, range: &AttributedRange) {
+        self.ranges.push(range.clone());
+    }
+
+    fn warning(&mut self, message: &str) {
+        self.warnings.push(message.to_owned());
+    }
+
+    fn finish(&mut self, end_file_offset: u64) {
+        self.end_file_offset = end_file_offset;
+    }
+}
+
+/// Reads the binary at `path` and attributes all of its bytes, using the
+/// debug info that `options.symbol_config` finds.
+///
+/// Returns an error if the file can't be read or parsed, or if its sections
+/// overlap or extend past the end of the file.
+///
+/// This must be called from within a Tokio runtime, because the lookups for
+/// the text sections are spawned as tasks. With the multi-threaded runtime,
+/// they run on all cores.
+pub async fn analyze_binary(
+    path: &Path,
+    options: &AnalysisOptions,
+) -> Result<Analysis, Box<dyn std::error::Error>> {
+    let mut analysis = Analysis::default();
+    analyze_binary_into(path, options, &mut analysis).await?;
+    Ok(analysis)
+}
+
+/// Like [`analyze_binary`], but passes the ranges to `sink` as soon as they
+/// are known, instead of collecting them. If it returns an error, the sink
+/// may already have received the ranges before the error. Like
+/// [`analyze_binary`], this needs a Tokio runtime.
+pub async fn analyze_binary_into<S: RangeSink + ?Sized>(
+    path: &Path,
+    options: &AnalysisOptions,
+    sink: &mut S,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let data =
+        std::fs::read(path).map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+
+    let file_kind = FileKind::parse(&data[..])
+        .map_err(|e| format!("Could not parse {}: {e}", path.display()))?;
+
+    let symbol_config = &options.symbol_config;
+    let symbol_manager = SymbolManager::with_config(symbol_config.symbol_manager_config());
+
+    // The end of the analyzed byte range.
+    let mut end_file_offset = data.len() as u64;
+
+    if file_kind == FileKind::MachOFat32 {
+        let mut previous_member_end_file_offset = 0;
+        let mut previous_member_name = None;
+        let mut found_requested_arch = false;
+        let mut available_arches = Vec::new();
+        for member in MachOFatFile32::parse(&data[..])?.arches() {
+            let member_start_file_offset = member.offset() as u64;
+            let member_size = member.size() as u64;
+
+            let data = data
+                .get(member_start_file_offset as usize..)
+                .and_then(|d| d.get(..member_size as usize))
+                .ok_or("Fat archive member extends past the end of the file")?;
+            let object_file = File::parse(data)?;
+
+            let disambiguator = if let Ok(Some(uuid)) = object_file.mach_uuid() {
+                let uuid = Uuid::from_bytes(uuid);
+                Some(MultiArchDisambiguator::DebugId(DebugId::from_uuid(uuid)))
+            } else {
+                None
+            };
+
+            let lib_info =
+                SymbolManager::library_info_for_binary_at_path(path, disambiguator.clone()).await?;
+
+            let member_name = match &lib_info.arch {
+                Some(name) => name.to_owned(),
+                None => format!(
+                    "Fat32 archive member with cputype {} and cpusubtype {}",
+                    member.cputype(),
+                    member.cpusubtype()
+                ),
+            };
+
+            if let Some(requested_arch) = &options.arch {
+                // Analyze only the requested member, as if it was a standalone binary.
+                if lib_info.arch.as_ref() != Some(requested_arch) {
+                    available_arches.push(member_name);
+                    continue;
+                }
+                found_requested_arch = true;
+
+                let symbol_map = symbol_config
+                    .load_symbol_map(&symbol_manager, path, &object_file, disambiguator)
+                    .await?;
+                let debug_info = read_debug_info(options, &object_file, &lib_info, &symbol_map);
+                let binary = Binary {
+                    member_name: None,
+                    file_offset: member_start_file_offset,
+                    size: member_size,
+                    relative_address_range: relative_address_range(&object_file),
+                    lib_info,
+                    string_literals: None,
+                };
+                process_binary(binary, &object_file, symbol_map, debug_info, options, sink).await?;
+
+                end_file_offset = member_start_file_offset + member_size;
+                break;
+            }
+
+            if member_start_file_offset < previous_member_end_file_offset {
+                return Err(format!("Overlapping fat archive members: Member with arch {member_name} starts at file offset {member_start_file_offset:#x} which is before the end file offset {previous_member_end_file_offset:#x} of member with arch {}", previous_member_name.unwrap_or_default()).into());
+            }
+
+            if member_start_file_offset > previous_member_end_file_offset {
+                sink.padding(&padding_range(
+                    None,
+                    previous_member_end_file_offset,
+                    member_start_file_offset,
+                ));
+            }
+
+            let symbol_map = symbol_config
+                .load_symbol_map(&symbol_manager, path, &object_file, disambiguator)
+                .await?;
+            let debug_info = read_debug_info(options, &object_file, &lib_info, &symbol_map);
+            let binary = Binary {
+                member_name: Some(member_name.clone()),
+                file_offset: member_start_file_offset,
+                size: member_size,
+                relative_address_range: relative_address_range(&object_file),
+                lib_info,
+                string_literals: None,
+            };
+            process_binary(binary, &object_file, symbol_map, debug_info, options, sink).await?;
+
+            previous_member_end_file_offset = member_start_file_offset + member_size;
+            previous_member_name = Some(member_name);
+        }
+
+        if let Some(requested_arch) = &options.arch {
+            if !found_requested_arch {
+                return Err(format!(
+                    "{} has no member with arch {requested_arch}. Available arches: {}",
+                    path.display(),
+                    available_arches.join(", ")
+                )
+                .into());
+            }
+        } else {
+            let file_end_file_offset = data.len() as u64;
+            if file_end_file_offset < previous_member_end_file_offset {
+                return Err(format!("Truncated fat archive member: File size is {file_end_file_offset:#x} which is less than the end file offset {previous_member_end_file_offset:#x} of member {}", previous_member_name.unwrap_or_default()).into());
+            }
+
+            if file_end_file_offset > previous_member_end_file_offset {
+                sink.padding(&padding_range(
+                    None,
+                    previous_member_end_file_offset,
+                    file_end_file_offset,
+                ));
+            }
+        }
+    } else {
+        let data = &data[..];
+
+        let object_file = File::parse(data)?;
+
+        let lib_info = SymbolManager::library_info_for_binary_at_path(path, None).await?;
+
+        if let Some(requested_arch) = &options.arch {
+            if lib_info.arch.as_ref() != Some(requested_arch) {
+                return Err(format!(
+                    "{} is not a fat binary and its arch is {}, not {requested_arch}",
+                    path.display(),
+                    lib_info.arch.as_deref().unwrap_or("unknown")
+                )
+                .into());
+            }
+        }
+
+        let symbol_map = symbol_config
+            .load_symbol_map(&symbol_manager, path, &object_file, None)
+            .await?;
+        let debug_info = read_debug_info(options, &object_file, &lib_info, &symbol_map);
+        let binary = Binary {
+            member_name: None,
+            file_offset: 0,
+            size: data.len() as u64,
+            relative_address_range: relative_address_range(&object_file),
+            lib_info,
+            string_literals: None,
+        };
+        process_binary(binary, &object_file, symbol_map, debug_info, options, sink).await?;
+    }
+
+    sink.finish(end_file_offset);
+    Ok(())
+}
+
+/// What we read from the DWARF, the symbol tables and the unwind tables
+/// ourselves, in addition to the symbol map.
+struct DebugInfo {
+    /// `None` if we need to look up every byte individually.
+    boundaries: Option<LookupBoundaries>,
+    units: Option<CompileUnits>,
+    data_symbols: DataSymbols,
+    /// The units of the DWARF sections in the binary itself.
+    debug_sections: Option<DebugSections>,
+    unwind_records: UnwindRecords,
+    symbol_tables: SymbolTables,
+    /// `None` unless [`AnalysisOptions::find_strings`] is set.
+    string_literals: Option<StringLiterals>,
+    /// For [`RangeSink::warning`].
+    warnings: Vec<String>,
+}
+
+/// Reads the lookup boundaries, unless we're brute forcing, the compilation
+/// units, the symbols in the data sections, the units of the binary's own
+/// DWARF sections, the records of its unwind tables, the entries of its
+/// symbol tables and, if requested, its string literals.
+fn read_debug_info(
+    options: &AnalysisOptions,
+    object_file: &File<'_>,
+    lib_info: &wholesym::LibraryInfo,
+    symbol_map: &wholesym::SymbolMap,
+) -> DebugInfo {
+    let symbol_config = &options.symbol_config;
+    let base_addr = relative_address_base(object_file);
+    let debug_file_data = symbol_config
+        .debug_file_for_binary(object_file)
+        .map(|path| std::fs::read(path).ok());
+    let separate_debug_file;
+    let debug_file = match &debug_file_data {
+        None => Some(object_file),
+        Some(Some(data)) => {
+            separate_debug_file = parse_debug_file(data, object_file);
+            separate_debug_file.as_ref()
+        }
+        Some(None) => None,
+    };
+    // Each file's DWARF is only decompressed once, and if the binary is its
+    // own debug file, all of the tables are read from the same data.
+    let object_dwarf_data = DwarfData::load(object_file);
+    let separate_dwarf_data = match debug_file {
+        Some(debug_file) if debug_file_data.is_some() => DwarfData::load(debug_file),
+        _ => None,
+    };
+    let object_dwarf = object_dwarf_data.as_ref().map(DwarfData::dwarf);
+    let separate_dwarf = separate_dwarf_data.as_ref().map(DwarfData::dwarf);
+    let debug_dwarf = match debug_file_data {
+        None => object_dwarf.as_ref(),
+        Some(_) => separate_dwarf.as_ref(),
+    };
+    let mut warnings = Vec::new();
+    let boundaries = if options.brute_force {
+        None
+    } else {
+        let symbol_file = symbol_config.symbol_file_for_binary(lib_info);
+        LookupBoundaries::compute(
+            object_file,
+            debug_file,
+            debug_dwarf,
+            symbol_file.as_deref(),
+            symbol_map,
+            base_addr,
+            &mut warnings,
+        )
+    };
+    let debug_sections = object_dwarf
+        .as_ref()
+        .map(|d| DebugSections::compute(d, &options.crates, &mut warnings));
+    DebugInfo {
+        boundaries,
+        units: debug_dwarf.map(|d| CompileUnits::compute(d, base_addr, &options.crates)),
+        data_symbols: DataSymbols::compute(object_file, debug_file, debug_dwarf, base_addr),
+        debug_sections,
+        unwind_records: UnwindRecords::compute(object_file, base_addr),
+        symbol_tables: SymbolTables::compute(object_file, base_addr),
+        string_literals: options
+            .find_strings
+            .then(|| StringLiterals::find(object_file)),
+        warnings,
+    }
+}
+
+/// The relative addresses from the start of the first segment to the end of
+/// the last one, or of the sections if there are no segments, as in object
+/// files.
+fn relative_address_range(object_file: &File<'_>) -> Range<u64> {
+    use object::{ObjectSection, ObjectSegment};
+    let base_addr = relative_address_base(object_file);
+    let mut ranges: Vec<(u64, u64)> = object_file
+        .segments()
+        .map(|s| (s.address(), s.size()))
+        .collect();
+    if ranges.is_empty() {
+        ranges = object_file
+            .sections()
+            .map(|s| (s.address(), s.size()))
+            .collect();
+    }
+    let ranges = ranges.into_iter().filter(|(_, size)| *size != 0);
+    let start = ranges.clone().map(|(address, _)| address).min();
+    let end = ranges.map(|(address, size)| address + size).max();
+    match (start, end) {
+        (Some(start), Some(end)) => start.saturating_sub(base_addr)..end.saturating_sub(base_addr),
+        _ => 0..0,
+    }
+}
+
+fn padding_range(binary: Option<Arc<Binary>>, start: u64, end: u64) -> AttributedRange {
+    AttributedRange {
+        file_offset: start,
+        size: end - start,
+        binary,
+        section: None,
+        relative_address: None,
+        addr_info: None,
+        crate_info: None,
+        instantiating_crate: None,
+    }
+}
+
+async fn process_binary<S: RangeSink + ?Sized>(
+    binary: Binary,
+    object_file: &File<'_>,
+    symbol_map: wholesym::SymbolMap,
+    mut debug_info: DebugInfo,
+    options: &AnalysisOptions,
+    sink: &mut S,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let binary = Arc::new(Binary {
+        string_literals: debug_info.string_literals.take(),
+        ..binary
+    });
+    sink.begin_binary(&binary);
+    for warning in &debug_info.warnings {
+        sink.warning(warning);
+    }
+
+    let base_addr = relative_address_base(object_file);
+
+    let mut sections = Vec::new();
+    for s in object_file.sections() {
+        use object::ObjectSection;
+        let name = s
+            .name_bytes()
+            .map_err(|e| format!("Could not read the name of section {}: {e}", s.index().0))?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        let file_range = s
+            .compressed_file_range()
+            .map_err(|e| format!("Could not read the file range of section {name}: {e}"))?;
+        let is_compressed = file_range.format != CompressionFormat::None;
+        if file_range.uncompressed_size == 0 {
+            continue;
+        }
+
+        sections.push(Section {
+            file_offset: file_range.offset,
+            svma: s.address(),
+            size: file_range.compressed_size,
+            kind: s.kind(),
+            name,
+            is_compressed,
+            uncompressed_size: file_range.uncompressed_size,
+        });
+    }
+
+    // The Mach-O symbol and string tables aren't in a section. Object files
+    // can have them in a section-less segment too, so only add them if they
+    // don't overlap a real section.
+    for linkedit_section in &debug_info.symbol_tables.linkedit_sections {
+        let start = linkedit_section.file_offset;
+        let end = start + linkedit_section.size;
+        if !sections
+            .iter()
+            .any(|s| s.file_offset < end && start < s.file_offset + s.size)
+        {
+            sections.push(linkedit_section.clone());
+        }
+    }
+
+    sections.sort_by_key(|s| s.file_offset);
+
+    let crates = &options.crates;
+    let pb = if options.show_progress {
+        ProgressBar::new(
+            sections
+                .iter()
+                .filter(|s| s.kind == SectionKind::Text)
+                .map(|s| s.size)
+                .sum(),
+        )
+    } else {
+        ProgressBar::hidden()
+    };
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template(
+                "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})",
+            )
+            .unwrap()
+            .progress_chars("#>-"),
+    );
+
+    // Start the lookups for all text sections up front, so that they run on all
+    // cores while we pass the results to the sink in file offset order.
+    let DebugInfo {
+        boundaries,
+        units,
+        data_symbols,
+        debug_sections,
+        unwind_records,
+        symbol_tables,
+        ..
+    } = debug_info;
+    let symbol_map = Arc::new(symbol_map);
+    let boundaries = boundaries.map(Arc::new);
+    let section_lookups: Vec<_> = sections
+        .iter()
+        .map(|s| {
+            if s.kind != SectionKind::Text {
+                return Vec::new();
+            }
+            let section_start_rel = s.svma - base_addr;
+            let section_end_rel = s.svma + s.size - base_addr;
+            (section_start_rel..section_end_rel)
+                .step_by(LOOKUP_CHUNK_SIZE as usize)
+                .map(|chunk_start| {
+                    let chunk_end = (chunk_start + LOOKUP_CHUNK_SIZE).min(section_end_rel);
+                    tokio::spawn(lookup_range(
+                        symbol_map.clone(),
+                        boundaries.clone(),
+                        chunk_start,
+                        chunk_end,
+                        pb.clone(),
+                    ))
+                })
+                .collect()
+        })
+        .collect();
+
+    // The unwind records and the symbol table entries are attributed to their
+    // functions. Look those up in tasks too, rather than one at a time while
+    // we go through the sections.
+    let mut function_addresses: Vec<u64> = unwind_records
+        .functions()
+        .chain(symbol_tables.functions())
+        .collect();
+    function_addresses.sort_unstable();
+    function_addresses.dedup();
+    let function_lookups: Vec<_> = function_addresses
+        .chunks(FUNCTION_LOOKUP_CHUNK_SIZE)
+        .map(|chunk| tokio::spawn(lookup_functions(symbol_map.clone(), chunk.to_vec())))
+        .collect();
+    let mut function_infos = HashMap::new();
+    for lookup in function_lookups {
+        let infos = lookup
+            .await
+            .map_err(|e| format!("Function lookup task failed: {e}"))?;
+        function_infos.extend(infos);
+    }
+
+    let mut previous_section_end_file_offset = 0;
+    let mut previous_section_name = None;
+
+    for (s, lookups) in sections.into_iter().zip(section_lookups) {
+        let section_name = &s.name;
+        let section_start_file_offset = s.file_offset;
+
+        if section_start_file_offset < previous_section_end_file_offset {
+            return Err(format!("Overlapping sections: Section {section_name} starts at file offset {section_start_file_offset:#x} which is before the end file offset {previous_section_end_file_offset:#x} of section {}", previous_section_name.unwrap_or_default()).into());
+        }
+
+        if section_start_file_offset > previous_section_end_file_offset {
+            sink.padding(&padding_range(
+                Some(binary.clone()),
+                binary.file_offset + previous_section_end_file_offset,
+                binary.file_offset + section_start_file_offset,
+            ));
+        }
+
+        previous_section_end_file_offset = s.file_offset + s.size;
+        previous_section_name = Some(s.name.clone());
+
+        let mut offset_runs = match &debug_sections {
+            Some(debug_sections) => debug_sections.runs(&s),
+            None => Vec::new(),
+        };
+        if offset_runs.is_empty() {
+            offset_runs = symbol_table_runs(
+                &s,
+                &symbol_tables,
+                &data_symbols,
+                units.as_ref(),
+                &function_infos,
+            );
+        }
+        if !offset_runs.is_empty() {
+            process_offset_section(&binary, Arc::new(s), offset_runs, crates, sink)?;
+            continue;
+        }
+
+        let unwind_runs = unwind_runs(&s, base_addr, &unwind_records, &function_infos);
+        let data_runs = if unwind_runs.is_empty() {
+            data_runs(&s, base_addr, &data_symbols)
+        } else {
+            unwind_runs
+        };
+        process_section(
+            &binary,
+            Arc::new(s),
+            lookups,
+            data_runs,
+            crates,
+            units.as_ref(),
+            sink,
+        )
+        .await?;
+    }
+
+    let file_end_file_offset = binary.size;
+    if file_end_file_offset < previous_section_end_file_offset {
+        return Err(format!("Truncated section: File size is {file_end_file_offset:#x} which is less than the end file offset {previous_section_end_file_offset:#x} of section {}", previous_section_name.unwrap_or_default()).into());
+    }
+
+    if file_end_file_offset > previous_section_end_file_offset {
+        sink.padding(&padding_range(
+            Some(binary.clone()),
+            binary.file_offset + previous_section_end_file_offset,
+            binary.file_offset + file_end_file_offset,
+        ));
+    }
+
+    pb.finish_with_message("Binary processed");
+    Ok(())
+}
+
+/// The number of bytes that are looked up in one task.
+const LOOKUP_CHUNK_SIZE: u64 = 256 * 1024;
+
+/// The number of functions that are looked up in one task.
+const FUNCTION_LOOKUP_CHUNK_SIZE: usize = 4096;
+
+/// A range of consecutive addresses which all have the same lookup result.
+struct LookupRun {
+    relative_address: u32,
+    size: u64,
+    addr_info: Option<wholesym::AddressInfo>,
+}
+
+/// Looks up the addresses in `start..end` and merges consecutive addresses
+/// with identical results into runs.
+async fn lookup_range(
+    symbol_map: Arc<wholesym::SymbolMap>,
+    boundaries: Option<Arc<LookupBoundaries>>,
+    start: u64,
+    end: u64,
+    pb: ProgressBar,
+) -> Vec<LookupRun> {
+    let mut runs: Vec<LookupRun> = Vec::new();
+    let mut addr = start;
+    while addr < end {
+        // All addresses in addr..next_addr have the same lookup result.
+        let next_addr = match &boundaries {
+            Some(boundaries) => boundaries
+                .next_boundary_after(addr)
+                .map_or(end, |b| b.min(end)),
+            None => addr + 1,
+        };
+
+        let addr_info = symbol_map
+            .lookup(wholesym::LookupAddress::Relative(addr as u32))
+            .await;
+
+        match runs.last_mut() {
+            Some(run) if run.addr_info == addr_info => run.size += next_addr - addr,
+            _ => runs.push(LookupRun {
+                relative_address: addr as u32,
+                size: next_addr - addr,
+                addr_info,
+            }),
+        }
+        addr = next_addr;
+    }
+    pb.inc(end - start);
+    runs
+}
+
+/// Consecutive bytes of a section which we attribute by their file offset
+/// rather than by their address, like the DWARF sections and the symbol
+/// tables. The runs of a section are in file order.
+pub(crate) struct OffsetRun {
+    pub size: u64,
+    /// `None` for bytes which don't belong to anything.
+    pub addr_info: Option<wholesym::AddressInfo>,
+    /// The crate if `addr_info` has no source file.
+    pub crate_info: Option<CrateInfo>,
+    /// The Rust crate of the compilation unit that the bytes belong to.
+    pub unit_crate: Option<CrateInfo>,
+}
+
+/// Splits a data section into runs for its symbols and the bytes between
+/// them. Returns no runs if the section isn't mapped, e.g. for debug info, or
+/// has no symbols.
+fn data_runs(section: &Section, base_addr: u64, data_symbols: &DataSymbols) -> Vec<LookupRun> {
+    if section.kind == SectionKind::Text || section.is_compressed || section.svma == 0 {
+        return Vec::new();
+    }
+    let Some(start) = section.svma.checked_sub(base_addr) else {
+        return Vec::new();
+    };
+    let end = start + section.size;
+    let symbols = data_symbols.in_range(start, end);
+    let mut runs = Vec::new();
+    let mut addr = start;
+    for (i, symbol) in symbols.iter().enumerate() {
+        // Symbols inside of a preceding symbol, e.g. the strings in a string
+        // table, are part of it.
+        if symbol.address < addr {
+            continue;
+        }
+        let next_symbol_address = symbols.get(i + 1).map_or(end, |s| s.address);
+        let symbol_end = match symbol.size {
+            Some(size) => (symbol.address + size).min(end),
+            None => next_symbol_address,
+        };
+        if symbol.address > addr {
+            runs.push(LookupRun {
+                relative_address: addr as u32,
+                size: symbol.address - addr,
+                addr_info: None,
+            });
+        }
+        runs.push(LookupRun {
+            relative_address: symbol.address as u32,
+            size: symbol_end - symbol.address,
+            addr_info: Some(symbol.address_info()),
+        });
+        addr = symbol_end;
+    }
+    if !runs.is_empty() && addr < end {
+        runs.push(LookupRun {
+            relative_address: addr as u32,
+            size: end - addr,
+            addr_info: None,
+        });
+    }
+    runs
+}
+
+/// Splits an unwind table into runs for the functions whose records are in
+/// it, with the function's symbol and outer frame as the lookup result, so
+/// that the unwind info shows up below the function in the profile. Returns
+/// no runs if the section has no unwind records.
+fn unwind_runs(
+    section: &Section,
+    base_addr: u64,
+    unwind_records: &UnwindRecords,
+    function_infos: &HashMap<u64, Option<wholesym::AddressInfo>>,
+) -> Vec<LookupRun> {
+    if section.kind == SectionKind::Text || section.is_compressed || section.svma == 0 {
+        return Vec::new();
+    }
+    let Some(start) = section.svma.checked_sub(base_addr) else {
+        return Vec::new();
+    };
+    let end = start + section.size;
+    let records = unwind_records.in_range(start, end);
+    let mut runs = Vec::new();
+    let mut addr = start;
+    for record in records {
+        // Skip records inside of the preceding one.
+        if record.address < addr {
+            continue;
+        }
+        if record.address > addr {
+            runs.push(LookupRun {
+                relative_address: addr as u32,
+                size: record.address - addr,
+                addr_info: None,
+            });
+        }
+        let addr_info = function_infos.get(&record.function).cloned().flatten();
+        let record_end = (record.address + record.size).min(end);
+        runs.push(LookupRun {
+            relative_address: record.address as u32,
+            size: record_end - record.address,
+            addr_info,
+        });
+        addr = record_end;
+    }
+    if !runs.is_empty() && addr < end {
+        runs.push(LookupRun {
+            relative_address: addr as u32,
+            size: end - addr,
+            addr_info: None,
+        });
+    }
+    runs
+}
+
+/// Splits a symbol or string table into runs for the symbols whose entries or
+/// names are in it, so that the cost of each symbol shows up below the
+/// function or variable. Returns no runs if the section isn't a symbol or
+/// string table.
+fn symbol_table_runs(
+    section: &Section,
+    symbol_tables: &SymbolTables,
+    data_symbols: &DataSymbols,
+    units: Option<&CompileUnits>,
+    function_infos: &HashMap<u64, Option<wholesym::AddressInfo>>,
+) -> Vec<OffsetRun> {
+    if section.is_compressed {
+        return Vec::new();
+    }
+    let start = section.file_offset;
+    let end = start + section.size;
+    let mut runs = Vec::new();
+    let mut offset = start;
+    for (entry_offset, size, symbol) in symbol_tables.in_range(start, end) {
+        // Skip names which the linker merged into the preceding name.
+        if entry_offset < offset {
+            continue;
+        }
+        if entry_offset > offset {
+            runs.push(OffsetRun {
+                size: entry_offset - offset,
+                addr_info: None,
+                crate_info: None,
+                unit_crate: None,
+            });
+        }
+        let addr_info = match symbol.address {
+            Some(address) if symbol.is_function => function_infos.get(&address).cloned().flatten(),
+            Some(address) => data_symbols
+                .in_range(address, address + 1)
+                .first()
+                .map(|s| s.address_info()),
+            None => None,
+        };
+        let addr_info = addr_info.unwrap_or_else(|| wholesym::AddressInfo {
+            symbol: wholesym::SymbolInfo {
+                address: symbol.address.unwrap_or_default() as u32,
+                size: None,
+                name: symbol.name.clone(),
+            },
+            frames: None,
+        });
+        let entry_end = (entry_offset + size).min(end);
+        let run = OffsetRun {
+            size: entry_end - entry_offset,
+            crate_info: namespace(&symbol.name).map(|name| CrateInfo {
+                name: name.to_owned(),
+                version: None,
+            }),
+            unit_crate: match (units, symbol.address) {
+                (Some(units), Some(address)) => units.crate_at(address).cloned(),
+                _ => None,
+            },
+            addr_info: Some(addr_info),
+        };
+        // Merge consecutive entries of the same symbol, like the names of
+        // its aliases.
+        match runs.last_mut() {
+            Some(last)
+                if last.addr_info == run.addr_info
+                    && last.crate_info == run.crate_info
+                    && last.unit_crate == run.unit_crate =>
+            {
+                last.size += run.size;
+            }
+            _ => runs.push(run),
+        }
+        offset = entry_end;
+    }
+    if !runs.is_empty() && offset < end {
+        runs.push(OffsetRun {
+            size: end - offset,
+            addr_info: None,
+            crate_info: None,
+            unit_crate: None,
+        });
+    }
+    runs
+}
+
+/// Looks up the functions at `addresses`, which are relative addresses.
+async fn lookup_functions(
+    symbol_map: Arc<wholesym::SymbolMap>,
+    addresses: Vec<u64>,
+) -> Vec<(u64, Option<wholesym::AddressInfo>)> {
+    let mut infos = Vec::with_capacity(addresses.len());
+    for address in addresses {
+        infos.push((address, function_info(&symbol_map, address).await));
+    }
+    infos
+}
+
+/// Looks up the function at `address`, without the frames of the functions
+/// which are inlined at its first instruction.
+async fn function_info(
+    symbol_map: &wholesym::SymbolMap,
+    address: u64,
+) -> Option<wholesym::AddressInfo> {
+    let mut addr_info = symbol_map
+        .lookup(wholesym::LookupAddress::Relative(address as u32))
+        .await?;
+    if let Some(frames) = &mut addr_info.frames {
+        frames.drain(..frames.len().saturating_sub(1));
+    }
+    Some(addr_info)
+}
+
+/// Passes the ranges for one section to the sink. Text sections are split into
+/// ranges with identical lookup results, and data sections into ranges for
+/// their symbols, if we have `data_runs` for them. Other sections become a
+/// single range.
+async fn process_section<S: RangeSink + ?Sized>(
+    binary: &Arc<Binary>,
+    section: Arc<Section>,
+    lookups: Vec<JoinHandle<Vec<LookupRun>>>,
+    data_runs: Vec<LookupRun>,
+    crates: &CrateMap,
+    units: Option<&CompileUnits>,
+    sink: &mut S,
+) -> Result<(), Box<dyn std::error::Error>> {
+    sink.begin_section(binary, &section);
+
+    let section_start_file_offset = binary.file_offset + section.file_offset;
+    let mut pending = AttributedRange {
+        file_offset: section_start_file_offset,
+        size: 0,
+        binary: Some(binary.clone()),
+        section: Some(section.clone()),
+        relative_address: None,
+        addr_info: None,
+        crate_info: None,
+        instantiating_crate: None,
+    };
+
+    if section.kind != SectionKind::Text && data_runs.is_empty() {
+        pending.size = section.size;
+        sink.range(&pending);
+        return Ok(());
+    }
+
+    let mut add_run = |pending: &mut AttributedRange, run: LookupRun| {
+        if pending.size == 0 {
+            pending.relative_address = Some(run.relative_address);
+            pending.addr_info = run.addr_info;
+        } else if run.addr_info != pending.addr_info {
+            let next = AttributedRange {
+                file_offset: pending.file_offset + pending.size,
+                size: 0,
+                binary: Some(binary.clone()),
+                section: Some(section.clone()),
+                relative_address: Some(run.relative_address),
+                addr_info: run.addr_info,
+                crate_info: None,
+                instantiating_crate: None,
+            };
+            set_crates(pending, crates, units);
+            sink.range(pending);
+            *pending = next;
+        }
+        pending.size += run.size;
+    };
+    for run in data_runs {
+        add_run(&mut pending, run);
+    }
+    for lookup in lookups {
+        let runs = lookup
+            .await
+            .map_err(|e| format!("Lookup task for section {} failed: {e}", section.name))?;
+        for run in runs {
+            add_run(&mut pending, run);
+        }
+    }
+    set_crates(&mut pending, crates, units);
+    sink.range(&pending);
+    check_section_end(
+        &section,
+        pending.file_offset + pending.size,
+        section_start_file_offset,
+    )
+}
+
+/// Passes the ranges for the runs of a DWARF section or a symbol table to the
+/// sink. They have no relative address.
+fn process_offset_section<S: RangeSink + ?Sized>(
+    binary: &Arc<Binary>,
+    section: Arc<Section>,
+    offset_runs: Vec<OffsetRun>,
+    crates: &CrateMap,
+    sink: &mut S,
+) -> Result<(), Box<dyn std::error::Error>> {
+    sink.begin_section(binary, &section);
+
+    let section_start_file_offset = binary.file_offset + section.file_offset;
+    let mut file_offset = section_start_file_offset;
+    for run in offset_runs {
+        let mut range = AttributedRange {
+            file_offset,
+            size: run.size,
+            binary: Some(binary.clone()),
+            section: Some(section.clone()),
+            relative_address: None,
+            addr_info: run.addr_info,
+            crate_info: None,
+            instantiating_crate: None,
+        };
+        range.crate_info = range
+            .outer_function_path()
+            .and_then(|path| crates.crate_for_path(&path))
+            .or(run.crate_info);
+        range.instantiating_crate = run.unit_crate.or_else(|| range.crate_info.clone());
+        sink.range(&range);
+        file_offset += run.size;
+    }
+
+    check_section_end(&section, file_offset, section_start_file_offset)
+}
+
+/// Checks that the ranges of a section ended at the end of the section.
+fn check_section_end(
+    section: &Section,
+    end_file_offset: u64,
+    section_start_file_offset: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let expected_end_file_offset = section_start_file_offset + section.size;
+    if end_file_offset != expected_end_file_offset {
+        return Err(format!(
+            "Unexpected file offset {end_file_offset:#x} after processing section {}, which ends at {expected_end_file_offset:#x}",
+            section.name
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn set_crates(range: &mut AttributedRange, crates: &CrateMap, units: Option<&CompileUnits>) {
+    range.crate_info = range
+        .outer_function_path()
+        .and_then(|path| crates.crate_for_path(&path));
+    // The unwind info of a function is attributed to the function's unit,
+    // rather than to the unit at its own address.
+    let is_text = range
+        .section
+        .as_ref()
+        .is_some_and(|s| s.kind == SectionKind::Text);
+    let address = match &range.addr_info {
+        Some(addr_info) if !is_text => Some(addr_info.symbol.address),
+        _ => range.relative_address,
+    };
+    let unit_crate = match (units, address) {
+        (Some(units), Some(address)) => units.crate_at(u64::from(address)),
+        _ => None,
+    };
+    range.instantiating_crate = unit_crate.cloned().or_else(|| range.crate_info.clone());
+}
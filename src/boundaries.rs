@@ -0,0 +1,522 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+
+use object::read::{Object, ObjectSymbol};
+use object::File;
+use pdb::FallibleIterator;
+
+use crate::dwarf::Reader;
+
+/// The relative addresses at which the result of a symbol map lookup can
+/// change.
+///
+/// A lookup result consists of the enclosing symbol and the inline frames
+/// with their file and line. addr2line derives the frames from the line
+/// table rows and from the address ranges of the subprogram and inlined
+/// subroutine DIEs, so if we collect the start of every row, the bounds of
+/// every DIE range and the bounds of every symbol, the lookup result is the
+/// same for all addresses between two consecutive boundaries. This lets us do
+/// one lookup per range instead of one per byte, and the emitted samples are
+/// identical to the ones from the brute force walk. The line and inline
+/// records of PDBs and breakpad symbol files work the same way.
+#[derive(Debug, Default)]
+pub struct LookupBoundaries {
+    /// Sorted and deduplicated.
+    addresses: Vec<u64>,
+    /// Sorted and disjoint. The code of these ranges has debug info that we
+    /// can't read, e.g. because it's in a split DWARF `.dwo` file, so every
+    /// byte in them is looked up.
+    unknown_ranges: Vec<Range<u64>>,
+}
+
+impl LookupBoundaries {
+    /// Collects the boundaries from the symbol map, from the symbols and the
+    /// DWARF of `debug_file`, which is either the binary itself or its
+    /// separate debug file, and from `symbol_file`, a PDB or breakpad symbol
+    /// file.
+    ///
+    /// DWARF units which can't be read are skipped with a message in
+    /// `warnings`. Returns `None`, also with a message, if there is neither
+    /// DWARF nor a symbol file, in which case the caller needs to look up
+    /// every byte.
+    pub fn compute(
+        object_file: &File<'_>,
+        debug_file: Option<&File<'_>>,
+        dwarf: Option<&gimli::Dwarf<Reader<'_>>>,
+        symbol_file: Option<&Path>,
+        symbol_map: &wholesym::SymbolMap,
+        base_addr: u64,
+        warnings: &mut Vec<String>,
+    ) -> Option<Self> {
+        let mut svmas = Vec::new();
+        let mut unknown_svma_ranges = Vec::new();
+        if let Some(dwarf) = dwarf {
+            dwarf_boundaries(dwarf, &mut svmas, &mut unknown_svma_ranges, warnings);
+        }
+
+        let mut addresses = Vec::new();
+        if let Some(path) = symbol_file {
+            match symbol_file_boundaries(path, warnings) {
+                Ok(relative_addresses) => addresses = relative_addresses,
+                Err(e) => warnings.push(format!(
+                    "Could not read the lookup boundaries from {}: {e}",
+                    path.display()
+                )),
+            }
+        }
+
+        if dwarf.is_none() && addresses.is_empty() {
+            warnings.push(
+                "Could not read the lookup boundaries from DWARF, a PDB or a breakpad symbol \
+                 file, so every byte of the code is looked up individually. This is slow."
+                    .to_owned(),
+            );
+            return None;
+        }
+
+        let symbols = object_file
+            .symbols()
+            .chain(debug_file.into_iter().flat_map(|f| f.symbols()));
+        for symbol in symbols {
+            if symbol.address() == 0 {
+                continue;
+            }
+            svmas.push(symbol.address());
+            svmas.push(symbol.address() + symbol.size());
+        }
+
+        addresses.extend(
+            svmas
+                .into_iter()
+                .filter_map(|svma| svma.checked_sub(base_addr)),
+        );
+        addresses.extend(
+            symbol_map
+                .iter_symbols()
+                .map(|(relative_address, _name)| u64::from(relative_address)),
+        );
+        addresses.sort_unstable();
+        addresses.dedup();
+
+        let mut unknown_ranges: Vec<Range<u64>> = unknown_svma_ranges
+            .into_iter()
+            .filter_map(|r| Some(r.start.checked_sub(base_addr)?..r.end.checked_sub(base_addr)?))
+            .filter(|r| !r.is_empty())
+            .collect();
+        unknown_ranges.sort_unstable_by_key(|r| r.start);
+        let mut merged: Vec<Range<u64>> = Vec::with_capacity(unknown_ranges.len());
+        for range in unknown_ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+
+        Some(LookupBoundaries {
+            addresses,
+            unknown_ranges: merged,
+        })
+    }
+
+    /// Returns the first boundary which is greater than `address`.
+    pub fn next_boundary_after(&self, address: u64) -> Option<u64> {
+        let index = self.unknown_ranges.partition_point(|r| r.end <= address);
+        let next_unknown_range = self.unknown_ranges.get(index);
+        if let Some(range) = next_unknown_range {
+            if range.start <= address {
+                return Some(address + 1);
+            }
+        }
+        let index = self.addresses.partition_point(|a| *a <= address);
+        let next_address = self.addresses.get(index).copied();
+        match (next_address, next_unknown_range) {
+            (Some(a), Some(range)) => Some(a.min(range.start)),
+            (a, range) => a.or(range.map(|r| r.start)),
+        }
+    }
+}
+
+/// Adds the SVMAs of all line table rows and of the bounds of all DIE
+/// address ranges to `svmas`, and the ranges of the units whose line table
+/// or DIEs we can't read to `unknown_ranges`.
+///
+/// A unit whose header gimli can't parse is skipped without adding its
+/// ranges, because addr2line can't read it either, so the lookups only
+/// return the symbol for its code.
+fn dwarf_boundaries(
+    dwarf: &gimli::Dwarf<Reader<'_>>,
+    svmas: &mut Vec<u64>,
+    unknown_ranges: &mut Vec<Range<u64>>,
+    warnings: &mut Vec<String>,
+) {
+    let mut units = dwarf.units();
+    let mut skeleton_units = 0;
+    loop {
+        let header = match units.next() {
+            Ok(Some(header)) => header,
+            Ok(None) => break,
+            Err(e) => {
+                warnings.push(format!("Could not read the next DWARF unit header: {e}"));
+                break;
+            }
+        };
+        let unit_offset = header
+            .offset()
+            .as_debug_info_offset()
+            .map_or(0, |offset| offset.0);
+        let unit = match dwarf.unit(header) {
+            Ok(unit) => unit,
+            Err(e) => {
+                warnings.push(format!(
+                    "Could not read the DWARF unit at .debug_info offset {unit_offset:#x}: {e}"
+                ));
+                continue;
+            }
+        };
+
+        if unit.dwo_id.is_some() {
+            // This is a skeleton unit. The line table and the inline info are
+            // in the .dwo file, which we don't read.
+            skeleton_units += 1;
+        } else if let Err(e) = unit_boundaries(dwarf, &unit, svmas) {
+            warnings.push(format!(
+                "Could not read the DWARF unit at .debug_info offset {unit_offset:#x}, \
+                 so every byte of its code is looked up individually: {e}"
+            ));
+        } else {
+            continue;
+        }
+        if let Ok(mut ranges) = dwarf.unit_ranges(&unit) {
+            while let Ok(Some(range)) = ranges.next() {
+                unknown_ranges.push(range.begin..range.end);
+            }
+        }
+    }
+    if skeleton_units > 0 {
+        warnings.push(format!(
+            "{skeleton_units} DWARF units are split DWARF units, so every byte of their code \
+             is looked up individually. This is slow."
+        ));
+    }
+}
+
+/// The line table rows and the bounds of the DIE address ranges of one unit.
+fn unit_boundaries(
+    dwarf: &gimli::Dwarf<Reader<'_>>,
+    unit: &gimli::Unit<Reader<'_>>,
+    svmas: &mut Vec<u64>,
+) -> gimli::Result<()> {
+    if let Some(program) = unit.line_program.clone() {
+        let mut rows = program.rows();
+        while let Some((_, row)) = rows.next_row()? {
+            svmas.push(row.address());
+        }
+    }
+
+    let mut entries = unit.entries();
+    while let Some((_, entry)) = entries.next_dfs()? {
+        let mut ranges = dwarf.die_ranges(unit, entry)?;
+        while let Some(range) = ranges.next()? {
+            svmas.push(range.begin);
+            svmas.push(range.end);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the relative addresses of the line and inline records and of the
+/// functions in a PDB or breakpad `.sym` file.
+fn symbol_file_boundaries(
+    path: &Path,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<u64>, Box<dyn std::error::Error>> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("sym") => Ok(breakpad_boundaries(&std::fs::read_to_string(path)?)),
+        Some("pdb") => Ok(pdb_boundaries(std::fs::File::open(path)?, warnings)?),
+        _ => Err("Not a PDB or breakpad symbol file".into()),
+    }
+}
+
+/// Reads the `FUNC`, `PUBLIC`, `INLINE` and line records of a breakpad
+/// symbol file. Their addresses are relative to the image base, like ours.
+/// Malformed lines are ignored.
+fn breakpad_boundaries(text: &str) -> Vec<u64> {
+    let mut addresses = Vec::new();
+    for line in text.lines() {
+        breakpad_record_boundaries(line, &mut addresses);
+    }
+    addresses
+}
+
+fn breakpad_record_boundaries(line: &str, addresses: &mut Vec<u64>) -> Option<()> {
+    let hex = |s: &str| u64::from_str_radix(s, 16).ok();
+    let mut fields = line.split_ascii_whitespace();
+    match fields.next()? {
+        // FUNC [m] address size parameter_size name
+        // PUBLIC [m] address parameter_size name
+        record @ ("FUNC" | "PUBLIC") => {
+            let mut address = fields.next()?;
+            if address == "m" {
+                address = fields.next()?;
+            }
+            let address = hex(address)?;
+            addresses.push(address);
+            if record == "FUNC" {
+                addresses.push(address.checked_add(hex(fields.next()?)?)?);
+            }
+        }
+        // INLINE depth call_line call_file origin address size [address size]...
+        "INLINE" => {
+            let mut fields = fields.skip(4);
+            while let (Some(address), Some(size)) = (fields.next(), fields.next()) {
+                let address = hex(address)?;
+                addresses.push(address);
+                addresses.push(address.checked_add(hex(size)?)?);
+            }
+        }
+        // address size line file
+        address => {
+            let address = hex(address)?;
+            let size = hex(fields.next()?)?;
+            addresses.push(address);
+            addresses.push(address.checked_add(size)?);
+        }
+    }
+    Some(())
+}
+
+/// Reads the line programs, the procedures and the inline sites of all
+/// modules of a PDB. Modules which can't be read are skipped with a message
+/// in `warnings`.
+fn pdb_boundaries(file: std::fs::File, warnings: &mut Vec<String>) -> pdb::Result<Vec<u64>> {
+    let mut pdb = pdb::PDB::open(file)?;
+    let address_map = pdb.address_map()?;
+    let debug_information = pdb.debug_information()?;
+    let mut modules = debug_information.modules()?;
+    let mut addresses = Vec::new();
+    while let Some(module) = modules.next()? {
+        let result = pdb
+            .module_info(&module)
+            .and_then(|module_info| match module_info {
+                Some(module_info) => {
+                    pdb_module_boundaries(&module_info, &address_map, &mut addresses)
+                }
+                None => Ok(()),
+            });
+        if let Err(e) = result {
+            warnings.push(format!(
+                "Could not read the PDB module {}: {e}",
+                module.module_name()
+            ));
+        }
+    }
+    Ok(addresses)
+}
+
+fn pdb_module_boundaries(
+    module_info: &pdb::ModuleInfo<'_>,
+    address_map: &pdb::AddressMap<'_>,
+    addresses: &mut Vec<u64>,
+) -> pdb::Result<()> {
+    let mut push = |offset: pdb::PdbInternalSectionOffset, size: Option<u32>| {
+        if let Some(rva) = offset.to_rva(address_map) {
+            let address = u64::from(rva.0);
+            addresses.push(address);
+            addresses.extend(size.map(|size| address + u64::from(size)));
+        }
+    };
+
+    let program = module_info.line_program()?;
+    let mut lines = program.lines();
+    while let Some(line) = lines.next()? {
+        push(line.offset, line.length);
+    }
+
+    let inlinees: HashMap<pdb::IdIndex, pdb::Inlinee<'_>> = module_info
+        .inlinees()?
+        .map(|inlinee| Ok((inlinee.index(), inlinee)))
+        .collect()?;
+    let mut symbols = module_info.symbols()?;
+    // The inline sites of a procedure follow it, and their line offsets are
+    // relative to its start.
+    let mut procedure_offset = None;
+    while let Some(symbol) = symbols.next()? {
+        match symbol.parse() {
+            Ok(pdb::SymbolData::Procedure(procedure)) => {
+                push(procedure.offset, Some(procedure.len));
+                procedure_offset = Some(procedure.offset);
+            }
+            Ok(pdb::SymbolData::InlineSite(site)) => {
+                let (Some(procedure_offset), Some(inlinee)) =
+                    (procedure_offset, inlinees.get(&site.inlinee))
+                else {
+                    continue;
+                };
+                let mut lines = inlinee.lines(procedure_offset, &site);
+                while let Some(line) = lines.next()? {
+                    push(line.offset, line.length);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_boundary_after() {
+        let boundaries = LookupBoundaries {
+            addresses: vec![0x10, 0x20, 0x30],
+            unknown_ranges: Vec::new(),
+        };
+        assert_eq!(boundaries.next_boundary_after(0), Some(0x10));
+        assert_eq!(boundaries.next_boundary_after(0x10), Some(0x20));
+        assert_eq!(boundaries.next_boundary_after(0x1f), Some(0x20));
+        assert_eq!(boundaries.next_boundary_after(0x2f), Some(0x30));
+        assert_eq!(boundaries.next_boundary_after(0x30), None);
+        assert_eq!(boundaries.next_boundary_after(u64::MAX), None);
+    }
+
+    #[test]
+    fn next_boundary_after_without_boundaries() {
+        let boundaries = LookupBoundaries::default();
+        assert_eq!(boundaries.next_boundary_after(0), None);
+    }
+
+    #[test]
+    fn next_boundary_after_in_unknown_ranges() {
+        let boundaries = LookupBoundaries {
+            addresses: vec![0x10, 0x40],
+            unknown_ranges: vec![0x20..0x24, 0x30..0x32],
+        };
+        assert_eq!(boundaries.next_boundary_after(0x10), Some(0x20));
+        assert_eq!(boundaries.next_boundary_after(0x20), Some(0x21));
+        assert_eq!(boundaries.next_boundary_after(0x23), Some(0x24));
+        assert_eq!(boundaries.next_boundary_after(0x24), Some(0x30));
+        assert_eq!(boundaries.next_boundary_after(0x31), Some(0x32));
+        assert_eq!(boundaries.next_boundary_after(0x32), Some(0x40));
+        assert_eq!(boundaries.next_boundary_after(0x40), None);
+    }
+
+    #[test]
+    fn breakpad_records() {
+        let text = "\
+MODULE Linux x86_64 0123456789ABCDEF0123456789ABCDEF0 libfoo.so
+INFO CODE_ID 0123456789ABCDEF
+FILE 0 src/foo.rs
+INLINE_ORIGIN 0 foo::inner
+FUNC m 1000 30 0 foo::outer
+INLINE 0 12 0 0 1008 8 1020 4
+1000 8 10 0
+1008 10 13 0
+PUBLIC 2000 0 _start
+STACK CFI INIT 1000 30 .cfa: $rsp 8 +
+bogus line
+";
+        let mut addresses = breakpad_boundaries(text);
+        addresses.sort_unstable();
+        addresses.dedup();
+        assert_eq!(
+            addresses,
+            [0x1000, 0x1008, 0x1010, 0x1018, 0x1020, 0x1024, 0x1030, 0x2000]
+        );
+    }
+
+    #[test]
+    fn bad_and_split_units_are_skipped() {
+        use gimli::write::{self, Address, AttributeValue, LineProgram, LineString};
+
+        let encoding = gimli::Encoding {
+            format: gimli::Format::Dwarf32,
+            version: 4,
+            address_size: 8,
+        };
+        // A unit with a line table and a subprogram, and a skeleton unit.
+        let mut dwarf = write::Dwarf::new();
+        for (begin, dwo_id) in [(0x1000, None), (0x2000, Some(1))] {
+            let mut line_program = LineProgram::none();
+            if dwo_id.is_none() {
+                line_program = LineProgram::new(
+                    encoding,
+                    gimli::LineEncoding::default(),
+                    LineString::String(b"/src".to_vec()),
+                    LineString::String(b"lib.rs".to_vec()),
+                    None,
+                );
+                let dir = line_program.default_directory();
+                let file = line_program.add_file(LineString::String(b"lib.rs".to_vec()), dir, None);
+                line_program.begin_sequence(Some(Address::Constant(begin)));
+                line_program.row().file = file;
+                line_program.row().line = 1;
+                line_program.generate_row();
+                line_program.row().address_offset = 2;
+                line_program.row().line = 2;
+                line_program.generate_row();
+                line_program.end_sequence(0x10);
+            }
+            let unit = dwarf.units.add(write::Unit::new(encoding, line_program));
+            let unit = dwarf.units.get_mut(unit);
+            let root = unit.root();
+            let subprogram = unit.add(root, gimli::DW_TAG_subprogram);
+            let subprogram = unit.get_mut(subprogram);
+            subprogram.set(
+                gimli::DW_AT_low_pc,
+                AttributeValue::Address(Address::Constant(begin + 4)),
+            );
+            subprogram.set(gimli::DW_AT_high_pc, AttributeValue::Udata(8));
+            let root = unit.get_mut(root);
+            root.set(
+                gimli::DW_AT_low_pc,
+                AttributeValue::Address(Address::Constant(begin)),
+            );
+            root.set(gimli::DW_AT_high_pc, AttributeValue::Udata(0x10));
+            if let Some(dwo_id) = dwo_id {
+                root.set(gimli::DW_AT_GNU_dwo_id, AttributeValue::Data8(dwo_id));
+            }
+        }
+        let mut sections = write::Sections::new(write::EndianVec::new(gimli::LittleEndian));
+        dwarf.write(&mut sections).unwrap();
+        let mut data = HashMap::new();
+        sections
+            .for_each(|id, section| {
+                data.insert(id, section.slice().to_vec());
+                Ok::<_, gimli::Error>(())
+            })
+            .unwrap();
+        // A unit of version 4 whose abbrev offset is out of bounds.
+        data.get_mut(&gimli::SectionId::DebugInfo)
+            .unwrap()
+            .extend([7, 0, 0, 0, 4, 0, 0xff, 0xff, 0xff, 0xff, 8]);
+
+        let dwarf = gimli::Dwarf::load(|id| {
+            let section = data.get(&id).map_or(&[][..], |d| &d[..]);
+            Ok::<_, gimli::Error>(gimli::EndianSlice::new(
+                section,
+                gimli::RunTimeEndian::Little,
+            ))
+        })
+        .unwrap();
+        let mut svmas = Vec::new();
+        let mut unknown_ranges = Vec::new();
+        let mut warnings = Vec::new();
+        dwarf_boundaries(&dwarf, &mut svmas, &mut unknown_ranges, &mut warnings);
+        svmas.sort_unstable();
+        svmas.dedup();
+        assert_eq!(svmas, [0x1000, 0x1002, 0x1004, 0x100c, 0x1010]);
+        assert_eq!(
+            unknown_ranges,
+            [Range {
+                start: 0x2000,
+                end: 0x2010
+            }]
+        );
+        assert_eq!(warnings.len(), 2, "{warnings:?}");
+        assert!(warnings[0].starts_with("Could not read the DWARF unit at .debug_info offset"));
+        assert!(warnings[1].starts_with("1 DWARF units are split DWARF units"));
+    }
+}
@@ -0,0 +1,268 @@
+use std::io::Write;
+use std::path::Path;
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::summary::{SizeSummary, SummaryKey};
+
+/// A budget file, e.g.:
+///
+/// ```toml
+/// [[budget]]
+/// name = "whole binary"
+/// max-size = 20_000_000
+///
+/// [[budget]]
+/// section = ".text"
+/// max-size = 12_000_000
+///
+/// [[budget]]
+/// path-prefix = "/rustc/"
+/// max-size = 2_000_000
+///
+/// [[budget]]
+/// crate = "regex"
+/// max-size = 500_000
+///
+/// [[budget]]
+/// symbol = "^serde_json::"
+/// max-size = 300_000
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BudgetFile {
+    #[serde(default, rename = "budget")]
+    budgets: Vec<BudgetEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct BudgetEntry {
+    name: Option<String>,
+    section: Option<String>,
+    path_prefix: Option<String>,
+    #[serde(rename = "crate")]
+    crate_name: Option<String>,
+    symbol: Option<String>,
+    max_size: u64,
+}
+
+/// A limit on the number of bytes matching a [`Matcher`].
+#[derive(Debug)]
+pub struct Budget {
+    pub name: String,
+    pub matcher: Matcher,
+    pub max_size: u64,
+}
+
+#[derive(Debug)]
+pub enum Matcher {
+    /// All bytes of the binary.
+    Total,
+    Section(String),
+    /// Bytes whose outer function's source path starts with this prefix.
+    PathPrefix(String),
+    Crate(String),
+    /// Bytes whose symbol name matches this regex.
+    Symbol(Regex),
+}
+
+impl Matcher {
+    pub fn matches(&self, key: &SummaryKey) -> bool {
+        match self {
+            Matcher::Total => true,
+            Matcher::Section(section) => key.section.as_ref() == Some(section),
+            Matcher::PathPrefix(prefix) => key.path.as_ref().is_some_and(|p| p.starts_with(prefix)),
+            Matcher::Crate(crate_name) => key
+                .crate_info
+                .as_ref()
+                .is_some_and(|c| &c.name == crate_name),
+            Matcher::Symbol(regex) => key.symbol.as_ref().is_some_and(|s| regex.is_match(s)),
+        }
+    }
+}
+
+pub fn read_budgets(path: &Path) -> Result<Vec<Budget>, Box<dyn std::error::Error>> {
+    let s = std::fs::read_to_string(path)
+        .map_err(|e| format!("Could not read budget file {}: {e}", path.display()))?;
+    let file: BudgetFile =
+        toml::from_str(&s).map_err(|e| format!("Invalid budget file {}: {e}", path.display()))?;
+    file.budgets.into_iter().map(Budget::from_entry).collect()
+}
+
+impl Budget {
+    fn from_entry(entry: BudgetEntry) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut matchers = Vec::new();
+        if let Some(section) = entry.section {
+            matchers.push(Matcher::Section(section));
+        }
+        if let Some(prefix) = entry.path_prefix {
+            matchers.push(Matcher::PathPrefix(prefix));
+        }
+        if let Some(crate_name) = entry.crate_name {
+            matchers.push(Matcher::Crate(crate_name));
+        }
+        if let Some(symbol) = entry.symbol {
+            let regex =
+                Regex::new(&symbol).map_err(|e| format!("Invalid symbol regex {symbol}: {e}"))?;
+            matchers.push(Matcher::Symbol(regex));
+        }
+        let matcher = match matchers.len() {
+            0 => Matcher::Total,
+            1 => matchers.pop().unwrap(),
+            _ => {
+                return Err(
+                    "A budget can only have one of section, path-prefix, crate or symbol".into(),
+                )
+            }
+        };
+        let name = entry.name.unwrap_or_else(|| match &matcher {
+            Matcher::Total => "total".to_owned(),
+            Matcher::Section(section) => format!("section {section}"),
+            Matcher::PathPrefix(prefix) => format!("path prefix {prefix}"),
+            Matcher::Crate(crate_name) => format!("crate {crate_name}"),
+            Matcher::Symbol(regex) => format!("symbols matching {regex}"),
+        });
+        Ok(Budget {
+            name,
+            matcher,
+            max_size: entry.max_size,
+        })
+    }
+
+    pub fn size(&self, summary: &SizeSummary) -> u64 {
+        summary
+            .sizes
+            .iter()
+            .filter(|(key, _)| self.matcher.matches(key))
+            .map(|(_, bytes)| bytes)
+            .sum()
+    }
+}
+
+/// Writes one line per budget and returns the number of exceeded budgets.
+pub fn write_report(
+    w: &mut impl Write,
+    budgets: &[Budget],
+    summary: &SizeSummary,
+) -> std::io::Result<usize> {
+    let mut exceeded_count = 0;
+    for budget in budgets {
+        let size = budget.size(summary);
+        let max_size = budget.max_size;
+        if size > max_size {
+            exceeded_count += 1;
+            writeln!(
+                w,
+                "FAIL  {}: {size} bytes, exceeds the budget of {max_size} bytes by {} bytes",
+                budget.name,
+                size - max_size
+            )?;
+        } else {
+            writeln!(
+                w,
+                "ok    {}: {size} bytes, budget {max_size} bytes",
+                budget.name
+            )?;
+        }
+    }
+    Ok(exceeded_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use binary_size_profiler::crates::CrateInfo;
+
+    use super::*;
+
+    fn budgets(toml: &str) -> Result<Vec<Budget>, Box<dyn std::error::Error>> {
+        let file: BudgetFile = toml::from_str(toml)?;
+        file.budgets.into_iter().map(Budget::from_entry).collect()
+    }
+
+    fn key() -> SummaryKey {
+        SummaryKey {
+            section: Some(".text".to_owned()),
+            path: Some("/rustc/abc/library/core/src/fmt/mod.rs".to_owned()),
+            crate_info: Some(CrateInfo {
+                name: "core".to_owned(),
+                version: None,
+            }),
+            symbol: Some("core::fmt::write".to_owned()),
+            ..SummaryKey::default()
+        }
+    }
+
+    #[test]
+    fn matchers() {
+        let key = key();
+        assert!(Matcher::Total.matches(&key));
+        assert!(Matcher::Section(".text".to_owned()).matches(&key));
+        assert!(!Matcher::Section(".rodata".to_owned()).matches(&key));
+        assert!(Matcher::PathPrefix("/rustc/".to_owned()).matches(&key));
+        assert!(!Matcher::PathPrefix("/home/".to_owned()).matches(&key));
+        assert!(Matcher::Crate("core".to_owned()).matches(&key));
+        assert!(!Matcher::Crate("alloc".to_owned()).matches(&key));
+        assert!(Matcher::Symbol(Regex::new("^core::fmt::").unwrap()).matches(&key));
+        assert!(!Matcher::Symbol(Regex::new("^serde::").unwrap()).matches(&key));
+    }
+
+    #[test]
+    fn matchers_without_debug_info() {
+        let key = SummaryKey::default();
+        assert!(Matcher::Total.matches(&key));
+        assert!(!Matcher::Section(".text".to_owned()).matches(&key));
+        assert!(!Matcher::PathPrefix(String::new()).matches(&key));
+        assert!(!Matcher::Crate("core".to_owned()).matches(&key));
+        assert!(!Matcher::Symbol(Regex::new("").unwrap()).matches(&key));
+    }
+
+    #[test]
+    fn budget_names() {
+        let budgets = budgets(
+            r#"
+            [[budget]]
+            max-size = 100
+            [[budget]]
+            crate = "core"
+            max-size = 10
+            [[budget]]
+            name = "formatting"
+            symbol = "^core::fmt::"
+            max-size = 10
+            "#,
+        )
+        .unwrap();
+        let names: Vec<&str> = budgets.iter().map(|b| b.name.as_str()).collect();
+        assert_eq!(names, ["total", "crate core", "formatting"]);
+    }
+
+    #[test]
+    fn invalid_budgets() {
+        assert!(
+            budgets("[[budget]]\nsection = \".text\"\ncrate = \"core\"\nmax-size = 1").is_err()
+        );
+        assert!(budgets("[[budget]]\nsymbol = \"(\"\nmax-size = 1").is_err());
+        assert!(budgets("[[budget]]\nsection = \".text\"").is_err());
+    }
+
+    #[test]
+    fn report() {
+        let mut summary = SizeSummary::default();
+        summary.add(key(), 60);
+        summary.add(SummaryKey::default(), 40);
+        let budgets =
+            budgets("[[budget]]\nmax-size = 100\n[[budget]]\ncrate = \"core\"\nmax-size = 50")
+                .unwrap();
+        assert_eq!(budgets[0].size(&summary), 100);
+        assert_eq!(budgets[1].size(&summary), 60);
+        let mut output = Vec::new();
+        assert_eq!(write_report(&mut output, &budgets, &summary).unwrap(), 1);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "ok    total: 100 bytes, budget 100 bytes\n\
+             FAIL  crate core: 60 bytes, exceeds the budget of 50 bytes by 10 bytes\n"
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Deserialize;
+
+/// The Rust crate that a source file belongs to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CrateInfo {
+    pub name: String,
+    /// The package version for registry crates and workspace members, or
+    /// `git#<rev>` for git checkouts. `None` for the standard library.
+    pub version: Option<String>,
+}
+
+impl fmt::Display for CrateInfo {
+    /// "serde_json v1.0.138", "wholesym (git#a1a651e)" or "core".
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.version {
+            Some(version) if version.starts_with(|c: char| c.is_ascii_digit()) => {
+                write!(f, "{} v{version}", self.name)
+            }
+            Some(version) => write!(f, "{} ({version})", self.name),
+            None => f.write_str(&self.name),
+        }
+    }
+}
+
+/// Maps source paths to crates. Knows the members of a cargo workspace, if
+/// it was created with [`CrateMap::from_cargo_metadata`], and falls back to
+/// [`crate_for_path`] for everything else.
+#[derive(Debug, Clone, Default)]
+pub struct CrateMap {
+    workspace_root: Option<String>,
+    /// The directories of the workspace members, longest first, so that
+    /// nested members win over the members that contain them.
+    members: Vec<(String, CrateInfo)>,
+}
+
+#[derive(Deserialize)]
+struct Metadata {
+    workspace_root: PathBuf,
+    packages: Vec<Package>,
+}
+
+#[derive(Deserialize)]
+struct Package {
+    name: String,
+    version: String,
+    manifest_path: PathBuf,
+}
+
+impl CrateMap {
+    /// Runs `cargo metadata` for the workspace of the `Cargo.toml` at
+    /// `manifest_path` to find the workspace members.
+    pub fn from_cargo_metadata(manifest_path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let output = Command::new(std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into()))
+            .args([
+                "metadata",
+                "--format-version",
+                "1",
+                "--no-deps",
+                "--manifest-path",
+            ])
+            .arg(manifest_path)
+            .output()
+            .map_err(|e| format!("Could not run cargo metadata: {e}"))?;
+        if !output.status.success() {
+            return Err(format!(
+                "cargo metadata failed for {}: {}",
+                manifest_path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+        let metadata: Metadata = serde_json::from_slice(&output.stdout)
+            .map_err(|e| format!("Invalid cargo metadata output: {e}"))?;
+
+        let mut members: Vec<(String, CrateInfo)> = metadata
+            .packages
+            .into_iter()
+            .filter_map(|package| {
+                let dir = package.manifest_path.parent()?.to_str()?.to_owned();
+                let info = CrateInfo {
+                    name: package.name,
+                    version: Some(package.version),
+                };
+                Some((dir, info))
+            })
+            .collect();
+        members.sort_by_key(|(dir, _)| std::cmp::Reverse(dir.len()));
+        Ok(CrateMap {
+            workspace_root: metadata.workspace_root.to_str().map(str::to_owned),
+            members,
+        })
+    }
+
+    /// Returns the crate of the source file at `path`. Cargo passes the paths
+    /// of workspace members to rustc relative to the workspace root, so
+    /// relative paths are resolved against it.
+    pub fn crate_for_path(&self, path: &str) -> Option<CrateInfo> {
+        if !self.members.is_empty() {
+            let absolute_path;
+            let path = match &self.workspace_root {
+                Some(root) if !is_absolute(path) => {
+                    absolute_path = format!("{root}/{path}");
+                    &absolute_path
+                }
+                _ => path,
+            };
+            let member = self.members.iter().find(|(dir, _)| {
+                path.strip_prefix(dir.as_str())
+                    .is_some_and(|rest| rest.starts_with(['/', '\\']))
+            });
+            if let Some((_, info)) = member {
+                return Some(info.clone());
+            }
+        }
+        crate_for_path(path)
+    }
+}
+
+pub(crate) fn is_absolute(path: &str) -> bool {
+    path.starts_with(['/', '\\']) || path.get(1..2) == Some(":")
+}
+
+/// Returns the Rust crate that the source file at `path` belongs to, if the
+/// path has one of the well-known layouts:
+///
+///  - `~/.cargo/registry/src/<registry>/<crate>-<version>/...`
+///  - `~/.cargo/git/checkouts/<repo>-<hash>/<rev>/...`, where the crate is the
+///    directory that contains `src`, or the repository for single-crate
+///    repositories
+///  - `/rustc/<hash>/library/<crate>/...` (the standard library)
+pub fn crate_for_path(path: &str) -> Option<CrateInfo> {
+    let components: Vec<&str> = path.split(['/', '\\']).collect();
+    if let Some(i) = components.iter().position(|c| *c == "registry") {
+        if components.get(i + 1) == Some(&"src") {
+            let dir = components.get(i + 3)?;
+            let (name, version) = split_version(dir);
+            return Some(CrateInfo {
+                name: name.to_owned(),
+                version: version.map(str::to_owned),
+            });
+        }
+    }
+    if let Some(i) = components.iter().position(|c| *c == "checkouts") {
+        if i > 0 && components[i - 1] == "git" {
+            let repo = components.get(i + 1)?;
+            let rev = components.get(i + 2)?;
+            let rest = components.get(i + 3..)?;
+            let name = match rest.iter().position(|c| *c == "src") {
+                Some(src) if src > 0 => rest[src - 1],
+                _ => repo.rsplit_once('-').map_or(*repo, |(name, _hash)| name),
+            };
+            return Some(CrateInfo {
+                name: name.to_owned(),
+                version: Some(format!("git#{rev}")),
+            });
+        }
+    }
+    if let Some(i) = components.iter().position(|c| *c == "rustc") {
+        if components.get(i + 2) == Some(&"library") {
+            return components.get(i + 3).map(|c| CrateInfo {
+                name: c.to_string(),
+                version: None,
+            });
+        }
+    }
+    None
+}
+
+/// "serde_json-1.0.138" -> ("serde_json", Some("1.0.138"))
+fn split_version(dir: &str) -> (&str, Option<&str>) {
+    dir.rmatch_indices('-')
+        .map(|(i, _)| i)
+        .find(|i| dir[i + 1..].starts_with(|c: char| c.is_ascii_digit()))
+        .map_or((dir, None), |i| (&dir[..i], Some(&dir[i + 1..])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(name: &str, version: Option<&str>) -> Option<CrateInfo> {
+        Some(CrateInfo {
+            name: name.to_owned(),
+            version: version.map(str::to_owned),
+        })
+    }
+
+    #[test]
+    fn registry_crate() {
+        assert_eq!(
+            crate_for_path(
+                "/home/me/.cargo/registry/src/index.crates.io-6f17d22bba15001f/serde_json-1.0.138/src/de.rs"
+            ),
+            info("serde_json", Some("1.0.138"))
+        );
+        assert_eq!(
+            crate_for_path(
+                r"C:\Users\me\.cargo\registry\src\index.crates.io-6f17d22bba15001f\windows-sys-0.59.0\src\lib.rs"
+            ),
+            info("windows-sys", Some("0.59.0"))
+        );
+    }
+
+    #[test]
+    fn git_checkout_crate() {
+        assert_eq!(
+            crate_for_path(
+                "/home/me/.cargo/git/checkouts/samply-15b9a8d9f1b5c7a2/a1a651e/wholesym/src/lib.rs"
+            ),
+            info("wholesym", Some("git#a1a651e"))
+        );
+        assert_eq!(
+            crate_for_path(
+                "/home/me/.cargo/git/checkouts/smallvec-0c5cd6e2b7a3e1f4/b6e4c1d/lib.rs"
+            ),
+            info("smallvec", Some("git#b6e4c1d"))
+        );
+    }
+
+    #[test]
+    fn standard_library_crate() {
+        assert_eq!(
+            crate_for_path(
+                "/rustc/90b35a6239c3d8bdabc530a6a0816f7ff89a0aaf/library/core/src/fmt/mod.rs"
+            ),
+            info("core", None)
+        );
+    }
+
+    #[test]
+    fn unknown_path() {
+        assert_eq!(crate_for_path("/usr/include/stdio.h"), None);
+        assert_eq!(crate_for_path("src/main.rs"), None);
+    }
+
+    #[test]
+    fn workspace_member() {
+        let crates = CrateMap {
+            workspace_root: Some("/work".to_owned()),
+            members: vec![
+                (
+                    "/work/crates/app-core".to_owned(),
+                    CrateInfo {
+                        name: "app-core".to_owned(),
+                        version: Some("0.2.0".to_owned()),
+                    },
+                ),
+                (
+                    "/work".to_owned(),
+                    CrateInfo {
+                        name: "app".to_owned(),
+                        version: Some("0.1.0".to_owned()),
+                    },
+                ),
+            ],
+        };
+        assert_eq!(
+            crates.crate_for_path("crates/app-core/src/lib.rs"),
+            info("app-core", Some("0.2.0"))
+        );
+        assert_eq!(
+            crates.crate_for_path("/work/src/main.rs"),
+            info("app", Some("0.1.0"))
+        );
+        assert_eq!(crates.crate_for_path("/workspace/src/main.rs"), None);
+    }
+
+    #[test]
+    fn version_suffix() {
+        assert_eq!(
+            split_version("serde_json-1.0.138"),
+            ("serde_json", Some("1.0.138"))
+        );
+        assert_eq!(
+            split_version("windows-sys-0.59.0"),
+            ("windows-sys", Some("0.59.0"))
+        );
+        assert_eq!(split_version("foo-bar"), ("foo-bar", None));
+        assert_eq!(split_version("libc"), ("libc", None));
+    }
+}
@@ -0,0 +1,545 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use object::read::{Object, ObjectSymbol};
+use object::{File, SymbolKind};
+use wholesym::{AddressInfo, FrameDebugInfo, SourceFilePath, SymbolInfo};
+
+use crate::crates::is_absolute;
+use crate::demangle::demangle;
+use crate::dwarf::Reader;
+
+/// The named objects in the data sections, i.e. statics, vtables, string
+/// tables, lookup tables and so on, from the symbol tables and from the
+/// DWARF variable DIEs.
+///
+/// The symbol map only knows about functions, so without these the data
+/// sections would be opaque.
+#[derive(Default)]
+pub struct DataSymbols {
+    /// Sorted by address, at most one per address.
+    symbols: Vec<DataSymbol>,
+}
+
+pub struct DataSymbol {
+    /// Relative to the image base.
+    pub address: u64,
+    /// `None` if neither the symbol table nor the DWARF knows the size, e.g.
+    /// for Mach-O symbols. Such symbols extend to the next symbol.
+    pub size: Option<u64>,
+    pub name: String,
+    /// The declaration of the variable, from the DWARF.
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
+
+impl DataSymbols {
+    /// Collects the symbols from the symbol tables of `object_file` and
+    /// `debug_file`, and the variables from `dwarf`, the DWARF of
+    /// `debug_file`, which is either the binary itself or its separate debug
+    /// file.
+    pub fn compute(
+        object_file: &File<'_>,
+        debug_file: Option<&File<'_>>,
+        dwarf: Option<&gimli::Dwarf<Reader<'_>>>,
+        base_addr: u64,
+    ) -> Self {
+        let debug_file_symbols = debug_file.into_iter().flat_map(|f| f.symbols());
+        let symbols = object_file
+            .symbols()
+            .chain(debug_file_symbols)
+            .filter(is_data_symbol)
+            .filter_map(|symbol| {
+                Some(DataSymbol {
+                    address: symbol.address().checked_sub(base_addr)?,
+                    size: Some(symbol.size()).filter(|size| *size != 0),
+                    name: demangle(symbol.name().ok()?),
+                    file: None,
+                    line: None,
+                })
+            });
+        let variables = dwarf.map(dwarf_variables).unwrap_or_default();
+        Self::merge(symbols, variables, base_addr)
+    }
+
+    /// Merges the symbols from the symbol tables, which have relative
+    /// addresses, with the variables from the DWARF, which have SVMAs. The
+    /// first symbol at an address wins, and the others only fill in what it
+    /// doesn't know.
+    fn merge(
+        symbols: impl Iterator<Item = DataSymbol>,
+        variables: Vec<DataSymbol>,
+        base_addr: u64,
+    ) -> Self {
+        let mut by_address: HashMap<u64, DataSymbol> = HashMap::new();
+        for symbol in symbols {
+            match by_address.entry(symbol.address) {
+                Entry::Occupied(mut entry) => {
+                    let existing = entry.get_mut();
+                    existing.size = existing.size.or(symbol.size);
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(symbol);
+                }
+            }
+        }
+
+        for variable in variables {
+            let Some(address) = variable.address.checked_sub(base_addr) else {
+                continue;
+            };
+            match by_address.entry(address) {
+                Entry::Occupied(mut entry) => {
+                    let existing = entry.get_mut();
+                    existing.size = existing.size.or(variable.size);
+                    existing.file = existing.file.take().or(variable.file);
+                    existing.line = existing.line.or(variable.line);
+                }
+                // Static variables which aren't in the symbol table, e.g.
+                // because it was stripped, get their name from the DWARF.
+                Entry::Vacant(entry) => {
+                    entry.insert(DataSymbol {
+                        address,
+                        ..variable
+                    });
+                }
+            }
+        }
+
+        let mut symbols: Vec<DataSymbol> = by_address.into_values().collect();
+        symbols.sort_by_key(|s| s.address);
+        DataSymbols { symbols }
+    }
+
+    /// The symbols which start in `start..end`, sorted by address.
+    pub fn in_range(&self, start: u64, end: u64) -> &[DataSymbol] {
+        let first = self.symbols.partition_point(|s| s.address < start);
+        let last = self.symbols.partition_point(|s| s.address < end);
+        &self.symbols[first..last]
+    }
+}
+
+impl DataSymbol {
+    /// The symbol as a lookup result, with a single frame for the variable
+    /// if we know where it was declared.
+    pub fn address_info(&self) -> AddressInfo {
+        let frames = (self.file.is_some() || self.line.is_some()).then(|| {
+            vec![FrameDebugInfo {
+                function: Some(self.name.clone()),
+                file_path: self.file.clone().map(|f| SourceFilePath::new(f, None)),
+                line_number: self.line,
+            }]
+        });
+        AddressInfo {
+            symbol: SymbolInfo {
+                address: self.address as u32,
+                size: self.size.map(|size| size as u32),
+                name: self.name.clone(),
+            },
+            frames,
+        }
+    }
+}
+
+/// Skips code, section and file symbols, undefined symbols, and symbols which
+/// don't name anything, like the ARM mapping symbols (`$d`) and the Mach-O
+/// section start labels (`ltmp0`).
+fn is_data_symbol(symbol: &object::Symbol<'_, '_>) -> bool {
+    matches!(symbol.kind(), SymbolKind::Data | SymbolKind::Unknown)
+        && !symbol.is_undefined()
+        && symbol.address() != 0
+        && symbol.name().is_ok_and(|name| {
+            !name.is_empty() && !name.starts_with('$') && !name.starts_with("ltmp")
+        })
+}
+
+/// Returns the variables with a static address from `dwarf`, with SVMAs
+/// instead of relative addresses. Units which can't be read are skipped, as
+/// is the rest of a unit after an error.
+fn dwarf_variables(dwarf: &gimli::Dwarf<Reader<'_>>) -> Vec<DataSymbol> {
+    let mut variables = Vec::new();
+    let mut units = dwarf.units();
+    while let Ok(Some(header)) = units.next() {
+        let Ok(unit) = dwarf.unit(header) else {
+            continue;
+        };
+        let mut entries = unit.entries();
+        while let Ok(Some((_, entry))) = entries.next_dfs() {
+            if entry.tag() != gimli::DW_TAG_variable {
+                continue;
+            }
+            if let Some(variable) = static_variable(dwarf, &unit, entry) {
+                variables.push(variable);
+            }
+        }
+    }
+    variables
+}
+
+/// Returns the variable if its location is a plain address, i.e. if it's a
+/// static rather than a local or thread-local variable.
+fn static_variable(
+    dwarf: &gimli::Dwarf<Reader<'_>>,
+    unit: &gimli::Unit<Reader<'_>>,
+    entry: &gimli::DebuggingInformationEntry<Reader<'_>>,
+) -> Option<DataSymbol> {
+    let gimli::AttributeValue::Exprloc(location) =
+        entry.attr_value(gimli::DW_AT_location).ok()??
+    else {
+        return None;
+    };
+    let mut operations = location.operations(unit.encoding());
+    let address = match operations.next().ok()?? {
+        gimli::Operation::Address { address } => address,
+        gimli::Operation::AddressIndex { index } => dwarf.address(unit, index).ok()?,
+        _ => return None,
+    };
+    if address == 0 || operations.next().ok()?.is_some() {
+        return None;
+    }
+
+    // The definitions of C++ static members refer to their declaration,
+    // which has the name and the type.
+    let declaration = match entry.attr_value(gimli::DW_AT_specification).ok()? {
+        Some(gimli::AttributeValue::UnitRef(offset)) => unit.entry(offset).ok(),
+        _ => None,
+    };
+    let attr = |name: gimli::DwAt| {
+        let value = entry.attr_value(name).ok()?;
+        value.or_else(|| declaration.as_ref()?.attr_value(name).ok()?)
+    };
+
+    let linkage_name =
+        attr(gimli::DW_AT_linkage_name).or_else(|| attr(gimli::DW_AT_MIPS_linkage_name));
+    let name = match linkage_name {
+        Some(value) => demangle(&dwarf.attr_string(unit, value).ok()?.to_string_lossy()),
+        None => dwarf
+            .attr_string(unit, attr(gimli::DW_AT_name)?)
+            .ok()?
+            .to_string_lossy()
+            .into_owned(),
+    };
+    let size = match attr(gimli::DW_AT_type) {
+        Some(gimli::AttributeValue::UnitRef(offset)) => type_size(unit, offset, 0),
+        _ => None,
+    };
+    let file = match attr(gimli::DW_AT_decl_file) {
+        Some(gimli::AttributeValue::FileIndex(index)) => file_path(dwarf, unit, index),
+        Some(value) => file_path(dwarf, unit, value.udata_value()?),
+        None => None,
+    };
+    let line = attr(gimli::DW_AT_decl_line)
+        .and_then(|value| value.udata_value())
+        .and_then(|line| u32::try_from(line).ok());
+    Some(DataSymbol {
+        address,
+        size: size.filter(|size| *size != 0),
+        name,
+        file,
+        line,
+    })
+}
+
+/// The maximum number of typedefs, qualifiers and array dimensions which we
+/// follow to find the size of a type.
+const MAX_TYPE_DEPTH: usize = 16;
+
+/// Returns the byte size of the type at `offset`. C arrays have no byte size,
+/// so their size is computed from the element type and the dimensions.
+fn type_size(
+    unit: &gimli::Unit<Reader<'_>>,
+    offset: gimli::UnitOffset,
+    depth: usize,
+) -> Option<u64> {
+    if depth > MAX_TYPE_DEPTH {
+        return None;
+    }
+    let entry = unit.entry(offset).ok()?;
+    if let Some(size) = entry.attr_value(gimli::DW_AT_byte_size).ok()? {
+        return size.udata_value();
+    }
+    let element_size = match entry.attr_value(gimli::DW_AT_type).ok()? {
+        Some(gimli::AttributeValue::UnitRef(offset)) => type_size(unit, offset, depth + 1)?,
+        _ => return None,
+    };
+    if entry.tag() != gimli::DW_TAG_array_type {
+        // A typedef, const, volatile and so on.
+        return Some(element_size);
+    }
+    let mut size = element_size;
+    let mut tree = unit.entries_tree(Some(offset)).ok()?;
+    let mut children = tree.root().ok()?.children();
+    while let Some(child) = children.next().ok()? {
+        let child = child.entry();
+        if child.tag() != gimli::DW_TAG_subrange_type {
+            continue;
+        }
+        let count = match child.attr_value(gimli::DW_AT_count).ok()? {
+            Some(count) => count.udata_value()?,
+            None => {
+                let upper_bound = child.attr_value(gimli::DW_AT_upper_bound).ok()??;
+                upper_bound.udata_value()? + 1
+            }
+        };
+        size = size.checked_mul(count)?;
+    }
+    Some(size)
+}
+
+/// Returns the path of the file with `index` in the unit's line program
+/// header, made absolute with the directory and the unit's `comp_dir`.
+pub(crate) fn file_path(
+    dwarf: &gimli::Dwarf<Reader<'_>>,
+    unit: &gimli::Unit<Reader<'_>>,
+    index: u64,
+) -> Option<String> {
+    let header = unit.line_program.as_ref()?.header();
+    let file = header.file(index)?;
+    let mut path = dwarf
+        .attr_string(unit, file.path_name())
+        .ok()?
+        .to_string_lossy()
+        .into_owned();
+    if is_absolute(&path) {
+        return Some(path);
+    }
+    if let Some(directory) = file.directory(header) {
+        let directory = dwarf.attr_string(unit, directory).ok()?;
+        path = format!("{}/{path}", directory.to_string_lossy());
+        if is_absolute(&path) {
+            return Some(path);
+        }
+    }
+    if let Some(comp_dir) = &unit.comp_dir {
+        path = format!("{}/{path}", comp_dir.to_string_lossy());
+    }
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use gimli::write::{self, Address, AttributeValue, Expression};
+
+    use super::*;
+
+    fn encoding() -> gimli::Encoding {
+        gimli::Encoding {
+            format: gimli::Format::Dwarf32,
+            version: 4,
+            address_size: 8,
+        }
+    }
+
+    fn write_sections(dwarf: &mut write::Dwarf) -> HashMap<gimli::SectionId, Vec<u8>> {
+        let mut sections = write::Sections::new(write::EndianVec::new(gimli::LittleEndian));
+        dwarf.write(&mut sections).unwrap();
+        let mut data = HashMap::new();
+        sections
+            .for_each(|id, section| {
+                data.insert(id, section.slice().to_vec());
+                Ok::<_, gimli::Error>(())
+            })
+            .unwrap();
+        data
+    }
+
+    fn load(data: &HashMap<gimli::SectionId, Vec<u8>>) -> gimli::Dwarf<Reader<'_>> {
+        gimli::Dwarf::load(|id| {
+            let section = data.get(&id).map_or(&[][..], |d| &d[..]);
+            Ok::<_, gimli::Error>(gimli::EndianSlice::new(
+                section,
+                gimli::RunTimeEndian::Little,
+            ))
+        })
+        .unwrap()
+    }
+
+    fn add_type(
+        unit: &mut write::Unit,
+        parent: write::UnitEntryId,
+        tag: gimli::DwTag,
+        ty: Option<write::UnitEntryId>,
+    ) -> write::UnitEntryId {
+        let id = unit.add(parent, tag);
+        if let Some(ty) = ty {
+            unit.get_mut(id)
+                .set(gimli::DW_AT_type, AttributeValue::UnitRef(ty));
+        }
+        id
+    }
+
+    #[test]
+    fn type_sizes() {
+        let mut dwarf = write::Dwarf::new();
+        let unit = dwarf
+            .units
+            .add(write::Unit::new(encoding(), write::LineProgram::none()));
+        let unit = dwarf.units.get_mut(unit);
+        let root = unit.root();
+        let int = add_type(unit, root, gimli::DW_TAG_base_type, None);
+        unit.get_mut(int)
+            .set(gimli::DW_AT_byte_size, AttributeValue::Udata(4));
+        let typedef = add_type(unit, root, gimli::DW_TAG_typedef, Some(int));
+        let typedef_of_typedef = add_type(unit, root, gimli::DW_TAG_typedef, Some(typedef));
+        let constant = add_type(
+            unit,
+            root,
+            gimli::DW_TAG_const_type,
+            Some(typedef_of_typedef),
+        );
+        let volatile = add_type(unit, root, gimli::DW_TAG_volatile_type, Some(constant));
+        // const int[3][2], with a count and an upper bound.
+        let array = add_type(unit, root, gimli::DW_TAG_array_type, Some(volatile));
+        let count = unit.add(array, gimli::DW_TAG_subrange_type);
+        unit.get_mut(count)
+            .set(gimli::DW_AT_count, AttributeValue::Udata(3));
+        let upper_bound = unit.add(array, gimli::DW_TAG_subrange_type);
+        unit.get_mut(upper_bound)
+            .set(gimli::DW_AT_upper_bound, AttributeValue::Udata(1));
+        // A flexible array member has no bounds.
+        let flexible_array = add_type(unit, root, gimli::DW_TAG_array_type, Some(int));
+        unit.add(flexible_array, gimli::DW_TAG_subrange_type);
+        // A void typedef has no size.
+        add_type(unit, root, gimli::DW_TAG_typedef, None);
+        // A chain of typedefs which is longer than MAX_TYPE_DEPTH.
+        let mut long_chain = int;
+        for _ in 0..=MAX_TYPE_DEPTH {
+            long_chain = add_type(unit, root, gimli::DW_TAG_typedef, Some(long_chain));
+        }
+        let data = write_sections(&mut dwarf);
+
+        let dwarf = load(&data);
+        let header = dwarf.units().next().unwrap().unwrap();
+        let unit = dwarf.unit(header).unwrap();
+        // The offsets of the children of the root, in the order in which they
+        // were added.
+        let mut offsets = Vec::new();
+        let mut tree = unit.entries_tree(None).unwrap();
+        let mut children = tree.root().unwrap().children();
+        while let Some(child) = children.next().unwrap() {
+            offsets.push(child.entry().offset());
+        }
+        let sizes: Vec<Option<u64>> = offsets
+            .iter()
+            .map(|offset| type_size(&unit, *offset, 0))
+            .collect();
+        let mut expected = vec![
+            Some(4),
+            Some(4),
+            Some(4),
+            Some(4),
+            Some(4),
+            Some(24),
+            None,
+            None,
+        ];
+        expected.extend([Some(4); MAX_TYPE_DEPTH]);
+        expected.push(None);
+        assert_eq!(sizes, expected);
+    }
+
+    fn symbol(address: u64, size: Option<u64>, name: &str) -> DataSymbol {
+        DataSymbol {
+            address,
+            size,
+            name: name.to_owned(),
+            file: None,
+            line: None,
+        }
+    }
+
+    #[test]
+    fn symbols_and_variables_are_merged() {
+        let mut dwarf = write::Dwarf::new();
+        let unit = dwarf
+            .units
+            .add(write::Unit::new(encoding(), write::LineProgram::none()));
+        let unit = dwarf.units.get_mut(unit);
+        let root = unit.root();
+        let int = add_type(unit, root, gimli::DW_TAG_base_type, None);
+        unit.get_mut(int)
+            .set(gimli::DW_AT_byte_size, AttributeValue::Udata(4));
+        let variables = [
+            ("COUNTER", Some(0x1010), 3),
+            ("STRIPPED", Some(0x1020), 4),
+            ("local", None, 5),
+        ];
+        for (name, address, line) in variables {
+            let mut location = Expression::new();
+            match address {
+                Some(address) => location.op_addr(Address::Constant(address)),
+                None => location.op_fbreg(-8),
+            }
+            let variable = add_type(unit, root, gimli::DW_TAG_variable, Some(int));
+            let variable = unit.get_mut(variable);
+            variable.set(
+                gimli::DW_AT_name,
+                AttributeValue::String(name.as_bytes().to_vec()),
+            );
+            variable.set(gimli::DW_AT_location, AttributeValue::Exprloc(location));
+            variable.set(gimli::DW_AT_decl_line, AttributeValue::Udata(line));
+        }
+        let mut data = write_sections(&mut dwarf);
+        // A unit of version 4 whose abbrev offset is out of bounds doesn't
+        // stop us from reading the others.
+        data.get_mut(&gimli::SectionId::DebugInfo)
+            .unwrap()
+            .extend([7, 0, 0, 0, 4, 0, 0xff, 0xff, 0xff, 0xff, 8]);
+
+        let dwarf = load(&data);
+        let variables = dwarf_variables(&dwarf);
+        assert_eq!(variables.len(), 2);
+
+        let symbols = vec![
+            // The first symbol at an address keeps its name, and gets the
+            // size from the second one.
+            symbol(0x10, None, "counter"),
+            symbol(0x10, Some(8), "counter_alias"),
+            symbol(0x30, Some(16), "TABLE"),
+        ];
+        let data_symbols = DataSymbols::merge(symbols.into_iter(), variables, 0x1000);
+        let merged: Vec<_> = data_symbols
+            .symbols
+            .iter()
+            .map(|s| (s.address, s.size, s.name.as_str(), s.line))
+            .collect();
+        assert_eq!(
+            merged,
+            [
+                (0x10, Some(8), "counter", Some(3)),
+                (0x20, Some(4), "STRIPPED", Some(4)),
+                (0x30, Some(16), "TABLE", None),
+            ]
+        );
+    }
+
+    #[test]
+    fn in_range() {
+        let data_symbols = DataSymbols {
+            symbols: vec![
+                // Overlaps the next one.
+                symbol(0x10, Some(0x20), "outer"),
+                // Zero-sized.
+                symbol(0x18, None, "inner"),
+                symbol(0x30, Some(8), "last"),
+            ],
+        };
+        let names = |start, end| -> Vec<&str> {
+            data_symbols
+                .in_range(start, end)
+                .iter()
+                .map(|s| s.name.as_str())
+                .collect()
+        };
+        assert_eq!(names(0, 0x100), ["outer", "inner", "last"]);
+        assert_eq!(names(0x10, 0x18), ["outer"]);
+        // Only symbols which start in the range are returned.
+        assert_eq!(names(0x11, 0x30), ["inner"]);
+        assert_eq!(names(0x18, 0x19), ["inner"]);
+        assert_eq!(names(0x31, 0x40), Vec::<&str>::new());
+        assert_eq!(names(0x30, 0x30), Vec::<&str>::new());
+    }
+}
@@ -0,0 +1,526 @@
+use std::collections::HashMap;
+
+use wholesym::{AddressInfo, FrameDebugInfo, SourceFilePath, SymbolInfo};
+
+use crate::analysis::{OffsetRun, Section};
+use crate::crates::{is_absolute, CrateInfo, CrateMap};
+use crate::data_symbols::file_path;
+use crate::dwarf::Reader;
+use crate::units::unit_crate;
+
+/// The DWARF sections whose bytes we attribute to compilation units, by their
+/// name without the `.debug_`, `.zdebug_` or `__debug_` prefix.
+const ATTRIBUTED_SECTIONS: [&str; 6] = ["info", "line", "ranges", "rnglists", "loc", "loclists"];
+
+/// Which compilation unit, and which source file within it, the bytes of the
+/// DWARF sections of a binary describe.
+///
+/// `.debug_info` is split into units, and each unit into the subtrees of its
+/// functions, which are attributed to the file that declares the function.
+/// Each unit's line program in `.debug_line` and the range and location lists
+/// that its entries refer to are attributed to the unit, or to the function
+/// that refers to them. Shared sections like `.debug_str` and `.debug_abbrev`
+/// remain a single range.
+#[derive(Default)]
+pub struct DebugSections {
+    units: Vec<DebugUnit>,
+    /// The spans of each section in `ATTRIBUTED_SECTIONS`, sorted by start.
+    spans: HashMap<&'static str, Vec<Span>>,
+}
+
+struct DebugUnit {
+    /// The unit's `DW_AT_name`.
+    name: String,
+    /// The Rust crate that the unit was compiled for.
+    crate_info: Option<CrateInfo>,
+}
+
+/// A part of a DWARF section, with offsets into the uncompressed section data.
+struct Span {
+    start: u64,
+    /// `None` for range and location lists, which extend to the next list.
+    end: Option<u64>,
+    /// Index into `units`.
+    unit: usize,
+    /// The source file of the function, or the unit's root source file.
+    file: Option<String>,
+}
+
+impl DebugSections {
+    /// Reads the units from `dwarf`. Unlike the other debug info, this has to
+    /// come from the analyzed binary itself rather than from a separate debug
+    /// file, because these are the bytes that we attribute.
+    ///
+    /// Units which can't be read are skipped with a message in `warnings`,
+    /// and their bytes are left unattributed, or partly attributed if the
+    /// error is in the middle of the unit. A broken unit header ends the list
+    /// of units.
+    pub fn compute(
+        dwarf: &gimli::Dwarf<Reader<'_>>,
+        crates: &CrateMap,
+        warnings: &mut Vec<String>,
+    ) -> Self {
+        let mut debug_sections = DebugSections::default();
+        let mut units = dwarf.units();
+        loop {
+            let header = match units.next() {
+                Ok(Some(header)) => header,
+                Ok(None) => break,
+                Err(e) => {
+                    warnings.push(format!("Could not read the next DWARF unit header: {e}"));
+                    break;
+                }
+            };
+            let Some(unit_start) = header.offset().as_debug_info_offset() else {
+                continue;
+            };
+            let unit_start = unit_start.0 as u64;
+            let unit_end = unit_start + header.length_including_self() as u64;
+            let result = dwarf.unit(header).and_then(|unit| {
+                debug_sections.add_unit(dwarf, &unit, unit_start, unit_end, crates)
+            });
+            if let Err(e) = result {
+                warnings.push(format!(
+                    "Could not read the DWARF unit at .debug_info offset {unit_start:#x}: {e}"
+                ));
+            }
+        }
+        for spans in debug_sections.spans.values_mut() {
+            spans.sort_by_key(|s| s.start);
+            // Lists which several entries refer to are only attributed once.
+            spans.dedup_by_key(|s| s.start);
+        }
+        debug_sections
+    }
+
+    fn add_unit(
+        &mut self,
+        dwarf: &gimli::Dwarf<Reader<'_>>,
+        unit: &gimli::Unit<Reader<'_>>,
+        unit_start: u64,
+        unit_end: u64,
+        crates: &CrateMap,
+    ) -> gimli::Result<()> {
+        let name = unit.name.map(|n| n.to_string_lossy().into_owned());
+        let comp_dir = unit.comp_dir.map(|d| d.to_string_lossy().into_owned());
+        let index = self.units.len();
+        let unit_path = name
+            .as_deref()
+            .and_then(|name| unit_path(name, comp_dir.as_deref()));
+        self.units.push(DebugUnit {
+            name: name.clone().unwrap_or_else(|| "[unnamed unit]".to_owned()),
+            crate_info: name.and_then(|name| unit_crate(&name, comp_dir.as_deref(), crates)),
+        });
+
+        if let Some(program) = &unit.line_program {
+            let header = program.header();
+            let start = header.offset().0 as u64;
+            let length_size = u64::from(header.encoding().format.initial_length_size());
+            self.add_span(
+                "line",
+                start,
+                Some(start + length_size + header.unit_length() as u64),
+                index,
+                unit_path.clone(),
+            );
+        }
+
+        let (ranges_section, locations_section) = if unit.header.version() >= 5 {
+            ("rnglists", "loclists")
+        } else {
+            ("ranges", "loc")
+        };
+        // The function whose subtree we're in, with its depth and file.
+        let mut function: Option<(isize, Option<String>)> = None;
+        let mut span_start = unit_start;
+        let mut depth = 0;
+        let mut entries = unit.entries();
+        while let Some((delta_depth, entry)) = entries.next_dfs()? {
+            depth += delta_depth;
+            // Units in .debug_info always have .debug_info offsets.
+            let Some(offset) = entry.offset().to_debug_info_offset(&unit.header) else {
+                continue;
+            };
+            let offset = offset.0 as u64;
+            if let Some((function_depth, file)) = &function {
+                if depth <= *function_depth {
+                    self.add_span("info", span_start, Some(offset), index, file.clone());
+                    span_start = offset;
+                    function = None;
+                }
+            }
+            if function.is_none() && entry.tag() == gimli::DW_TAG_subprogram {
+                if let Some(file) = decl_file(dwarf, unit, entry) {
+                    self.add_span("info", span_start, Some(offset), index, unit_path.clone());
+                    span_start = offset;
+                    function = Some((depth, Some(file)));
+                }
+            }
+
+            let file = match &function {
+                Some((_, file)) => file,
+                None => &unit_path,
+            };
+            let mut attrs = entry.attrs();
+            while let Some(attr) = attrs.next()? {
+                if let Ok(Some(offset)) = dwarf.attr_ranges_offset(unit, attr.value()) {
+                    self.add_span(ranges_section, offset.0 as u64, None, index, file.clone());
+                }
+                if let Ok(Some(offset)) = dwarf.attr_locations_offset(unit, attr.value()) {
+                    self.add_span(
+                        locations_section,
+                        offset.0 as u64,
+                        None,
+                        index,
+                        file.clone(),
+                    );
+                }
+            }
+        }
+        let file = match function {
+            Some((_, file)) => file,
+            None => unit_path,
+        };
+        self.add_span("info", span_start, Some(unit_end), index, file);
+        Ok(())
+    }
+
+    fn add_span(
+        &mut self,
+        section: &'static str,
+        start: u64,
+        end: Option<u64>,
+        unit: usize,
+        file: Option<String>,
+    ) {
+        if end == Some(start) {
+            return;
+        }
+        self.spans.entry(section).or_default().push(Span {
+            start,
+            end,
+            unit,
+            file,
+        });
+    }
+
+    /// Splits `section` into runs for its units and source files, with the
+    /// unit as the symbol and a frame for the source file if it's known.
+    /// Returns no runs if it isn't one of the DWARF sections which we
+    /// attribute.
+    ///
+    /// The offsets of compressed sections refer to the uncompressed data, so
+    /// their runs get the share of the compressed bytes that corresponds to
+    /// their share of the uncompressed bytes.
+    pub fn runs(&self, section: &Section) -> Vec<OffsetRun> {
+        let Some(spans) = dwarf_section_id(&section.name).and_then(|id| self.spans.get(id)) else {
+            return Vec::new();
+        };
+        let size = section.uncompressed_size;
+        let file_offset = |offset: u64| {
+            (u128::from(offset) * u128::from(section.size) / u128::from(size.max(1))) as u64
+        };
+
+        let mut parts = Vec::new();
+        let mut offset = 0;
+        for (i, span) in spans.iter().enumerate() {
+            let next_start = spans.get(i + 1).map_or(size, |s| s.start);
+            let end = span.end.unwrap_or(next_start).min(size);
+            // Skip spans inside of the preceding one, which we can only get
+            // from broken DWARF.
+            if span.start < offset || span.start >= end {
+                continue;
+            }
+            if span.start > offset {
+                add_part(&mut parts, offset, span.start, None);
+            }
+            add_part(&mut parts, span.start, end, Some(span));
+            offset = end;
+        }
+        if parts.is_empty() {
+            return Vec::new();
+        }
+        if offset < size {
+            add_part(&mut parts, offset, size, None);
+        }
+
+        parts
+            .into_iter()
+            .map(|(start, end, span)| OffsetRun {
+                size: file_offset(end) - file_offset(start),
+                addr_info: span.map(|span| self.address_info(span)),
+                crate_info: None,
+                unit_crate: span.and_then(|span| self.units[span.unit].crate_info.clone()),
+            })
+            .filter(|run| run.size != 0)
+            .collect()
+    }
+
+    /// The unit as a lookup result, with a single frame for the source file.
+    fn address_info(&self, span: &Span) -> AddressInfo {
+        let name = &self.units[span.unit].name;
+        let frames = span.file.as_ref().map(|file| {
+            vec![FrameDebugInfo {
+                function: Some(name.clone()),
+                file_path: Some(SourceFilePath::new(file.clone(), None)),
+                line_number: None,
+            }]
+        });
+        AddressInfo {
+            symbol: SymbolInfo {
+                address: 0,
+                size: None,
+                name: name.clone(),
+            },
+            frames,
+        }
+    }
+}
+
+/// The uncompressed start and end of a part of a section, and its span.
+type Part<'a> = (u64, u64, Option<&'a Span>);
+
+/// Adds a part, or extends the last part if it has the same unit and file.
+fn add_part<'a>(parts: &mut Vec<Part<'a>>, start: u64, end: u64, span: Option<&'a Span>) {
+    let key = |span: Option<&'a Span>| span.map(|s| (s.unit, s.file.as_deref()));
+    match parts.last_mut() {
+        Some((_, previous_end, previous)) if key(*previous) == key(span) => *previous_end = end,
+        _ => parts.push((start, end, span)),
+    }
+}
+
+/// Returns the entry in `ATTRIBUTED_SECTIONS` for a section name like
+/// `.debug_info`, `.zdebug_info` or `__debug_info`.
+fn dwarf_section_id(name: &str) -> Option<&'static str> {
+    let id = [".debug_", ".zdebug_", "__debug_"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))?;
+    ATTRIBUTED_SECTIONS.iter().copied().find(|s| *s == id)
+}
+
+/// The root source file of a unit. Rust unit names look like
+/// `src/lib.rs/@/serde.a1b2c3d4e5f6a7b8-cgu.0`, the others are the path of
+/// the source file.
+fn unit_path(name: &str, comp_dir: Option<&str>) -> Option<String> {
+    let path = match name.split_once("/@/") {
+        Some((root, _)) => root,
+        None => name,
+    };
+    if path.is_empty() {
+        return None;
+    }
+    match comp_dir {
+        Some(comp_dir) if !is_absolute(path) => Some(format!("{comp_dir}/{path}")),
+        _ => Some(path.to_owned()),
+    }
+}
+
+/// Returns the file which declares the function of a subprogram entry.
+/// Out-of-line instances of inlined functions and the definitions of methods
+/// have the declaration in their abstract origin or specification.
+fn decl_file(
+    dwarf: &gimli::Dwarf<Reader<'_>>,
+    unit: &gimli::Unit<Reader<'_>>,
+    entry: &gimli::DebuggingInformationEntry<Reader<'_>>,
+) -> Option<String> {
+    let origin = [gimli::DW_AT_abstract_origin, gimli::DW_AT_specification]
+        .iter()
+        .find_map(|name| match entry.attr_value(*name).ok()? {
+            Some(gimli::AttributeValue::UnitRef(offset)) => unit.entry(offset).ok(),
+            _ => None,
+        });
+    let value = match entry.attr_value(gimli::DW_AT_decl_file).ok()? {
+        Some(value) => value,
+        None => origin?.attr_value(gimli::DW_AT_decl_file).ok()??,
+    };
+    match value {
+        gimli::AttributeValue::FileIndex(index) => file_path(dwarf, unit, index),
+        value => file_path(dwarf, unit, value.udata_value()?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use gimli::write::{self, LineProgram, LineString};
+    use object::SectionKind;
+
+    use super::*;
+
+    /// Writes the DWARF sections of a unit for each of `units`, which are
+    /// (`DW_AT_name`, `DW_AT_comp_dir`) pairs, with a line program each.
+    fn write_dwarf(units: &[(&str, &str)]) -> HashMap<gimli::SectionId, Vec<u8>> {
+        let encoding = gimli::Encoding {
+            format: gimli::Format::Dwarf32,
+            version: 4,
+            address_size: 8,
+        };
+        let mut dwarf = write::Dwarf::new();
+        for (i, (name, comp_dir)) in units.iter().enumerate() {
+            let mut line_program = LineProgram::new(
+                encoding,
+                gimli::LineEncoding::default(),
+                LineString::String(comp_dir.as_bytes().to_vec()),
+                LineString::String(name.as_bytes().to_vec()),
+                None,
+            );
+            let dir = line_program.default_directory();
+            let file = line_program.add_file(LineString::String(b"lib.rs".to_vec()), dir, None);
+            line_program.begin_sequence(Some(write::Address::Constant(0x1000 * i as u64)));
+            line_program.row().file = file;
+            line_program.row().line = 1;
+            line_program.generate_row();
+            line_program.end_sequence(0x10);
+
+            let unit = dwarf.units.add(write::Unit::new(encoding, line_program));
+            let unit = dwarf.units.get_mut(unit);
+            let root = unit.get_mut(unit.root());
+            root.set(
+                gimli::DW_AT_name,
+                write::AttributeValue::String(name.as_bytes().to_vec()),
+            );
+            root.set(
+                gimli::DW_AT_comp_dir,
+                write::AttributeValue::String(comp_dir.as_bytes().to_vec()),
+            );
+        }
+        let mut sections = write::Sections::new(write::EndianVec::new(gimli::LittleEndian));
+        dwarf.write(&mut sections).unwrap();
+        let mut data = HashMap::new();
+        sections
+            .for_each(|id, section| {
+                data.insert(id, section.slice().to_vec());
+                Ok::<_, gimli::Error>(())
+            })
+            .unwrap();
+        data
+    }
+
+    fn section(name: &str, size: u64) -> Section {
+        Section {
+            file_offset: 0,
+            svma: 0,
+            size,
+            kind: SectionKind::Debug,
+            name: name.to_owned(),
+            is_compressed: false,
+            uncompressed_size: size,
+        }
+    }
+
+    /// The unit name, the crate and the size of each run.
+    fn runs(runs: Vec<OffsetRun>) -> Vec<(String, Option<String>, u64)> {
+        runs.into_iter()
+            .map(|run| {
+                (
+                    run.addr_info.unwrap().symbol.name,
+                    run.unit_crate.map(|c| c.name),
+                    run.size,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn units_of_info_and_line() {
+        let units = [
+            ("src/lib.rs/@/serde.a1b2c3d4e5f6a7b8-cgu.0", "/work/serde"),
+            ("src/main.rs/@/app.a1b2c3d4e5f6a7b8-cgu.0", "/work/app"),
+        ];
+        let data = write_dwarf(&units);
+        let dwarf = gimli::Dwarf::load(|id| {
+            let section = data.get(&id).map_or(&[][..], |d| &d[..]);
+            Ok::<_, gimli::Error>(gimli::EndianSlice::new(
+                section,
+                gimli::RunTimeEndian::Little,
+            ))
+        })
+        .unwrap();
+
+        // The sizes of the units and of their line programs.
+        let mut unit_sizes = Vec::new();
+        let mut line_sizes = Vec::new();
+        let mut headers = dwarf.units();
+        while let Some(header) = headers.next().unwrap() {
+            unit_sizes.push(header.length_including_self() as u64);
+            let unit = dwarf.unit(header).unwrap();
+            let program = unit.line_program.unwrap();
+            let header = program.header();
+            line_sizes.push(header.unit_length() as u64 + 4);
+        }
+
+        let mut warnings = Vec::new();
+        let debug_sections = DebugSections::compute(&dwarf, &CrateMap::default(), &mut warnings);
+        assert!(warnings.is_empty());
+        let info_size = data[&gimli::SectionId::DebugInfo].len() as u64;
+        let line_size = data[&gimli::SectionId::DebugLine].len() as u64;
+        assert_eq!(unit_sizes.iter().sum::<u64>(), info_size);
+        assert_eq!(line_sizes.iter().sum::<u64>(), line_size);
+
+        let expected = |sizes: &[u64]| -> Vec<(String, Option<String>, u64)> {
+            units
+                .iter()
+                .zip(["serde", "app"])
+                .zip(sizes)
+                .map(|(((name, _), crate_name), size)| {
+                    (name.to_string(), Some(crate_name.to_owned()), *size)
+                })
+                .collect()
+        };
+        assert_eq!(
+            runs(debug_sections.runs(&section(".debug_info", info_size))),
+            expected(&unit_sizes)
+        );
+        assert_eq!(
+            runs(debug_sections.runs(&section(".debug_line", line_size))),
+            expected(&line_sizes)
+        );
+        assert!(debug_sections.runs(&section(".debug_str", 100)).is_empty());
+    }
+
+    #[test]
+    fn section_ids() {
+        assert_eq!(dwarf_section_id(".debug_info"), Some("info"));
+        assert_eq!(dwarf_section_id(".zdebug_line"), Some("line"));
+        assert_eq!(dwarf_section_id("__debug_rnglists"), Some("rnglists"));
+        assert_eq!(dwarf_section_id(".debug_str"), None);
+        assert_eq!(dwarf_section_id(".text"), None);
+        assert_eq!(dwarf_section_id("debug_info"), None);
+    }
+
+    #[test]
+    fn rust_unit_path() {
+        assert_eq!(
+            unit_path(
+                "src/lib.rs/@/serde.a1b2c3d4e5f6a7b8-cgu.0",
+                Some("/work/serde")
+            ),
+            Some("/work/serde/src/lib.rs".to_owned())
+        );
+        assert_eq!(
+            unit_path(
+                "/work/app/src/main.rs/@/app.a1b2c3d4e5f6a7b8-cgu.0",
+                Some("/work")
+            ),
+            Some("/work/app/src/main.rs".to_owned())
+        );
+        assert_eq!(
+            unit_path("/@/app.a1b2c3d4e5f6a7b8-cgu.0", Some("/work")),
+            None
+        );
+    }
+
+    #[test]
+    fn c_unit_path() {
+        assert_eq!(
+            unit_path("hello.c", Some("/work")),
+            Some("/work/hello.c".to_owned())
+        );
+        assert_eq!(
+            unit_path(r"C:\work\hello.c", Some(r"C:\work")),
+            Some(r"C:\work\hello.c".to_owned())
+        );
+        assert_eq!(unit_path("hello.c", None), Some("hello.c".to_owned()));
+    }
+}
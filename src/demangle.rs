@@ -0,0 +1,83 @@
+/// Demangles Rust (legacy `_ZN...` and v0 `_R...`) and Itanium C++ (`_Z...`)
+/// symbols, without the Rust hash suffix. Other names are returned unchanged,
+/// since the symbol map usually demangles them already, including MSVC names.
+pub fn demangle(name: &str) -> String {
+    if let Ok(demangled) = rustc_demangle::try_demangle(name) {
+        return strip_hash(&format!("{demangled:#}")).to_owned();
+    }
+    // Mach-O symbols have an extra leading underscore.
+    let mangled = if name.starts_with("__Z") {
+        &name[1..]
+    } else {
+        name
+    };
+    let cpp = cpp_demangle::Symbol::new(mangled).ok().and_then(|symbol| {
+        symbol
+            .demangle(&cpp_demangle::DemangleOptions::default())
+            .ok()
+    });
+    cpp.unwrap_or_else(|| strip_hash(name).to_owned())
+}
+
+/// "core::fmt::write::h0123456789abcdef" -> "core::fmt::write"
+fn strip_hash(name: &str) -> &str {
+    match name.rsplit_once("::h") {
+        Some((prefix, hash)) if hash.len() == 16 && hash.bytes().all(|b| b.is_ascii_hexdigit()) => {
+            prefix
+        }
+        _ => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rust_legacy() {
+        assert_eq!(
+            demangle("_ZN4core3fmt5write17h0123456789abcdefE"),
+            "core::fmt::write"
+        );
+        assert_eq!(
+            demangle("_ZN70_$LT$alloc..vec..Vec$LT$T$C$A$GT$$u20$as$u20$core..ops..drop..Drop$GT$4drop17h0123456789abcdefE"),
+            "<alloc::vec::Vec<T,A> as core::ops::drop::Drop>::drop"
+        );
+        // Already demangled by the symbol map, but with the hash.
+        assert_eq!(
+            demangle("core::fmt::write::h0123456789abcdef"),
+            "core::fmt::write"
+        );
+    }
+
+    #[test]
+    fn rust_v0() {
+        assert_eq!(
+            demangle("_RNvNtCs1234_7mycrate3foo3bar"),
+            "mycrate::foo::bar"
+        );
+        assert_eq!(
+            demangle("_RINvNtC3std3mem8align_ofdE"),
+            "std::mem::align_of::<f64>"
+        );
+    }
+
+    #[test]
+    fn itanium_cpp() {
+        assert_eq!(demangle("_ZN2ns3fooIiEEvT_"), "void ns::foo<int>(int)");
+        // With the extra underscore of Mach-O symbols.
+        assert_eq!(demangle("__ZN2ns3barEv"), "ns::bar()");
+    }
+
+    #[test]
+    fn other_names_are_unchanged() {
+        assert_eq!(demangle("main"), "main");
+        assert_eq!(demangle("_start"), "_start");
+        assert_eq!(
+            demangle("public: void __cdecl ns::Foo::bar(void)"),
+            "public: void __cdecl ns::Foo::bar(void)"
+        );
+        // Not a hash, since it's too short.
+        assert_eq!(demangle("ns::h1234"), "ns::h1234");
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::io::Write;
+
+use fxprof_processed_profile::{
+    CategoryHandle, CpuDelta, FrameFlags, Profile, Timestamp, WeightType,
+};
+
+use crate::summary::{SizeSummary, SummaryKey};
+
+/// Returns the size change for every stack whose size differs between `old`
+/// and `new`, sorted by stack. Keys which only differ in what isn't part of
+/// the stack, like the instantiating crate, are summed up.
+pub fn size_deltas(old: &SizeSummary, new: &SizeSummary) -> Vec<(Vec<String>, i64)> {
+    let mut deltas: HashMap<Vec<String>, i64> = HashMap::new();
+    for (key, bytes) in &old.sizes {
+        *deltas.entry(key.stack_labels()).or_default() -= *bytes as i64;
+    }
+    for (key, bytes) in &new.sizes {
+        *deltas.entry(key.stack_labels()).or_default() += *bytes as i64;
+    }
+    let mut deltas: Vec<(Vec<String>, i64)> = deltas
+        .into_iter()
+        .filter(|(_, delta)| *delta != 0)
+        .collect();
+    deltas.sort_unstable();
+    deltas
+}
+
+/// Adds a thread whose samples have the size change per stack as their
+/// weight: positive for bytes that were added, negative for bytes that were
+/// removed. The call tree of this thread shows where the binary grew.
+pub fn add_diff_thread(profile: &mut Profile, pid: u32, deltas: &[(Vec<String>, i64)]) {
+    let category = CategoryHandle::OTHER;
+    let process = profile.add_process("diff", pid, Timestamp::from_millis_since_reference(0.));
+    let thread = profile.add_thread(
+        process,
+        pid,
+        Timestamp::from_millis_since_reference(0.),
+        true,
+    );
+    profile.set_thread_samples_weight_type(thread, WeightType::Bytes);
+
+    let root_s = profile.handle_for_string("(root)");
+    let root_frame =
+        profile.handle_for_frame_with_label(thread, root_s, category, FrameFlags::empty());
+    let root_stack = profile.handle_for_stack(thread, root_frame, None);
+
+    let mut timestamp = 0;
+    for (labels, delta) in deltas {
+        let mut stack = root_stack;
+        for label in labels {
+            let s = profile.handle_for_string(label);
+            let frame =
+                profile.handle_for_frame_with_label(thread, s, category, FrameFlags::empty());
+            stack = profile.handle_for_stack(thread, frame, Some(stack));
+        }
+        // Sample weights are i32, so changes of 2 GiB or more are split up
+        // into several samples.
+        let mut remaining = *delta;
+        while remaining != 0 {
+            let weight =
+                i32::try_from(remaining).unwrap_or(if remaining < 0 { i32::MIN } else { i32::MAX });
+            profile.add_sample(
+                thread,
+                Timestamp::from_millis_since_reference(timestamp as f64),
+                Some(stack),
+                CpuDelta::ZERO,
+                weight,
+            );
+            timestamp += u64::from(weight.unsigned_abs());
+            remaining -= i64::from(weight);
+        }
+    }
+}
+
+/// Writes the `top` most grown and shrunk sections, files and functions.
+pub fn write_report(
+    w: &mut impl Write,
+    old: &SizeSummary,
+    new: &SizeSummary,
+    top: usize,
+) -> std::io::Result<()> {
+    let old_total = old.total();
+    let new_total = new.total();
+    writeln!(
+        w,
+        "Total: {old_total} -> {new_total} bytes ({:+})",
+        new_total as i64 - old_total as i64
+    )?;
+
+    let section = |key: &SummaryKey| {
+        let section = key.section.as_deref().unwrap_or("<outside of sections>");
+        match &key.member {
+            Some(member) => format!("{member} {section}"),
+            None => section.to_owned(),
+        }
+    };
+    let file = |key: &SummaryKey| key.path.clone().unwrap_or_else(|| "<unknown path>".into());
+    let function = |key: &SummaryKey| {
+        key.outer_function()
+            .unwrap_or("<unknown function>")
+            .to_owned()
+    };
+
+    write_group(
+        w,
+        "sections",
+        old.sizes_by(section),
+        new.sizes_by(section),
+        top,
+    )?;
+    write_group(w, "files", old.sizes_by(file), new.sizes_by(file), top)?;
+    write_group(
+        w,
+        "functions",
+        old.sizes_by(function),
+        new.sizes_by(function),
+        top,
+    )?;
+    Ok(())
+}
+
+fn write_group(
+    w: &mut impl Write,
+    name: &str,
+    old_sizes: HashMap<String, u64>,
+    new_sizes: HashMap<String, u64>,
+    top: usize,
+) -> std::io::Result<()> {
+    let names: HashSet<&String> = old_sizes.keys().chain(new_sizes.keys()).collect();
+    let mut deltas: Vec<(&str, i64)> = names
+        .into_iter()
+        .map(|name| {
+            let old_bytes = old_sizes.get(name).copied().unwrap_or(0);
+            let new_bytes = new_sizes.get(name).copied().unwrap_or(0);
+            (name.as_str(), new_bytes as i64 - old_bytes as i64)
+        })
+        .filter(|(_, delta)| *delta != 0)
+        .collect();
+    deltas.sort_by(|(name1, delta1), (name2, delta2)| {
+        delta2.cmp(delta1).then_with(|| name1.cmp(name2))
+    });
+
+    writeln!(w)?;
+    writeln!(w, "Grown {name}:")?;
+    for (name, delta) in deltas.iter().filter(|(_, d)| *d > 0).take(top) {
+        writeln!(w, "{delta:>+12}  {name}")?;
+    }
+    writeln!(w)?;
+    writeln!(w, "Shrunk {name}:")?;
+    for (name, delta) in deltas.iter().rev().filter(|(_, d)| *d < 0).take(top) {
+        writeln!(w, "{delta:>+12}  {name}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use binary_size_profiler::crates::CrateInfo;
+    use object::SectionKind;
+
+    use super::*;
+
+    fn key(path: &str, function: &str) -> SummaryKey {
+        SummaryKey {
+            section: Some(".text".to_owned()),
+            section_kind: Some(SectionKind::Text),
+            path: Some(path.to_owned()),
+            symbol: Some(function.to_owned()),
+            frames: vec![function.to_owned()],
+            ..SummaryKey::default()
+        }
+    }
+
+    fn summaries() -> (SizeSummary, SizeSummary) {
+        let mut old = SizeSummary::default();
+        old.add(key("src/a.rs", "a"), 100);
+        old.add(key("src/b.rs", "b"), 50);
+        old.add(key("src/c.rs", "c"), 10);
+        let mut new = SizeSummary::default();
+        new.add(key("src/a.rs", "a"), 120);
+        // The instantiating crate isn't part of the stack, so these bytes
+        // count towards the same stack as the ones above.
+        new.add(
+            SummaryKey {
+                instantiating_crate: Some(CrateInfo {
+                    name: "app".to_owned(),
+                    version: None,
+                }),
+                ..key("src/a.rs", "a")
+            },
+            30,
+        );
+        new.add(key("src/c.rs", "c"), 10);
+        new.add(key("src/d.rs", "d"), 20);
+        (old, new)
+    }
+
+    #[test]
+    fn size_deltas_of_stacks() {
+        let (old, new) = summaries();
+        let deltas = size_deltas(&old, &new);
+        let stack = |file: &str, function: &str| {
+            vec![
+                ".text".to_owned(),
+                "/src".to_owned(),
+                format!("/src/{file}"),
+                function.to_owned(),
+            ]
+        };
+        assert_eq!(
+            deltas,
+            [
+                (stack("a.rs", "a"), 50),
+                (stack("b.rs", "b"), -50),
+                (stack("d.rs", "d"), 20),
+            ]
+        );
+    }
+
+    #[test]
+    fn report() {
+        let (old, new) = summaries();
+        let mut output = Vec::new();
+        write_report(&mut output, &old, &new, 1).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\
+Total: 160 -> 180 bytes (+20)
+
+Grown sections:
+         +20  .text
+
+Shrunk sections:
+
+Grown files:
+         +50  src/a.rs
+
+Shrunk files:
+         -50  src/b.rs
+
+Grown functions:
+         +50  a
+
+Shrunk functions:
+         -50  b
+"
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use std::borrow::Cow;
+
+use object::read::{Object, ObjectSection};
+use object::File;
+
+pub(crate) type Reader<'a> = gimli::EndianSlice<'a, gimli::RunTimeEndian>;
+
+/// The DWARF sections of a binary or debug file, decompressed once, so that
+/// the lookup boundaries, the compilation units, the data symbols and the
+/// debug section spans can all be read from the same data.
+pub struct DwarfData<'data> {
+    sections: gimli::DwarfSections<Cow<'data, [u8]>>,
+    endian: gimli::RunTimeEndian,
+}
+
+impl<'data> DwarfData<'data> {
+    /// Loads the DWARF sections of `file`. Missing sections, and sections
+    /// which fail to decompress, are empty. Returns `None` if the file has no
+    /// `.debug_info`.
+    pub fn load(file: &File<'data>) -> Option<Self> {
+        let has_debug_info = file
+            .section_by_name(".debug_info")
+            .is_some_and(|s| s.size() != 0);
+        if !has_debug_info {
+            return None;
+        }
+        let endian = if file.is_little_endian() {
+            gimli::RunTimeEndian::Little
+        } else {
+            gimli::RunTimeEndian::Big
+        };
+        let load_section = |id: gimli::SectionId| -> Result<Cow<'data, [u8]>, gimli::Error> {
+            let data = file
+                .section_by_name(id.name())
+                .and_then(|s| s.uncompressed_data().ok())
+                .unwrap_or(Cow::Borrowed(&[]));
+            Ok(data)
+        };
+        let sections = gimli::DwarfSections::load(load_section).ok()?;
+        Some(DwarfData { sections, endian })
+    }
+
+    pub fn dwarf(&self) -> gimli::Dwarf<Reader<'_>> {
+        self.sections
+            .borrow(|section| gimli::EndianSlice::new(section, self.endian))
+    }
+}
@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use binary_size_profiler::{AttributedRange, RangeSink};
+
+use crate::output::OutputSink;
+use crate::summary::{SizeSummary, SummaryKey};
+
+/// Writes Brendan Gregg's folded stack format, one `frame;frame;frame bytes`
+/// line per unique stack, for flamegraph.pl and inferno.
+#[derive(Default)]
+pub struct FoldedSink {
+    summary: SizeSummary,
+}
+
+impl RangeSink for FoldedSink {
+    fn range(&mut self, range: &AttributedRange) {
+        self.summary.range(range);
+    }
+}
+
+impl OutputSink for FoldedSink {
+    fn write_output(&mut self, w: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+        // Different keys can have the same stack, e.g. if two symbols have the
+        // same name, so sum up the sizes per stack. Sorting makes the output
+        // stable, which helps when diffing it.
+        let mut sizes: BTreeMap<String, u64> = BTreeMap::new();
+        for (key, bytes) in &self.summary.sizes {
+            if *bytes != 0 {
+                *sizes.entry(folded_stack(key)).or_default() += bytes;
+            }
+        }
+        for (stack, bytes) in sizes {
+            writeln!(w, "{stack} {bytes}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The stack of `key` with the same frames as in the profile, joined by
+/// semicolons. Semicolons in frame names, e.g. from Rust array types like
+/// `[u8; 4]`, would split the frame, so they are replaced with colons.
+fn folded_stack(key: &SummaryKey) -> String {
+    let mut stack = String::from("(root)");
+    for label in key.stack_labels() {
+        stack.push(';');
+        stack.push_str(&label.replace(';', ":"));
+    }
+    stack
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use binary_size_profiler::wholesym::{AddressInfo, FrameDebugInfo, SourceFilePath, SymbolInfo};
+    use binary_size_profiler::Section;
+    use object::SectionKind;
+
+    use super::*;
+
+    fn text_range(size: u64, function: &str) -> AttributedRange {
+        AttributedRange {
+            file_offset: 0x1000,
+            size,
+            binary: None,
+            section: Some(Arc::new(Section {
+                file_offset: 0x1000,
+                svma: 0x1000,
+                size: 0x100,
+                kind: SectionKind::Text,
+                name: ".text".to_owned(),
+                is_compressed: false,
+                uncompressed_size: 0x100,
+            })),
+            relative_address: Some(0x1000),
+            addr_info: Some(AddressInfo {
+                symbol: SymbolInfo {
+                    address: 0x1000,
+                    size: None,
+                    name: function.to_owned(),
+                },
+                frames: Some(vec![FrameDebugInfo {
+                    function: Some(function.to_owned()),
+                    file_path: Some(SourceFilePath::new("src/lib.rs".to_owned(), None)),
+                    line_number: Some(1),
+                }]),
+            }),
+            crate_info: None,
+            instantiating_crate: None,
+        }
+    }
+
+    #[test]
+    fn semicolons_are_escaped() {
+        let mut sink = FoldedSink::default();
+        sink.range(&AttributedRange {
+            file_offset: 0,
+            size: 64,
+            binary: None,
+            section: None,
+            relative_address: None,
+            addr_info: None,
+            crate_info: None,
+            instantiating_crate: None,
+        });
+        sink.range(&text_range(16, "<[u8; 4] as core::fmt::Debug>::fmt"));
+        sink.range(&text_range(8, "<[u8; 4] as core::fmt::Debug>::fmt"));
+        let mut output = Vec::new();
+        sink.write_output(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "(root) 64
+(root);.text;/src;/src/lib.rs;<[u8: 4] as core::fmt::Debug>::fmt 24
+"
+        );
+    }
+}
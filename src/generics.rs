@@ -0,0 +1,404 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use binary_size_profiler::{demangle, AttributedRange, RangeSink};
+use object::SectionKind;
+
+use crate::output::OutputSink;
+use crate::table::format_size;
+
+/// The number of instances listed below each generic function.
+const LARGEST_INSTANCES: usize = 5;
+
+/// One monomorphized instance, i.e. one symbol.
+struct Instance {
+    /// The generic function or template which this is an instance of.
+    function: String,
+    address: u32,
+    /// The generic argument lists, e.g. `["u8, alloc::alloc::Global"]`.
+    args: Vec<String>,
+    bytes: u64,
+}
+
+/// Groups the code bytes by generic function or C++ template, ignoring the
+/// generic arguments, and reports the functions with the most bytes across all
+/// their instances, similar to `cargo llvm-lines`, but for the final binary.
+pub struct GenericsSink {
+    top: usize,
+    /// Keyed by fat archive member and symbol address.
+    instances: HashMap<(Option<String>, u32), Instance>,
+}
+
+impl GenericsSink {
+    pub fn new(top: usize) -> Self {
+        GenericsSink {
+            top,
+            instances: HashMap::new(),
+        }
+    }
+}
+
+impl RangeSink for GenericsSink {
+    fn range(&mut self, range: &AttributedRange) {
+        // Only code counts, not the data symbols, the unwind info of the
+        // functions or the DWARF.
+        let is_text = range
+            .section
+            .as_ref()
+            .is_some_and(|s| s.kind == SectionKind::Text);
+        let Some(addr_info) = range.addr_info.as_ref().filter(|_| is_text) else {
+            return;
+        };
+        let symbol = &addr_info.symbol;
+        let member = range.binary.as_ref().and_then(|b| b.member_name.clone());
+        let instance = self
+            .instances
+            .entry((member, symbol.address))
+            .or_insert_with(|| {
+                let (function, args) = split_generic_name(&symbol.name);
+                Instance {
+                    function,
+                    address: symbol.address,
+                    args,
+                    bytes: 0,
+                }
+            });
+        instance.bytes += range.size;
+    }
+}
+
+struct GenericFunction<'a> {
+    name: String,
+    bytes: u64,
+    instances: Vec<&'a Instance>,
+}
+
+impl OutputSink for GenericsSink {
+    fn write_output(&mut self, w: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+        let mut functions: HashMap<String, GenericFunction> = HashMap::new();
+        for instance in self.instances.values() {
+            let name = instance.function.clone();
+            let function = functions
+                .entry(name.clone())
+                .or_insert_with(|| GenericFunction {
+                    name,
+                    bytes: 0,
+                    instances: Vec::new(),
+                });
+            function.bytes += instance.bytes;
+            function.instances.push(instance);
+        }
+        // Functions with a single instance and no generic arguments aren't
+        // generic, or at least don't cause any bloat.
+        let mut functions: Vec<GenericFunction> = functions
+            .into_values()
+            .filter(|f| f.instances.len() > 1 || f.instances.iter().any(|i| !i.args.is_empty()))
+            .collect();
+        functions.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.name.cmp(&b.name)));
+
+        writeln!(
+            w,
+            " INSTANCES     SIZE   GENERIC FUNCTION / LARGEST INSTANCES"
+        )?;
+        writeln!(
+            w,
+            " ---------  -------   ------------------------------------"
+        )?;
+        let others = functions.split_off(functions.len().min(self.top));
+        for function in &mut functions {
+            writeln!(
+                w,
+                " {:>9}  {:>7}   {}",
+                function.instances.len(),
+                format_size(function.bytes),
+                function.name
+            )?;
+            function.instances.sort_by(|a, b| {
+                b.bytes
+                    .cmp(&a.bytes)
+                    .then_with(|| a.address.cmp(&b.address))
+            });
+            for instance in function.instances.iter().take(LARGEST_INSTANCES) {
+                writeln!(
+                    w,
+                    "            {:>7}       {}",
+                    format_size(instance.bytes),
+                    instance_label(instance)
+                )?;
+            }
+            let remaining = function.instances.len().saturating_sub(LARGEST_INSTANCES);
+            if remaining > 0 {
+                writeln!(w, "                          [{remaining} more]")?;
+            }
+        }
+        if !others.is_empty() {
+            let instances: usize = others.iter().map(|f| f.instances.len()).sum();
+            let bytes = others.iter().map(|f| f.bytes).sum();
+            writeln!(
+                w,
+                " {instances:>9}  {:>7}   [{} Others]",
+                format_size(bytes),
+                others.len()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// The address and the generic arguments of the instance. Legacy mangled
+/// names only contain the generic parameters of impls, e.g. `Vec<T,A>`, so
+/// the address is what tells their instances apart.
+fn instance_label(instance: &Instance) -> String {
+    let mut label = format!("{:#x}", instance.address);
+    for args in &instance.args {
+        label.push_str(&format!(" <{args}>"));
+    }
+    label
+}
+
+/// Splits a symbol name into the generic function or C++ template it is an
+/// instance of, and the generic argument lists of the instance.
+///
+/// The name is demangled if necessary. For C++, the parameter list and the
+/// return type are dropped, because they usually depend on the template
+/// arguments too.
+fn split_generic_name(symbol_name: &str) -> (String, Vec<String>) {
+    let name = demangle(symbol_name);
+    split_generics(strip_signature(&name))
+}
+
+/// The label of the frame which groups the instances of a generic function
+/// in the profile, or `None` if the symbol isn't generic. Legacy mangled Rust
+/// symbols have no generic arguments, so they never get a frame.
+pub fn generic_frame_label(symbol_name: &str) -> Option<String> {
+    let (function, args) = split_generic_name(symbol_name);
+    if args.is_empty() {
+        return None;
+    }
+    Some(format!("{function} [all instances]"))
+}
+
+/// C++ operators whose names contain brackets, longest first.
+const BRACKET_OPERATORS: [&str; 12] = [
+    "<=>", "<<=", ">>=", "->*", "<<", ">>", "<=", ">=", "->", "()", "<", ">",
+];
+
+/// The length of the operator at `name[i..]`, if `name[..i]` ends with the
+/// `operator` keyword, e.g. 2 for the `<<` of `std::ostream::operator<<`.
+fn operator_len(name: &str, i: usize) -> Option<usize> {
+    if !name.is_char_boundary(i) || !name[..i].ends_with("operator") {
+        return None;
+    }
+    BRACKET_OPERATORS
+        .iter()
+        .find(|op| name[i..].starts_with(*op))
+        .map(|op| op.len())
+}
+
+/// Drops the parameter list, the qualifiers after it and the return type or
+/// MSVC access specifier and calling convention before the name, e.g.
+/// `public: void __cdecl ns::f<int>(int) const` becomes `ns::f<int>`. Rust
+/// names have none of these and are returned unchanged.
+fn strip_signature(name: &str) -> &str {
+    let bytes = name.as_bytes();
+    let mut depth = 0usize;
+    let mut params_start = None;
+    let mut name_start = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if let Some(len) = operator_len(name, i) {
+            i += len;
+            continue;
+        }
+        match bytes[i] {
+            b'>' if i > 0 && bytes[i - 1] == b'-' => {}
+            b'<' | b'(' | b'[' => {
+                // "(anonymous namespace)::f" starts with a parenthesis which
+                // isn't a parameter list.
+                if bytes[i] == b'(' && depth == 0 && i > 0 && params_start.is_none() {
+                    params_start = Some(i);
+                }
+                depth += 1;
+            }
+            b'>' | b')' | b']' => depth = depth.saturating_sub(1),
+            // Spaces separate the name from the return type, but not in
+            // "operator new" or "operator<< <T>".
+            b' ' if depth == 0
+                && params_start.is_none()
+                && !name[..i].ends_with("operator")
+                && bytes.get(i + 1) != Some(&b'<') =>
+            {
+                name_start = i + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    &name[name_start..params_start.unwrap_or(name.len())]
+}
+
+/// Removes the generic argument lists from a demangled name and returns them
+/// separately, e.g. `<alloc::vec::Vec<u8> as core::ops::drop::Drop>::drop`
+/// becomes `<alloc::vec::Vec as core::ops::drop::Drop>::drop` and `["u8"]`.
+/// Qualified paths like `<T as Trait>` are kept.
+fn split_generics(name: &str) -> (String, Vec<String>) {
+    let bytes = name.as_bytes();
+    let mut stripped = String::with_capacity(name.len());
+    let mut args = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if let Some(len) = operator_len(name, i) {
+            stripped.push_str(&name[i..i + len]);
+            i += len;
+        } else if bytes[i] == b'<' && starts_generic_args(&stripped) {
+            let end = matching_angle_bracket(name, i).unwrap_or(bytes.len());
+            args.push(name[i + 1..end].trim().to_owned());
+            // Also drop the "::" of a turbofish, and the space in
+            // "operator<< <T>".
+            if stripped.ends_with("::") {
+                stripped.truncate(stripped.len() - 2);
+            }
+            stripped.truncate(stripped.trim_end().len());
+            i = end + 1;
+        } else {
+            let c = name[i..].chars().next().unwrap();
+            stripped.push(c);
+            i += c.len_utf8();
+        }
+    }
+    (stripped, args)
+}
+
+/// A `<` starts a generic argument list if it follows a path segment, a
+/// turbofish `::` or an operator name, and a qualified path otherwise.
+fn starts_generic_args(before: &str) -> bool {
+    let is_operator = before
+        .trim_end()
+        .rsplit("::")
+        .next()
+        .is_some_and(|segment| segment.starts_with("operator"));
+    is_operator
+        || before.ends_with("::")
+        || before
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// Returns the index of the `>` which closes the `<` at `start`, ignoring the
+/// `>` of `->` in function types and the brackets of operator names.
+fn matching_angle_bracket(name: &str, start: usize) -> Option<usize> {
+    let bytes = name.as_bytes();
+    let mut depth = 0;
+    let mut i = start;
+    while i < bytes.len() {
+        if let Some(len) = operator_len(name, i) {
+            i += len;
+            continue;
+        }
+        match bytes[i] {
+            b'<' => depth += 1,
+            b'>' if i > 0 && bytes[i - 1] == b'-' => {}
+            b'>' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(name: &str) -> (String, Vec<String>) {
+        split_generic_name(name)
+    }
+
+    #[test]
+    fn rust_generics() {
+        assert_eq!(
+            split("core::ptr::drop_in_place<alloc::string::String>"),
+            (
+                "core::ptr::drop_in_place".to_owned(),
+                vec!["alloc::string::String".to_owned()]
+            )
+        );
+        assert_eq!(
+            split("<alloc::vec::Vec<u8> as core::ops::drop::Drop>::drop"),
+            (
+                "<alloc::vec::Vec as core::ops::drop::Drop>::drop".to_owned(),
+                vec!["u8".to_owned()]
+            )
+        );
+        assert_eq!(
+            split("core::mem::size_of::<u32>"),
+            ("core::mem::size_of".to_owned(), vec!["u32".to_owned()])
+        );
+        assert_eq!(
+            split("<T as core::any::Any>::type_id"),
+            ("<T as core::any::Any>::type_id".to_owned(), vec![])
+        );
+    }
+
+    #[test]
+    fn cpp_templates() {
+        assert_eq!(
+            split("std::vector<int, std::allocator<int> >::push_back(int const&)"),
+            (
+                "std::vector::push_back".to_owned(),
+                vec!["int, std::allocator<int>".to_owned()]
+            )
+        );
+        assert_eq!(
+            split("void ns::f<int>(int) const"),
+            ("ns::f".to_owned(), vec!["int".to_owned()])
+        );
+        assert_eq!(
+            split("public: void __cdecl ns::f<int>(int) const"),
+            ("ns::f".to_owned(), vec!["int".to_owned()])
+        );
+        assert_eq!(
+            split("(anonymous namespace)::helper<char>(char)"),
+            (
+                "(anonymous namespace)::helper".to_owned(),
+                vec!["char".to_owned()]
+            )
+        );
+    }
+
+    #[test]
+    fn cpp_operators() {
+        assert_eq!(
+            split("std::ostream::operator<<(int)"),
+            ("std::ostream::operator<<".to_owned(), vec![])
+        );
+        assert_eq!(
+            split("std::ostream& std::operator<< <std::char_traits<char> >(std::ostream&, char const*)"),
+            ("std::operator<<".to_owned(), vec!["std::char_traits<char>".to_owned()])
+        );
+        assert_eq!(
+            split("bool ns::operator< <int>(ns::A<int> const&, ns::A<int> const&)"),
+            ("ns::operator<".to_owned(), vec!["int".to_owned()])
+        );
+    }
+
+    #[test]
+    fn frame_label() {
+        assert_eq!(
+            generic_frame_label("core::ptr::drop_in_place<alloc::string::String>"),
+            Some("core::ptr::drop_in_place [all instances]".to_owned())
+        );
+        assert_eq!(generic_frame_label("main"), None);
+        // Legacy mangling drops the generic arguments.
+        assert_eq!(
+            generic_frame_label("_ZN4core3ptr13drop_in_place17h0123456789abcdefE"),
+            None
+        );
+    }
+}
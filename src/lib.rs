@@ -0,0 +1,38 @@
+//! Attributes every byte of a binary to its section and, for code, to the
+//! symbol, inline frames and source location from the binary's debug info.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use binary_size_profiler::{analyze_binary, AnalysisOptions};
+//!
+//! let analysis = analyze_binary("app".as_ref(), &AnalysisOptions::default()).await?;
+//! for range in &analysis.ranges {
+//!     let section = range.section.as_ref().map_or("<none>", |s| &s.name);
+//!     println!("{:#x} {} {section}", range.file_offset, range.size);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+mod analysis;
+mod boundaries;
+pub mod crates;
+mod data_symbols;
+mod debug_sections;
+mod demangle;
+mod dwarf;
+mod sink;
+pub mod strings;
+mod symbol_tables;
+mod symbols;
+mod units;
+mod unwind;
+
+pub use analysis::{
+    analyze_binary, analyze_binary_into, Analysis, AnalysisOptions, AttributedRange, Binary,
+    Section,
+};
+pub use demangle::demangle;
+pub use sink::RangeSink;
+pub use symbols::SymbolConfig;
+pub use wholesym;
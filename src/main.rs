@@ -1,616 +1,589 @@
-use std::collections::HashMap;
-use std::convert::TryFrom;
-use std::path::Path;
-
-use fxprof_processed_profile::{
-    CategoryHandle, CpuDelta, FrameAddress, FrameFlags, FrameHandle, FrameSymbolInfo,
-    LibraryHandle, LibraryInfo, Profile, ReferenceTimestamp, SamplingInterval, SourceLocation,
-    StackHandle, StringHandle, Symbol, ThreadHandle, TimelineUnit, Timestamp, WeightType,
+mod budget;
+mod diff;
+mod folded;
+mod generics;
+mod output;
+mod pprof;
+mod profile;
+mod ranges;
+mod speedscope;
+mod sqlite;
+mod string_report;
+mod summary;
+mod table;
+mod treemap;
+
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use binary_size_profiler::crates::CrateMap;
+use binary_size_profiler::{
+    analyze_binary_into, AnalysisOptions, AttributedRange, RangeSink, SymbolConfig,
 };
-use indicatif::{ProgressBar, ProgressStyle};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use flate2::write::GzEncoder;
+use flate2::Compression;
 use mimalloc::MiMalloc;
-use object::read::macho::{FatArch, MachOFatFile32};
-use object::read::Object;
-use object::{CompressionFormat, File, FileKind, SectionKind};
-use uuid::Uuid;
-use wholesym::debugid::DebugId;
-use wholesym::samply_symbols::relative_address_base;
-use wholesym::{MultiArchDisambiguator, SourceFilePath, SymbolManager, SymbolManagerConfig};
+
+use crate::folded::FoldedSink;
+use crate::generics::GenericsSink;
+use crate::output::OutputSink;
+use crate::pprof::PprofSink;
+use crate::profile::ProfileSink;
+use crate::ranges::{RangesFormat, RangesSink};
+use crate::speedscope::SpeedscopeSink;
+use crate::sqlite::SqliteSink;
+use crate::string_report::StringsSink;
+use crate::summary::SizeSummary;
+use crate::table::{GroupBy, TableSink};
+use crate::treemap::TreemapSink;
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    use std::io::Read;
-
-    let path = &std::env::args().nth(1).unwrap();
-    let file_name = Path::new(path).file_name().unwrap().to_str().unwrap();
-
-    let mut data = Vec::new();
-    let mut file = std::fs::File::open(path).unwrap();
-    file.read_to_end(&mut data).unwrap();
-
-    let file_kind = FileKind::parse(&data[..]).unwrap();
-
-    let mut profile = Profile::new(
-        "size-profiler",
-        ReferenceTimestamp::from_millis_since_unix_epoch(0.),
-        SamplingInterval::from_hz(1000.),
-    );
-
-    profile.set_timeline_unit(TimelineUnit::Bytes);
-
-    let process = profile.add_process(file_name, 0, Timestamp::from_millis_since_reference(0.));
-    let thread = profile.add_thread(process, 0, Timestamp::from_millis_since_reference(0.), true);
-    profile.set_thread_samples_weight_type(thread, WeightType::Bytes);
-    profile.set_symbolicated(true);
-    let category = CategoryHandle::OTHER;
-
-    let root_s = profile.handle_for_string("(root)");
-    let root_frame =
-        profile.handle_for_frame_with_label(thread, root_s, category, FrameFlags::empty());
-    let root_stack = profile.handle_for_stack(thread, root_frame, None);
-
-    let config = SymbolManagerConfig::default()
-        .respect_nt_symbol_path(true)
-        .breakpad_symbols_server(
-            "https://symbols.mozilla.org/try/",
-            "./breakpad-symbol-cache/",
-        )
-        .breakpad_symindex_cache_dir("./breakpad-symindex-cache/");
-    let symbol_manager = SymbolManager::with_config(config);
-
-    // If we got a fat binary, pick the first member.
-    if file_kind == FileKind::MachOFat32 {
-        let mut previous_member_end_file_offset = 0;
-        let mut previous_member_name = None;
-        for member in MachOFatFile32::parse(&data[..]).unwrap().arches() {
-            let member_start_file_offset = member.offset() as u64;
-            let member_size = member.size() as u64;
-
-            let data = &data[member_start_file_offset as usize..][..member_size as usize];
-            let object_file = File::parse(data).unwrap();
-
-            let disambiguator = if let Ok(Some(uuid)) = object_file.mach_uuid() {
-                let uuid = Uuid::from_bytes(uuid);
-                Some(MultiArchDisambiguator::DebugId(DebugId::from_uuid(uuid)))
-            } else {
-                None
-            };
-
-            let lib_info = SymbolManager::library_info_for_binary_at_path(
-                Path::new(path),
-                disambiguator.clone(),
-            )
-            .await
-            .unwrap();
-
-            let member_name = match &lib_info.arch {
-                Some(name) => name.to_owned(),
-                None => format!(
-                    "Fat32 archive member with cputype {} and cpusubtype {}",
-                    member.cputype(),
-                    member.cpusubtype()
-                ),
-            };
-
-            if member_start_file_offset < previous_member_end_file_offset {
-                panic!("Overlapping fat archive members: Member with arch {member_name} starts at file offset {member_start_file_offset:#x} which is before the end file offset {previous_member_end_file_offset:#x} of member with arch {}", previous_member_name.unwrap());
-            }
+/// Generate a binary size profile from a binary and its debug info.
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Opt {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The binary to profile.
+    #[arg(required = true)]
+    input: Option<PathBuf>,
+
+    #[command(flatten)]
+    output: OutputArgs,
+
+    #[command(flatten)]
+    format_options: FormatOptions,
+
+    #[command(flatten)]
+    common: CommonArgs,
+}
 
-            if member_start_file_offset > previous_member_end_file_offset {
-                let padding_bytes_before_member =
-                    member_start_file_offset - previous_member_end_file_offset;
-                profile.add_sample(
-                    thread,
-                    Timestamp::from_millis_since_reference(previous_member_end_file_offset as f64),
-                    Some(root_stack),
-                    CpuDelta::ZERO,
-                    i32::try_from(padding_bytes_before_member).unwrap(),
-                );
-            }
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Compare two binaries. Writes a profile whose sample weights are the size
+    /// changes, and prints the sections, files and functions which grew and
+    /// shrank the most.
+    Diff(DiffArgs),
 
-            let member_s = profile.handle_for_string(&member_name);
-            let member_frame = profile.handle_for_frame_with_label(
-                thread,
-                member_s,
-                category,
-                FrameFlags::empty(),
-            );
-            let member_stack = profile.handle_for_stack(thread, member_frame, Some(root_stack));
-
-            let symbol_map = symbol_manager
-                .load_symbol_map_for_binary_at_path(Path::new(path), disambiguator)
-                .await
-                .unwrap();
-
-            process_binary(
-                &mut profile,
-                thread,
-                member_stack,
-                &object_file,
-                lib_info,
-                symbol_map,
-                category,
-                member_start_file_offset,
-                member_size,
-            )
-            .await;
-
-            previous_member_end_file_offset = member_start_file_offset + member_size;
-            previous_member_name = Some(member_name);
-        }
+    /// Check the sizes of the binary and its components against the budgets
+    /// in a TOML file. Exits with an error if any budget is exceeded.
+    Check(CheckArgs),
+}
 
-        let file_end_file_offset = data.len() as u64;
-        if file_end_file_offset < previous_member_end_file_offset {
-            panic!("Truncated fat archive member: File size is {file_end_file_offset:#x} which is less than the end file offset {previous_member_end_file_offset:#x} of member {}", previous_member_name.unwrap());
-        }
+#[derive(Debug, Args)]
+struct DiffArgs {
+    /// The old version of the binary.
+    old: PathBuf,
 
-        if file_end_file_offset > previous_member_end_file_offset {
-            let padding_bytes_after_section =
-                file_end_file_offset - previous_member_end_file_offset;
-            profile.add_sample(
-                thread,
-                Timestamp::from_millis_since_reference(previous_member_end_file_offset as f64),
-                Some(root_stack),
-                CpuDelta::ZERO,
-                i32::try_from(padding_bytes_after_section).unwrap(),
-            );
-        }
-    } else {
-        let data = &data[..];
-
-        let object_file = File::parse(data).unwrap();
-
-        let lib_info = SymbolManager::library_info_for_binary_at_path(Path::new(path), None)
-            .await
-            .unwrap();
-
-        let symbol_map = symbol_manager
-            .load_symbol_map_for_binary_at_path(Path::new(path), None)
-            .await
-            .unwrap();
-
-        process_binary(
-            &mut profile,
-            thread,
-            root_stack,
-            &object_file,
-            lib_info,
-            symbol_map,
-            category,
-            0,
-            data.len() as u64,
-        )
-        .await;
-    }
-
-    // Add a final sample with zero weight, so that the profiler's automatic time range detection
-    // includes all the file bytes.
-    profile.add_sample(
-        thread,
-        Timestamp::from_millis_since_reference(data.len() as f64),
-        Some(root_stack),
-        CpuDelta::ZERO,
-        0,
-    );
+    /// The new version of the binary.
+    new: PathBuf,
 
-    let output_file = std::fs::File::create("output.json").unwrap();
-    let writer = std::io::BufWriter::new(output_file);
-    serde_json::to_writer(writer, &profile).unwrap();
+    #[command(flatten)]
+    output: OutputArgs,
 
-    Ok(())
+    #[command(flatten)]
+    common: CommonArgs,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-struct Section {
-    file_offset: u64,
-    svma: u64,
-    size: u64,
-    kind: SectionKind,
-    name: String,
-    is_compressed: bool,
+#[derive(Debug, Args)]
+struct CheckArgs {
+    /// The binary to check.
+    input: PathBuf,
+
+    /// The TOML file with the budgets.
+    #[arg(long)]
+    budget: PathBuf,
+
+    #[command(flatten)]
+    common: CommonArgs,
 }
 
-#[allow(clippy::too_many_arguments)]
-async fn process_binary(
-    profile: &mut Profile,
-    thread: ThreadHandle,
-    root_stack: StackHandle,
-    object_file: &File<'_>,
-    lib_info: wholesym::LibraryInfo,
-    symbol_map: wholesym::SymbolMap,
-    category: CategoryHandle,
-    timestamp_offset: u64,
-    binary_file_size: u64,
-) {
-    let name = lib_info.name.unwrap();
-    let debug_name = lib_info.debug_name.unwrap_or_else(|| name.clone());
-    let path = lib_info.path.unwrap_or_else(|| name.clone());
-    let debug_path = lib_info.debug_path.unwrap_or_else(|| path.clone());
-    let lib = LibraryInfo {
-        name,
-        debug_name,
-        path,
-        debug_path,
-        debug_id: lib_info.debug_id.unwrap_or_default(),
-        code_id: lib_info.code_id.map(|ci| ci.to_string()),
-        arch: lib_info.arch,
-        symbol_table: None,
-    };
+#[derive(Debug, Args)]
+struct OutputArgs {
+    /// Where to write the output, or "-" for stdout [default: output.json,
+    /// output.json.gz for json-gz, output.folded for folded,
+    /// output.speedscope.json for speedscope, output.pb.gz for pprof,
+    /// output.html for html, output.sqlite for sqlite, output.jsonl for jsonl,
+    /// output.csv for csv, stdout for table, generics and strings].
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// The output format. Defaults to pprof if the output path ends in
+    /// ".pb.gz", to json-gz if it ends in ".gz", to sqlite if it ends in
+    /// ".sqlite" or ".db", to jsonl or csv for those extensions, and to json
+    /// otherwise.
+    #[arg(short, long, value_enum)]
+    format: Option<OutputFormat>,
+
+    /// The number of rows per group in the table output, the number of
+    /// functions in the generics report, the number of strings per category
+    /// in the strings report, and the number of grown and shrunk entries per
+    /// category in the diff report.
+    #[arg(long, default_value_t = 20)]
+    top: usize,
+}
 
-    let base_addr = relative_address_base(object_file);
+/// Options for the output formats of the main command, which the diff
+/// command doesn't support.
+#[derive(Debug, Args)]
+struct FormatOptions {
+    /// How to group the rows of the table output. Multiple comma-separated
+    /// values create nested groups, e.g. "section,file".
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "section")]
+    group_by: Vec<GroupBy>,
+
+    /// In the json and json-gz profiles, group the instances of each generic
+    /// function or C++ template below an extra frame with the function's
+    /// name. Rust code has
+    /// to be built with `-C symbol-mangling-version=v0`, because legacy
+    /// mangled symbols don't contain the generic arguments.
+    #[arg(long)]
+    generic_frames: bool,
+}
 
-    let library_handle = profile.add_lib(lib);
+/// Options for finding debug info and for processing a binary.
+#[derive(Debug, Args)]
+struct CommonArgs {
+    /// Read symbol settings from this TOML file. Command-line flags take
+    /// precedence over the file.
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Breakpad symbol server which is queried for debug info
+    /// [default: https://symbols.mozilla.org/try/].
+    #[arg(long)]
+    symbol_server: Option<String>,
+
+    /// Directory in which files downloaded from the symbol server are cached
+    /// [default: ./breakpad-symbol-cache/].
+    #[arg(long)]
+    symbol_cache: Option<PathBuf>,
+
+    /// A local directory with debug files. Can be specified multiple times.
+    #[arg(long)]
+    symbol_dir: Vec<PathBuf>,
+
+    /// A local directory laid out like a debuginfod server
+    /// (`<dir>/buildid/<build-id>/debuginfo`). Can be specified multiple times.
+    #[arg(long)]
+    debuginfod_mirror: Vec<PathBuf>,
+
+    /// Load debug info from this file instead of searching for it, e.g. a
+    /// `.debug`, `.dSYM` or `.pdb` file. Breakpad `.sym` files go into a
+    /// `--symbol-dir`, at `<dir>/<debug name>/<debug ID>/<name>.sym`.
+    #[arg(long)]
+    debug_file: Option<PathBuf>,
+
+    /// Never make network requests; only use local debug info and the
+    /// symbol cache.
+    #[arg(long, conflicts_with = "symbol_server")]
+    offline: bool,
+
+    /// For fat (universal) binaries, only profile the member with this
+    /// architecture, e.g. "arm64" or "x86_64".
+    #[arg(long)]
+    arch: Option<String>,
+
+    /// Look up the debug info for every byte individually, instead of once per
+    /// address range from the DWARF line tables. This is much slower; it is
+    /// useful for verifying the output of the faster default.
+    #[arg(long)]
+    brute_force: bool,
+
+    /// The `Cargo.toml` of the workspace that the binary was built from. Its
+    /// members, as listed by `cargo metadata`, are used to attribute source
+    /// files to crates.
+    #[arg(long)]
+    manifest_path: Option<PathBuf>,
+
+    /// Don't display progress bars.
+    #[arg(short, long)]
+    quiet: bool,
+}
 
-    let mut sections: Vec<_> = object_file
-        .sections()
-        .filter_map(|s| {
-            use object::ObjectSection;
-            let file_range = s.compressed_file_range().unwrap();
-            let is_compressed = file_range.format != CompressionFormat::None;
-            if file_range.uncompressed_size == 0 {
-                return None;
+impl CommonArgs {
+    fn symbol_config(&self) -> Result<SymbolConfig, Box<dyn std::error::Error>> {
+        let mut config = match &self.config {
+            Some(path) => SymbolConfig::from_file(path)?,
+            None => SymbolConfig::default(),
+        };
+        if let Some(symbol_server) = &self.symbol_server {
+            if config.offline {
+                return Err(
+                    "--symbol-server can't be used with offline = true in the config file".into(),
+                );
             }
+            config.symbol_server = Some(symbol_server.clone());
+        }
+        if let Some(symbol_cache) = &self.symbol_cache {
+            config.symbol_cache = Some(symbol_cache.clone());
+        }
+        config.symbol_dirs.extend(self.symbol_dir.iter().cloned());
+        config
+            .debuginfod_mirrors
+            .extend(self.debuginfod_mirror.iter().cloned());
+        if let Some(debug_file) = &self.debug_file {
+            config.debug_file = Some(debug_file.clone());
+        }
+        if self.offline && config.symbol_server.is_some() {
+            return Err("--offline can't be used with symbol-server in the config file".into());
+        }
+        config.offline |= self.offline;
+        Ok(config)
+    }
 
-            Some(Section {
-                file_offset: file_range.offset,
-                svma: s.address(),
-                size: file_range.compressed_size,
-                kind: s.kind(),
-                name: s.name().unwrap().to_string(),
-                is_compressed,
-            })
+    fn analysis_options(&self) -> Result<AnalysisOptions, Box<dyn std::error::Error>> {
+        let crates = match &self.manifest_path {
+            Some(manifest_path) => CrateMap::from_cargo_metadata(manifest_path)?,
+            None => CrateMap::default(),
+        };
+        Ok(AnalysisOptions {
+            symbol_config: self.symbol_config()?,
+            arch: self.arch.clone(),
+            brute_force: self.brute_force,
+            show_progress: !self.quiet,
+            crates,
+            find_strings: false,
         })
-        .collect();
+    }
+}
 
-    sections.sort_by_key(|s| s.file_offset);
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Firefox profiler JSON, which can be loaded with `samply load`.
+    Json,
+    /// Gzip-compressed Firefox profiler JSON. Both samply and
+    /// profiler.firefox.com can load this directly.
+    JsonGz,
+    /// A textual table of sizes, similar to bloaty.
+    Table,
+    /// Folded stacks, one "frame;frame;frame bytes" line per stack, for
+    /// flamegraph.pl and inferno.
+    Folded,
+    /// A speedscope profile with byte counts as the sample weights.
+    Speedscope,
+    /// A gzipped pprof profile (profile.proto), for `go tool pprof`.
+    Pprof,
+    /// A self-contained HTML file with an interactive treemap.
+    Html,
+    /// An SQLite database with tables for the ranges, inline frames, symbols,
+    /// sections and source files.
+    Sqlite,
+    /// One JSON object per line for each attributed range, with its section,
+    /// symbol and inline frames.
+    Jsonl,
+    /// One CSV row for each attributed range, with the inline frames as a JSON
+    /// array in the last column.
+    Csv,
+    /// A textual report of the generic functions with the most bytes across
+    /// all their monomorphized instances.
+    Generics,
+    /// A textual report of the string literals: the largest ones and the
+    /// functions which use them, duplicates, and panic metadata.
+    Strings,
+}
 
-    let mut previous_section_end_file_offset = 0;
-    let mut previous_section_name = None;
+impl OutputFormat {
+    fn default_output_path(self) -> &'static str {
+        match self {
+            OutputFormat::Json => "output.json",
+            OutputFormat::JsonGz => "output.json.gz",
+            OutputFormat::Table => "-",
+            OutputFormat::Folded => "output.folded",
+            OutputFormat::Speedscope => "output.speedscope.json",
+            OutputFormat::Pprof => "output.pb.gz",
+            OutputFormat::Html => "output.html",
+            OutputFormat::Sqlite => "output.sqlite",
+            OutputFormat::Jsonl => "output.jsonl",
+            OutputFormat::Csv => "output.csv",
+            OutputFormat::Generics => "-",
+            OutputFormat::Strings => "-",
+        }
+    }
 
-    for s in sections {
-        let section_name = &s.name;
-        let section_start_file_offset = s.file_offset;
+    /// Whether the sink writes the output while the ranges arrive, into the
+    /// writer from [`OutputArgs::open_output`], rather than in `write_output`.
+    fn is_streamed(self) -> bool {
+        matches!(self, OutputFormat::Jsonl | OutputFormat::Csv)
+    }
+}
 
-        if section_start_file_offset < previous_section_end_file_offset {
-            panic!("Overlapping sections: Section {section_name} starts at file offset {section_start_file_offset:#x} which is before the end file offset {previous_section_end_file_offset:#x} of section {}", previous_section_name.unwrap());
+impl OutputArgs {
+    fn format(&self) -> OutputFormat {
+        if let Some(format) = self.format {
+            return format;
         }
-
-        if section_start_file_offset > previous_section_end_file_offset {
-            let padding_bytes_before_section =
-                section_start_file_offset - previous_section_end_file_offset;
-            profile.add_sample(
-                thread,
-                Timestamp::from_millis_since_reference(
-                    (timestamp_offset + previous_section_end_file_offset) as f64,
-                ),
-                Some(root_stack),
-                CpuDelta::ZERO,
-                i32::try_from(padding_bytes_before_section).unwrap(),
-            );
+        let file_name = self.output.as_deref().and_then(Path::file_name);
+        let file_name = file_name.and_then(|f| f.to_str()).unwrap_or_default();
+        if file_name.ends_with(".pb.gz") {
+            OutputFormat::Pprof
+        } else if file_name.ends_with(".gz") {
+            OutputFormat::JsonGz
+        } else if file_name.ends_with(".sqlite") || file_name.ends_with(".db") {
+            OutputFormat::Sqlite
+        } else if file_name.ends_with(".jsonl") {
+            OutputFormat::Jsonl
+        } else if file_name.ends_with(".csv") {
+            OutputFormat::Csv
+        } else {
+            OutputFormat::Json
         }
-
-        process_section(
-            profile,
-            thread,
-            root_stack,
-            &s,
-            &symbol_map,
-            base_addr,
-            library_handle,
-            category,
-            timestamp_offset,
-        )
-        .await;
-
-        previous_section_end_file_offset = s.file_offset + s.size;
-        previous_section_name = Some(s.name);
     }
 
-    let file_end_file_offset = binary_file_size;
-    if file_end_file_offset < previous_section_end_file_offset {
-        panic!("Truncated section: File size is {file_end_file_offset:#x} which is less than the end file offset {previous_section_end_file_offset:#x} of section {}", previous_section_name.unwrap());
+    fn output_path(&self) -> PathBuf {
+        self.output
+            .clone()
+            .unwrap_or_else(|| self.format().default_output_path().into())
     }
 
-    if file_end_file_offset > previous_section_end_file_offset {
-        let padding_bytes_after_section = file_end_file_offset - previous_section_end_file_offset;
-        profile.add_sample(
-            thread,
-            Timestamp::from_millis_since_reference(
-                (timestamp_offset + previous_section_end_file_offset) as f64,
-            ),
-            Some(root_stack),
-            CpuDelta::ZERO,
-            i32::try_from(padding_bytes_after_section).unwrap(),
-        );
+    fn is_stdout(&self) -> bool {
+        self.output_path() == Path::new("-")
     }
-}
 
-#[allow(clippy::too_many_arguments)]
-async fn process_section(
-    profile: &mut Profile,
-    thread: ThreadHandle,
-    root_stack: StackHandle,
-    section: &Section,
-    symbol_map: &wholesym::SymbolMap,
-    base_addr: u64,
-    library_handle: LibraryHandle,
-    category: CategoryHandle,
-    timestamp_offset: u64,
-) {
-    let section_s = profile.handle_for_string(&section.name);
-    let section_frame =
-        profile.handle_for_frame_with_label(thread, section_s, category, FrameFlags::empty());
-    let section_stack = profile.handle_for_stack(thread, section_frame, Some(root_stack));
-
-    if section.kind != SectionKind::Text {
-        let section_kind_str = profile.handle_for_string(&format!("{:?}", section.kind));
-        let section_kind_frame = profile.handle_for_frame_with_label(
-            thread,
-            section_kind_str,
-            category,
-            FrameFlags::empty(),
-        );
-        let section_kind_stack =
-            profile.handle_for_stack(thread, section_kind_frame, Some(section_stack));
-        profile.add_sample(
-            thread,
-            Timestamp::from_millis_since_reference((timestamp_offset + section.file_offset) as f64),
-            Some(section_kind_stack),
-            CpuDelta::ZERO,
-            i32::try_from(section.size).unwrap(),
-        );
-        return;
+    fn open_output(&self) -> Result<Box<dyn Write>, Box<dyn std::error::Error>> {
+        create_output(&self.output_path())
     }
 
-    let section_size = section.size;
-    let section_start_rel = section.svma - base_addr;
-    let section_end_rel = section.svma + section_size - base_addr;
-
-    let unknown_path_str = profile.handle_for_string("<unknown path>");
-    let unknown_path_frame = profile.handle_for_frame_with_label(
-        thread,
-        unknown_path_str,
-        category,
-        FrameFlags::empty(),
-    );
-
-    let unknown_bytes_str = profile.handle_for_string("<unknown bytes>");
-    let unknown_bytes_frame = profile.handle_for_frame_with_label(
-        thread,
-        unknown_bytes_str,
-        category,
-        FrameFlags::empty(),
-    );
-
-    let unknown_path_stack =
-        profile.handle_for_stack(thread, unknown_path_frame, Some(section_stack));
-
-    let pb = ProgressBar::new(section_size);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template(
-                "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})",
-            )
-            .unwrap()
-            .progress_chars("#>-"),
-    );
-
-    let mut pending_sample_relative_address = 0;
-    let mut pending_sample_addr_info = None;
-    let mut pending_sample_bytes = 0;
-    let mut pending_sample_file_offset = section.file_offset;
-
-    let mut stack_prefix_for_path: HashMap<String, StackHandle> = HashMap::new();
-    for addr in dbg!(section_start_rel..section_end_rel /* .min(40326317)*/) {
-        if addr & 0xffff == 0 {
-            pb.set_position(addr - section_start_rel);
+    /// Creates the sink for the output format, for the binary at `input`.
+    /// `crates` is used for the source files of inlined code.
+    fn sink(
+        &self,
+        input: &Path,
+        crates: &CrateMap,
+        format_options: &FormatOptions,
+    ) -> Result<Box<dyn OutputSink>, Box<dyn std::error::Error>> {
+        let name = file_name(input)?;
+        let format = self.format();
+        if format_options.generic_frames
+            && !matches!(format, OutputFormat::Json | OutputFormat::JsonGz)
+        {
+            return Err("--generic-frames only supports the json and json-gz formats".into());
         }
+        Ok(match format {
+            OutputFormat::Json | OutputFormat::JsonGz => {
+                let mut sink = ProfileSink::new(format_options.generic_frames);
+                sink.add_process(name, 0);
+                Box::new(sink)
+            }
+            OutputFormat::Table => {
+                Box::new(TableSink::new(format_options.group_by.clone(), self.top))
+            }
+            OutputFormat::Folded => Box::<FoldedSink>::default(),
+            OutputFormat::Speedscope => Box::new(SpeedscopeSink::new(name)),
+            OutputFormat::Pprof => Box::new(PprofSink::new()),
+            OutputFormat::Html => Box::new(TreemapSink::new(name)),
+            OutputFormat::Sqlite => {
+                let path = (!self.is_stdout()).then(|| self.output_path());
+                Box::new(SqliteSink::new(crates.clone(), path.as_deref())?)
+            }
+            OutputFormat::Jsonl => Box::new(RangesSink::new(
+                RangesFormat::JsonLines,
+                self.open_output()?,
+            )),
+            OutputFormat::Csv => Box::new(RangesSink::new(RangesFormat::Csv, self.open_output()?)),
+            OutputFormat::Generics => Box::new(GenericsSink::new(self.top)),
+            OutputFormat::Strings => Box::new(StringsSink::new(self.top)),
+        })
+    }
 
-        let addr_info = symbol_map
-            .lookup(wholesym::LookupAddress::Relative(addr as u32))
-            .await;
-
-        if pending_sample_bytes == 0 {
-            pending_sample_addr_info = addr_info;
-            pending_sample_relative_address = addr as u32;
-        } else if addr_info != pending_sample_addr_info {
-            emit_sample_for_address(
-                pending_sample_relative_address,
-                pending_sample_addr_info,
-                Timestamp::from_millis_since_reference(
-                    (timestamp_offset + pending_sample_file_offset) as f64,
-                ),
-                pending_sample_bytes,
-                section_stack,
-                unknown_path_stack,
-                unknown_bytes_frame,
-                thread,
-                library_handle,
-                category,
-                profile,
-                &mut stack_prefix_for_path,
-            );
-            pending_sample_file_offset += pending_sample_bytes;
-            pending_sample_relative_address = addr as u32;
-            pending_sample_addr_info = addr_info;
-            pending_sample_bytes = 0;
+    fn write(&self, sink: &mut dyn OutputSink) -> Result<(), Box<dyn std::error::Error>> {
+        let format = self.format();
+        // The output is already open, and has all but the buffered rest. The
+        // SQLite database is written in place unless it goes to stdout.
+        if format.is_streamed() || (format == OutputFormat::Sqlite && !self.is_stdout()) {
+            return sink.write_output(&mut std::io::sink());
+        }
+        let mut writer = self.open_output()?;
+
+        // Write straight into the (compressing) writer, so that the uncompressed
+        // output never needs to exist in full, neither in memory nor on disk.
+        if format == OutputFormat::JsonGz {
+            let mut encoder = GzEncoder::new(&mut writer, Compression::default());
+            sink.write_output(&mut encoder)?;
+            encoder.finish()?;
+        } else {
+            sink.write_output(&mut writer)?;
         }
-        pending_sample_bytes += 1;
+        writer.flush()?;
+        Ok(())
     }
-    emit_sample_for_address(
-        pending_sample_relative_address,
-        pending_sample_addr_info,
-        Timestamp::from_millis_since_reference(
-            (timestamp_offset + pending_sample_file_offset) as f64,
-        ),
-        pending_sample_bytes,
-        section_stack,
-        unknown_path_stack,
-        unknown_bytes_frame,
-        thread,
-        library_handle,
-        category,
-        profile,
-        &mut stack_prefix_for_path,
-    );
-    pending_sample_file_offset += pending_sample_bytes;
-
-    assert_eq!(
-        pending_sample_file_offset,
-        section.file_offset + section.size,
-        "Unexpected file offset after processing section {}",
-        &section.name
-    );
-
-    pb.finish_with_message("Section processed");
 }
 
-fn get_outer_function_location(addr_info: &Option<wholesym::AddressInfo>) -> Option<String> {
-    let frames = addr_info.as_ref()?.frames.as_ref()?;
-    let file_path = frames.last()?.file_path.as_ref()?;
-    Some(file_path.display_path())
+/// Prints the warnings about the debug info of the analyzed binaries.
+struct PrintWarnings;
+
+impl RangeSink for PrintWarnings {
+    fn range(&mut self, _range: &AttributedRange) {}
+
+    fn warning(&mut self, message: &str) {
+        eprintln!("warning: {message}");
+    }
 }
 
-fn get_path_stack(
-    addr_info: &Option<wholesym::AddressInfo>,
-    root_stack: StackHandle,
-    thread: ThreadHandle,
-    category: CategoryHandle,
-    profile: &mut Profile,
-    stack_prefix_for_path: &mut HashMap<String, StackHandle>,
-) -> Option<StackHandle> {
-    let path = get_outer_function_location(addr_info)?;
-    if let Some(ps) = stack_prefix_for_path.get(&path) {
-        return Some(*ps);
+#[tokio::main]
+async fn main() {
+    let opt = Opt::parse();
+    let result = match opt.command {
+        Some(Command::Diff(args)) => run_diff(args).await,
+        Some(Command::Check(args)) => run_check(args).await,
+        None => run_profile(opt).await,
+    };
+    if let Err(err) = result {
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    }
+}
+
+async fn run_profile(opt: Opt) -> Result<(), Box<dyn std::error::Error>> {
+    let input = opt.input.expect("clap should have required the input");
+    let mut options = opt.common.analysis_options()?;
+    options.find_strings = opt.output.format() == OutputFormat::Strings;
+    let mut sink = opt
+        .output
+        .sink(&input, &options.crates, &opt.format_options)?;
+    let mut warnings = PrintWarnings;
+    let mut sinks: Vec<&mut dyn RangeSink> = vec![&mut *sink, &mut warnings];
+    analyze_binary_into(&input, &options, &mut sinks).await?;
+    opt.output.write(&mut *sink)
+}
+
+async fn run_diff(args: DiffArgs) -> Result<(), Box<dyn std::error::Error>> {
+    if !matches!(
+        args.output.format(),
+        OutputFormat::Json | OutputFormat::JsonGz
+    ) {
+        return Err("The diff command only supports the json and json-gz formats".into());
+    }
+    let options = args.common.analysis_options()?;
+    let mut profile = ProfileSink::new(false);
+    let mut old_summary = SizeSummary::default();
+    let mut new_summary = SizeSummary::default();
+    profile.add_process(file_name(&args.old)?, 0);
+    let mut warnings = PrintWarnings;
+    let mut sinks: Vec<&mut dyn RangeSink> = vec![&mut profile, &mut old_summary, &mut warnings];
+    analyze_binary_into(&args.old, &options, &mut sinks).await?;
+    profile.add_process(file_name(&args.new)?, 1);
+    let mut sinks: Vec<&mut dyn RangeSink> = vec![&mut profile, &mut new_summary, &mut warnings];
+    analyze_binary_into(&args.new, &options, &mut sinks).await?;
+
+    let deltas = diff::size_deltas(&old_summary, &new_summary);
+    diff::add_diff_thread(profile.profile_mut(), 2, &deltas);
+    args.output.write(&mut profile)?;
+
+    // Don't mix the report into the profile if the profile goes to stdout.
+    let top = args.output.top;
+    if args.output.is_stdout() {
+        diff::write_report(&mut std::io::stderr(), &old_summary, &new_summary, top)?;
+    } else {
+        diff::write_report(&mut std::io::stdout(), &old_summary, &new_summary, top)?;
     }
-    let path = path.trim_start_matches("C:\\b\\s\\w\\ir\\cache\\builder\\");
-    let mut accum_path = String::new();
-
-    let mut path_stack = root_stack;
-
-    for p in path.split(['/', '\\']) {
-        accum_path.push('/');
-        accum_path.push_str(p);
-        let frame_str = profile.handle_for_string(&accum_path);
-        let frame =
-            profile.handle_for_frame_with_label(thread, frame_str, category, FrameFlags::empty());
-        path_stack = profile.handle_for_stack(thread, frame, Some(path_stack));
+    Ok(())
+}
+
+async fn run_check(args: CheckArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let budgets = budget::read_budgets(&args.budget)?;
+    let options = args.common.analysis_options()?;
+    let mut summary = SizeSummary::default();
+    let mut warnings = PrintWarnings;
+    let mut sinks: Vec<&mut dyn RangeSink> = vec![&mut summary, &mut warnings];
+    analyze_binary_into(&args.input, &options, &mut sinks).await?;
+
+    let exceeded_count = budget::write_report(&mut std::io::stdout(), &budgets, &summary)?;
+    if exceeded_count != 0 {
+        return Err(format!("{exceeded_count} size budget(s) exceeded").into());
     }
-    stack_prefix_for_path.insert(path.to_owned(), path_stack);
-    Some(path_stack)
+    Ok(())
 }
 
-fn get_special_path(
-    file_path: Option<SourceFilePath>,
-    profile: &mut Profile,
-) -> Option<StringHandle> {
-    let file_path = file_path?;
-    let s = match file_path.mapped_path() {
-        Some(mapped_path) => {
-            let special_path = mapped_path.to_special_path_str();
-            profile.handle_for_string(&special_path)
-        }
-        None => profile.handle_for_string(file_path.raw_path()),
-    };
-    Some(s)
+fn file_name(path: &Path) -> Result<&str, Box<dyn std::error::Error>> {
+    let file_name = path
+        .file_name()
+        .and_then(|f| f.to_str())
+        .ok_or_else(|| format!("Invalid input path {}", path.display()))?;
+    Ok(file_name)
+}
+
+/// Opens `path` for writing, or stdout if `path` is "-".
+fn create_output(path: &Path) -> Result<Box<dyn Write>, Box<dyn std::error::Error>> {
+    if path.as_os_str() == "-" {
+        return Ok(Box::new(BufWriter::new(std::io::stdout().lock())));
+    }
+    let file = std::fs::File::create(path)
+        .map_err(|e| format!("Could not create {}: {e}", path.display()))?;
+    Ok(Box::new(BufWriter::new(file)))
 }
 
-#[allow(clippy::too_many_arguments)]
-fn emit_sample_for_address(
-    relative_address: u32,
-    addr_info: Option<wholesym::AddressInfo>,
-    timestamp: Timestamp,
-    bytes: u64,
-    root_stack: StackHandle,
-    unknown_path_stack: StackHandle,
-    unknown_bytes_frame: FrameHandle,
-    thread: ThreadHandle,
-    library_handle: LibraryHandle,
-    category: CategoryHandle,
-    profile: &mut Profile,
-    stack_prefix_for_path: &mut HashMap<String, StackHandle>,
-) {
-    let path_stack = get_path_stack(
-        &addr_info,
-        root_stack,
-        thread,
-        category,
-        profile,
-        stack_prefix_for_path,
-    )
-    .unwrap_or(unknown_path_stack);
-
-    let stack = if let Some(addr_info) = addr_info {
-        let symbol = Symbol {
-            address: addr_info.symbol.address,
-            size: addr_info.symbol.size,
-            name: addr_info.symbol.name,
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generic_frames_only_for_profiles() {
+        let sink = |args: &[&str]| {
+            let args = ["binary-size-profiler"]
+                .iter()
+                .chain(args)
+                .chain(&["binary"]);
+            let opt = Opt::try_parse_from(args).unwrap();
+            opt.output
+                .sink(
+                    Path::new("binary"),
+                    &CrateMap::default(),
+                    &opt.format_options,
+                )
+                .map(|_| ())
         };
-        let native_symbol = profile.handle_for_native_symbol(thread, library_handle, &symbol);
-        let mut s = path_stack;
-        if let Some(mut frames) = addr_info.frames {
-            frames.reverse();
-            for (inline_depth, f) in frames.into_iter().enumerate() {
-                let name = f.function.unwrap_or_else(|| symbol.name.clone());
-                let name = profile.handle_for_string(&name);
-                let file_path = get_special_path(f.file_path, profile);
-                let frame = profile.handle_for_frame_with_address_and_symbol(
-                    thread,
-                    FrameAddress::RelativeAddressFromInstructionPointer(
-                        library_handle,
-                        relative_address,
-                    ),
-                    FrameSymbolInfo {
-                        name: Some(name),
-                        native_symbol,
-                        source_location: SourceLocation {
-                            file_path,
-                            line: f.line_number,
-                            col: None,
-                        },
-                    },
-                    inline_depth as u16,
-                    category,
-                    FrameFlags::empty(),
-                );
-                s = profile.handle_for_stack(thread, frame, Some(s));
-            }
-        } else {
-            let name = profile.handle_for_string(&symbol.name);
-            let frame = profile.handle_for_frame_with_address_and_symbol(
-                thread,
-                FrameAddress::RelativeAddressFromInstructionPointer(
-                    library_handle,
-                    relative_address,
-                ),
-                FrameSymbolInfo {
-                    name: Some(name),
-                    native_symbol,
-                    source_location: SourceLocation {
-                        file_path: None,
-                        line: None,
-                        col: None,
-                    },
-                },
-                0,
-                category,
-                FrameFlags::empty(),
-            );
-            s = profile.handle_for_stack(thread, frame, Some(s));
-        }
-        s
-    } else {
-        profile.handle_for_stack(thread, unknown_bytes_frame, Some(path_stack))
-    };
+        let error = sink(&["--generic-frames", "-f", "table"]).unwrap_err();
+        assert!(error.to_string().starts_with("--generic-frames"));
+        assert!(sink(&["--generic-frames", "-f", "folded", "-o", "-"]).is_err());
+        assert!(sink(&["-f", "folded", "-o", "-"]).is_ok());
+    }
 
-    profile.add_sample(
-        thread,
-        timestamp,
-        Some(stack),
-        CpuDelta::ZERO,
-        i32::try_from(bytes).unwrap(),
-    );
+    #[test]
+    fn offline_rejects_symbol_servers() {
+        let parse = |args: &[&str]| {
+            let args = ["binary-size-profiler"]
+                .iter()
+                .chain(args)
+                .chain(&["binary"]);
+            Opt::try_parse_from(args)
+        };
+        assert!(parse(&[
+            "--offline",
+            "--symbol-server",
+            "https://symbols.example.com/"
+        ])
+        .is_err());
+
+        let path = std::env::temp_dir().join(format!(
+            "binary-size-profiler-{}-offline.toml",
+            std::process::id()
+        ));
+        let config = path.to_str().unwrap();
+        std::fs::write(&path, "symbol-server = \"https://symbols.example.com/\"").unwrap();
+        let opt = parse(&["--offline", "--config", config]).unwrap();
+        assert!(opt.common.symbol_config().is_err());
+
+        std::fs::write(&path, "offline = true").unwrap();
+        let opt = parse(&[
+            "--symbol-server",
+            "https://symbols.example.com/",
+            "--config",
+            config,
+        ])
+        .unwrap();
+        assert!(opt.common.symbol_config().is_err());
+        let opt = parse(&["--offline", "--config", config]).unwrap();
+        assert!(opt.common.symbol_config().unwrap().offline);
+        std::fs::remove_file(&path).unwrap();
+    }
 }
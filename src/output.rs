@@ -0,0 +1,9 @@
+use std::io::Write;
+
+use binary_size_profiler::RangeSink;
+
+/// A [`RangeSink`] for one of the output formats, which writes the output
+/// once it has received all ranges.
+pub trait OutputSink: RangeSink {
+    fn write_output(&mut self, w: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>>;
+}
@@ -0,0 +1,432 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Arc;
+
+use binary_size_profiler::{AttributedRange, Binary, RangeSink};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use object::SectionKind;
+use prost::Message;
+
+use crate::output::OutputSink;
+use crate::summary::path_frame_labels;
+
+/// Builds a pprof profile, as described by `profile.proto` in
+/// github.com/google/pprof. Every attributed range becomes a sample whose
+/// value is its byte count. Text ranges have a location at their address with
+/// one line per inline frame. The section and path frames of the Firefox
+/// profile become locations without a mapping, so that the stacks look the
+/// same in pprof's views.
+pub struct PprofSink {
+    profile: proto::Profile,
+    strings: HashMap<String, i64>,
+    functions: HashMap<(String, Option<String>), u64>,
+    label_locations: HashMap<String, u64>,
+    /// The locations of text ranges and data symbols, by mapping id and
+    /// address.
+    address_locations: HashMap<(u64, u64), u64>,
+    /// The mapping id for the current binary.
+    mapping_id: u64,
+}
+
+impl PprofSink {
+    pub fn new() -> Self {
+        let mut sink = PprofSink {
+            profile: proto::Profile::default(),
+            strings: HashMap::new(),
+            functions: HashMap::new(),
+            label_locations: HashMap::new(),
+            address_locations: HashMap::new(),
+            mapping_id: 0,
+        };
+        // The string table must start with the empty string.
+        sink.string("");
+        let sample_type = proto::ValueType {
+            r#type: sink.string("bytes"),
+            unit: sink.string("bytes"),
+        };
+        sink.profile.sample_type.push(sample_type);
+        sink
+    }
+
+    fn string(&mut self, s: &str) -> i64 {
+        if let Some(index) = self.strings.get(s) {
+            return *index;
+        }
+        let index = self.profile.string_table.len() as i64;
+        self.profile.string_table.push(s.to_owned());
+        self.strings.insert(s.to_owned(), index);
+        index
+    }
+
+    fn function(&mut self, name: &str, file: Option<String>) -> u64 {
+        let key = (name.to_owned(), file);
+        if let Some(id) = self.functions.get(&key) {
+            return *id;
+        }
+        let id = self.profile.function.len() as u64 + 1;
+        let function = proto::Function {
+            id,
+            name: self.string(name),
+            system_name: self.string(name),
+            filename: key.1.as_deref().map_or(0, |f| self.string(f)),
+            start_line: 0,
+        };
+        self.profile.function.push(function);
+        self.functions.insert(key, id);
+        id
+    }
+
+    /// A location without a mapping for a stack label such as a section name.
+    fn label_location(&mut self, label: &str) -> u64 {
+        if let Some(id) = self.label_locations.get(label) {
+            return *id;
+        }
+        let function_id = self.function(label, None);
+        let id = self.add_location(
+            0,
+            0,
+            vec![proto::Line {
+                function_id,
+                line: 0,
+            }],
+        );
+        self.label_locations.insert(label.to_owned(), id);
+        id
+    }
+
+    fn add_location(&mut self, mapping_id: u64, address: u64, line: Vec<proto::Line>) -> u64 {
+        let id = self.profile.location.len() as u64 + 1;
+        self.profile.location.push(proto::Location {
+            id,
+            mapping_id,
+            address,
+            line,
+            is_folded: false,
+        });
+        id
+    }
+
+    /// The location for the bytes of a text range or a data symbol, with the
+    /// inline frames from innermost to outermost, as pprof expects them.
+    /// Ranges at the same address share the location. Ranges without an
+    /// address, like symbol table entries, each get their own.
+    fn text_location(&mut self, range: &AttributedRange) -> u64 {
+        let Some(addr_info) = &range.addr_info else {
+            return self.label_location("<unknown bytes>");
+        };
+        let key = range
+            .relative_address
+            .map(|address| (self.mapping_id, u64::from(address)));
+        if let Some(id) = key.and_then(|key| self.address_locations.get(&key)) {
+            return *id;
+        }
+        let lines = match &addr_info.frames {
+            Some(frames) => frames
+                .iter()
+                .map(|f| {
+                    let name = f.function.as_deref().unwrap_or(&addr_info.symbol.name);
+                    let file = f.file_path.as_ref().map(|p| p.display_path());
+                    proto::Line {
+                        function_id: self.function(name, file),
+                        line: f.line_number.map_or(0, i64::from),
+                    }
+                })
+                .collect(),
+            None => vec![proto::Line {
+                function_id: self.function(&addr_info.symbol.name, None),
+                line: 0,
+            }],
+        };
+        let address = key.map_or(0, |(_, address)| address);
+        let id = self.add_location(self.mapping_id, address, lines);
+        if let Some(key) = key {
+            self.address_locations.insert(key, id);
+        }
+        id
+    }
+}
+
+impl RangeSink for PprofSink {
+    fn begin_binary(&mut self, binary: &Arc<Binary>) {
+        let lib_info = &binary.lib_info;
+        let path = lib_info
+            .path
+            .as_deref()
+            .or(lib_info.name.as_deref())
+            .unwrap_or_default();
+        // pprof's build id is the GNU build id on Linux, which is the code id.
+        let build_id = match (&lib_info.code_id, &lib_info.debug_id) {
+            (Some(code_id), _) => code_id.to_string(),
+            (None, Some(debug_id)) => debug_id.breakpad().to_string(),
+            (None, None) => String::new(),
+        };
+        self.mapping_id = self.profile.mapping.len() as u64 + 1;
+        let mapping = proto::Mapping {
+            id: self.mapping_id,
+            memory_start: binary.relative_address_range.start,
+            memory_limit: binary.relative_address_range.end,
+            file_offset: 0,
+            filename: self.string(path),
+            build_id: self.string(&build_id),
+            has_functions: true,
+            has_filenames: true,
+            has_line_numbers: true,
+            has_inline_frames: true,
+        };
+        self.profile.mapping.push(mapping);
+    }
+
+    fn range(&mut self, range: &AttributedRange) {
+        // Build the stack from the root, then reverse it, because pprof
+        // stacks start with the leaf.
+        let mut location_id = vec![self.label_location("(root)")];
+        let mut label = Vec::new();
+        let member_name = range.binary.as_ref().and_then(|b| b.member_name.as_deref());
+        if let Some(member_name) = member_name {
+            location_id.push(self.label_location(member_name));
+        }
+        if let Some(section) = &range.section {
+            location_id.push(self.label_location(&section.name));
+            label.push(proto::Label {
+                key: self.string("section"),
+                str: self.string(&section.name),
+                num: 0,
+                num_unit: 0,
+            });
+            if section.kind == SectionKind::Text {
+                match range.outer_function_path() {
+                    Some(path) => {
+                        for path_label in path_frame_labels(range.crate_info.as_ref(), &path) {
+                            location_id.push(self.label_location(&path_label));
+                        }
+                    }
+                    None => location_id.push(self.label_location("<unknown path>")),
+                }
+                location_id.push(self.text_location(range));
+            } else {
+                location_id.push(self.label_location(&format!("{:?}", section.kind)));
+                // The symbols of data sections.
+                if range.addr_info.is_some() {
+                    match (range.outer_function_path(), &range.crate_info) {
+                        (Some(path), _) => {
+                            for path_label in path_frame_labels(range.crate_info.as_ref(), &path) {
+                                location_id.push(self.label_location(&path_label));
+                            }
+                        }
+                        (None, Some(crate_info)) => {
+                            location_id.push(self.label_location(&crate_info.to_string()));
+                        }
+                        (None, None) => {}
+                    }
+                    location_id.push(self.text_location(range));
+                }
+            }
+        }
+        location_id.reverse();
+        self.profile.sample.push(proto::Sample {
+            location_id,
+            value: vec![range.size as i64],
+            label,
+        });
+    }
+}
+
+impl OutputSink for PprofSink {
+    fn write_output(&mut self, w: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+        let mut encoder = GzEncoder::new(w, Compression::default());
+        encoder.write_all(&self.profile.encode_to_vec())?;
+        encoder.finish()?;
+        Ok(())
+    }
+}
+
+/// The messages from pprof's `profile.proto`.
+mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Profile {
+        #[prost(message, repeated, tag = "1")]
+        pub sample_type: Vec<ValueType>,
+        #[prost(message, repeated, tag = "2")]
+        pub sample: Vec<Sample>,
+        #[prost(message, repeated, tag = "3")]
+        pub mapping: Vec<Mapping>,
+        #[prost(message, repeated, tag = "4")]
+        pub location: Vec<Location>,
+        #[prost(message, repeated, tag = "5")]
+        pub function: Vec<Function>,
+        #[prost(string, repeated, tag = "6")]
+        pub string_table: Vec<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ValueType {
+        #[prost(int64, tag = "1")]
+        pub r#type: i64,
+        #[prost(int64, tag = "2")]
+        pub unit: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Sample {
+        #[prost(uint64, repeated, tag = "1")]
+        pub location_id: Vec<u64>,
+        #[prost(int64, repeated, tag = "2")]
+        pub value: Vec<i64>,
+        #[prost(message, repeated, tag = "3")]
+        pub label: Vec<Label>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Label {
+        #[prost(int64, tag = "1")]
+        pub key: i64,
+        #[prost(int64, tag = "2")]
+        pub str: i64,
+        #[prost(int64, tag = "3")]
+        pub num: i64,
+        #[prost(int64, tag = "4")]
+        pub num_unit: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Mapping {
+        #[prost(uint64, tag = "1")]
+        pub id: u64,
+        #[prost(uint64, tag = "2")]
+        pub memory_start: u64,
+        #[prost(uint64, tag = "3")]
+        pub memory_limit: u64,
+        #[prost(uint64, tag = "4")]
+        pub file_offset: u64,
+        #[prost(int64, tag = "5")]
+        pub filename: i64,
+        #[prost(int64, tag = "6")]
+        pub build_id: i64,
+        #[prost(bool, tag = "7")]
+        pub has_functions: bool,
+        #[prost(bool, tag = "8")]
+        pub has_filenames: bool,
+        #[prost(bool, tag = "9")]
+        pub has_line_numbers: bool,
+        #[prost(bool, tag = "10")]
+        pub has_inline_frames: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Location {
+        #[prost(uint64, tag = "1")]
+        pub id: u64,
+        #[prost(uint64, tag = "2")]
+        pub mapping_id: u64,
+        #[prost(uint64, tag = "3")]
+        pub address: u64,
+        #[prost(message, repeated, tag = "4")]
+        pub line: Vec<Line>,
+        #[prost(bool, tag = "5")]
+        pub is_folded: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Line {
+        #[prost(uint64, tag = "1")]
+        pub function_id: u64,
+        #[prost(int64, tag = "2")]
+        pub line: i64,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Function {
+        #[prost(uint64, tag = "1")]
+        pub id: u64,
+        #[prost(int64, tag = "2")]
+        pub name: i64,
+        #[prost(int64, tag = "3")]
+        pub system_name: i64,
+        #[prost(int64, tag = "4")]
+        pub filename: i64,
+        #[prost(int64, tag = "5")]
+        pub start_line: i64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use binary_size_profiler::wholesym::{AddressInfo, SymbolInfo};
+    use binary_size_profiler::Section;
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    fn text_range(binary: &Arc<Binary>, relative_address: u32, symbol: &str) -> AttributedRange {
+        AttributedRange {
+            file_offset: u64::from(relative_address),
+            size: 4,
+            binary: Some(binary.clone()),
+            section: Some(Arc::new(Section {
+                file_offset: 0x1000,
+                svma: 0x1000,
+                size: 0x100,
+                kind: SectionKind::Text,
+                name: ".text".to_owned(),
+                is_compressed: false,
+                uncompressed_size: 0x100,
+            })),
+            relative_address: Some(relative_address),
+            addr_info: Some(AddressInfo {
+                symbol: SymbolInfo {
+                    address: 0x1000,
+                    size: None,
+                    name: symbol.to_owned(),
+                },
+                frames: None,
+            }),
+            crate_info: None,
+            instantiating_crate: None,
+        }
+    }
+
+    #[test]
+    fn locations_are_shared_by_address() {
+        let binary = Arc::new(Binary {
+            member_name: None,
+            file_offset: 0,
+            size: 0x2000,
+            relative_address_range: 0..0x2000,
+            lib_info: Default::default(),
+            string_literals: None,
+        });
+        let mut sink = PprofSink::new();
+        sink.begin_binary(&binary);
+        sink.range(&text_range(&binary, 0x1000, "main"));
+        sink.range(&text_range(&binary, 0x1004, "main"));
+        sink.range(&text_range(&binary, 0x1000, "main"));
+        let mut output = Vec::new();
+        sink.write_output(&mut output).unwrap();
+        let mut data = Vec::new();
+        GzDecoder::new(&output[..]).read_to_end(&mut data).unwrap();
+        let profile = proto::Profile::decode(&data[..]).unwrap();
+
+        assert_eq!(profile.string_table[0], "");
+        let leaves: Vec<u64> = profile.sample.iter().map(|s| s.location_id[0]).collect();
+        assert_eq!(leaves[0], leaves[2]);
+        assert_ne!(leaves[0], leaves[1]);
+        let location = |id: u64| &profile.location[id as usize - 1];
+        assert_eq!(location(leaves[0]).address, 0x1000);
+        assert_eq!(location(leaves[1]).address, 0x1004);
+        assert_eq!(location(leaves[0]).mapping_id, 1);
+        // Both locations are in the same function.
+        assert_eq!(
+            location(leaves[0]).line[0].function_id,
+            location(leaves[1]).line[0].function_id
+        );
+        // The root, the section and "<unknown path>" are shared too.
+        assert_eq!(
+            profile.sample[0].location_id[1..],
+            profile.sample[1].location_id[1..]
+        );
+        assert_eq!(profile.location.len(), 5);
+    }
+}
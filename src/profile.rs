@@ -0,0 +1,427 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io::Write;
+use std::sync::Arc;
+
+use binary_size_profiler::crates::CrateInfo;
+use binary_size_profiler::wholesym::{AddressInfo, SourceFilePath};
+use binary_size_profiler::{AttributedRange, Binary, RangeSink, Section};
+use fxprof_processed_profile::{
+    CategoryHandle, CpuDelta, FrameAddress, FrameFlags, FrameSymbolInfo, LibraryHandle,
+    LibraryInfo, Profile, ReferenceTimestamp, SamplingInterval, SourceLocation, StackHandle,
+    StringHandle, Symbol, ThreadHandle, TimelineUnit, Timestamp, WeightType,
+};
+use object::SectionKind;
+
+use crate::generics::generic_frame_label;
+use crate::output::OutputSink;
+use crate::summary::path_frame_labels;
+
+/// Builds a Firefox profile with one process per analyzed file. The samples
+/// are the attributed ranges, with the file offset as the timestamp and the
+/// byte count as the weight.
+pub struct ProfileSink {
+    profile: Profile,
+    thread: Option<ThreadState>,
+    generic_frames: bool,
+}
+
+struct ThreadState {
+    thread: ThreadHandle,
+    category: CategoryHandle,
+    root_stack: StackHandle,
+    /// The library and the stack for the ranges of the current binary.
+    binary: Option<(LibraryHandle, StackHandle)>,
+    section: Option<SectionState>,
+    /// The generic function frame label for each symbol name, if generic
+    /// frames are enabled.
+    generic_labels: Option<HashMap<String, Option<String>>>,
+}
+
+/// The path frames start with a frame for the crate, if it is known, so
+/// they're cached by crate and path.
+type PathStacks = HashMap<(Option<CrateInfo>, String), StackHandle>;
+
+enum SectionState {
+    /// Non-text sections are below a frame with the section kind. The symbols
+    /// of data sections are below their path, like functions.
+    Other {
+        kind_stack: StackHandle,
+        stack_prefix_for_path: PathStacks,
+    },
+    Text {
+        section_stack: StackHandle,
+        unknown_path_stack: StackHandle,
+        unknown_bytes_stack: StackHandle,
+        stack_prefix_for_path: PathStacks,
+    },
+}
+
+impl ProfileSink {
+    /// With `generic_frames`, the instances of each generic function or C++
+    /// template are grouped below an extra frame with the function's name.
+    pub fn new(generic_frames: bool) -> Self {
+        let mut profile = Profile::new(
+            "size-profiler",
+            ReferenceTimestamp::from_millis_since_unix_epoch(0.),
+            SamplingInterval::from_hz(1000.),
+        );
+        profile.set_timeline_unit(TimelineUnit::Bytes);
+        profile.set_symbolicated(true);
+        ProfileSink {
+            profile,
+            thread: None,
+            generic_frames,
+        }
+    }
+
+    /// Adds a process with one thread, which receives all following ranges.
+    pub fn add_process(&mut self, name: &str, pid: u32) {
+        let profile = &mut self.profile;
+        let process = profile.add_process(name, pid, Timestamp::from_millis_since_reference(0.));
+        let thread = profile.add_thread(
+            process,
+            pid,
+            Timestamp::from_millis_since_reference(0.),
+            true,
+        );
+        profile.set_thread_samples_weight_type(thread, WeightType::Bytes);
+        let category = CategoryHandle::OTHER;
+
+        let root_s = profile.handle_for_string("(root)");
+        let root_frame =
+            profile.handle_for_frame_with_label(thread, root_s, category, FrameFlags::empty());
+        let root_stack = profile.handle_for_stack(thread, root_frame, None);
+
+        self.thread = Some(ThreadState {
+            thread,
+            category,
+            root_stack,
+            binary: None,
+            section: None,
+            generic_labels: self.generic_frames.then(HashMap::new),
+        });
+    }
+
+    pub fn profile_mut(&mut self) -> &mut Profile {
+        &mut self.profile
+    }
+
+    fn state(&mut self) -> (&mut Profile, &mut ThreadState) {
+        let state = self
+            .thread
+            .as_mut()
+            .expect("add_process must be called before ranges are added");
+        (&mut self.profile, state)
+    }
+}
+
+impl RangeSink for ProfileSink {
+    fn begin_binary(&mut self, binary: &Arc<Binary>) {
+        let (profile, state) = self.state();
+        let lib_info = binary.lib_info.clone();
+        // wholesym derives the name from the path, so it's only missing for
+        // binaries which didn't come from a file.
+        let name = match (lib_info.name, &lib_info.path) {
+            (Some(name), _) => name,
+            (None, Some(path)) => path.clone(),
+            (None, None) => "[unknown]".to_owned(),
+        };
+        let debug_name = lib_info.debug_name.unwrap_or_else(|| name.clone());
+        let path = lib_info.path.unwrap_or_else(|| name.clone());
+        let debug_path = lib_info.debug_path.unwrap_or_else(|| path.clone());
+        let lib = LibraryInfo {
+            name,
+            debug_name,
+            path,
+            debug_path,
+            debug_id: lib_info.debug_id.unwrap_or_default(),
+            code_id: lib_info.code_id.map(|ci| ci.to_string()),
+            arch: lib_info.arch,
+            symbol_table: None,
+        };
+        let library_handle = profile.add_lib(lib);
+
+        let binary_stack = match &binary.member_name {
+            Some(member_name) => state.label_stack(profile, member_name, state.root_stack),
+            None => state.root_stack,
+        };
+        state.binary = Some((library_handle, binary_stack));
+    }
+
+    fn begin_section(&mut self, _binary: &Arc<Binary>, section: &Arc<Section>) {
+        let (profile, state) = self.state();
+        let (_, binary_stack) = state.binary.expect("begin_binary should have been called");
+        let section_stack = state.label_stack(profile, &section.name, binary_stack);
+        let section_state = if section.kind == SectionKind::Text {
+            let unknown_path_stack = state.label_stack(profile, "<unknown path>", section_stack);
+            let unknown_bytes_stack =
+                state.label_stack(profile, "<unknown bytes>", unknown_path_stack);
+            SectionState::Text {
+                section_stack,
+                unknown_path_stack,
+                unknown_bytes_stack,
+                stack_prefix_for_path: HashMap::new(),
+            }
+        } else {
+            let kind_label = format!("{:?}", section.kind);
+            let kind_stack = state.label_stack(profile, &kind_label, section_stack);
+            SectionState::Other {
+                kind_stack,
+                stack_prefix_for_path: HashMap::new(),
+            }
+        };
+        state.section = Some(section_state);
+    }
+
+    fn padding(&mut self, range: &AttributedRange) {
+        let (profile, state) = self.state();
+        // Padding between fat archive members has no binary.
+        let stack = match (&range.binary, state.binary) {
+            (Some(_), Some((_, binary_stack))) => binary_stack,
+            _ => state.root_stack,
+        };
+        add_sample(profile, state.thread, range.file_offset, stack, range.size);
+    }
+
+    fn range(&mut self, range: &AttributedRange) {
+        let (profile, state) = self.state();
+        let stack = state.stack_for_range(profile, range);
+        add_sample(profile, state.thread, range.file_offset, stack, range.size);
+    }
+
+    fn finish(&mut self, end_file_offset: u64) {
+        // Add a final sample with zero weight, so that the profiler's automatic time range detection
+        // includes all the file bytes.
+        let (profile, state) = self.state();
+        add_sample(profile, state.thread, end_file_offset, state.root_stack, 0);
+    }
+}
+
+impl OutputSink for ProfileSink {
+    fn write_output(&mut self, w: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+        serde_json::to_writer(w, &self.profile)?;
+        Ok(())
+    }
+}
+
+impl ThreadState {
+    fn label_stack(&self, profile: &mut Profile, label: &str, parent: StackHandle) -> StackHandle {
+        let s = profile.handle_for_string(label);
+        let frame =
+            profile.handle_for_frame_with_label(self.thread, s, self.category, FrameFlags::empty());
+        profile.handle_for_stack(self.thread, frame, Some(parent))
+    }
+
+    fn stack_for_range(&mut self, profile: &mut Profile, range: &AttributedRange) -> StackHandle {
+        let (library_handle, _) = self.binary.expect("begin_binary should have been called");
+        let mut section = self
+            .section
+            .take()
+            .expect("begin_section should have been called");
+        let stack = match &mut section {
+            SectionState::Other { kind_stack, .. } if range.addr_info.is_none() => *kind_stack,
+            SectionState::Other {
+                kind_stack,
+                stack_prefix_for_path,
+            } => {
+                let path_stack = match range.outer_function_path() {
+                    Some(path) => {
+                        self.path_stack(profile, range, path, *kind_stack, stack_prefix_for_path)
+                    }
+                    // Symbols without a source file, e.g. in the symbol
+                    // tables, can still have a crate.
+                    None => match &range.crate_info {
+                        Some(crate_info) => {
+                            self.label_stack(profile, &crate_info.to_string(), *kind_stack)
+                        }
+                        None => *kind_stack,
+                    },
+                };
+                let addr_info = range.addr_info.as_ref().unwrap();
+                match range.relative_address {
+                    // The frames of unwind info are at the address of its
+                    // function, like the function's code.
+                    Some(_) => self.frames_stack(
+                        profile,
+                        addr_info,
+                        library_handle,
+                        addr_info.symbol.address,
+                        path_stack,
+                    ),
+                    // The compilation units of DWARF sections and the symbols
+                    // of symbol tables have no address.
+                    None => match &addr_info.frames {
+                        Some(frames) => frames.iter().rev().fold(path_stack, |stack, f| {
+                            let name = f.function.as_deref().unwrap_or(&addr_info.symbol.name);
+                            self.label_stack(profile, name, stack)
+                        }),
+                        None => self.label_stack(profile, &addr_info.symbol.name, path_stack),
+                    },
+                }
+            }
+            SectionState::Text {
+                unknown_bytes_stack,
+                ..
+            } if range.addr_info.is_none() => *unknown_bytes_stack,
+            SectionState::Text {
+                section_stack,
+                unknown_path_stack,
+                stack_prefix_for_path,
+                ..
+            } => {
+                let path_stack = match range.outer_function_path() {
+                    Some(path) => {
+                        self.path_stack(profile, range, path, *section_stack, stack_prefix_for_path)
+                    }
+                    None => *unknown_path_stack,
+                };
+                let addr_info = range.addr_info.as_ref().unwrap();
+                let generic_label = self.generic_labels.as_mut().and_then(|labels| {
+                    let symbol_name = &addr_info.symbol.name;
+                    labels
+                        .entry(symbol_name.clone())
+                        .or_insert_with(|| generic_frame_label(symbol_name))
+                        .clone()
+                });
+                let path_stack = match generic_label {
+                    Some(label) => self.label_stack(profile, &label, path_stack),
+                    None => path_stack,
+                };
+                self.frames_stack(
+                    profile,
+                    addr_info,
+                    library_handle,
+                    range.relative_address.unwrap(),
+                    path_stack,
+                )
+            }
+        };
+        self.section = Some(section);
+        stack
+    }
+
+    /// Returns the stack for the crate and the path directories of `path`
+    /// on top of `parent`.
+    fn path_stack(
+        &self,
+        profile: &mut Profile,
+        range: &AttributedRange,
+        path: String,
+        parent: StackHandle,
+        stack_prefix_for_path: &mut PathStacks,
+    ) -> StackHandle {
+        let key = (range.crate_info.clone(), path);
+        if let Some(path_stack) = stack_prefix_for_path.get(&key) {
+            return *path_stack;
+        }
+        let mut path_stack = parent;
+        for label in path_frame_labels(key.0.as_ref(), &key.1) {
+            path_stack = self.label_stack(profile, &label, path_stack);
+        }
+        stack_prefix_for_path.insert(key, path_stack);
+        path_stack
+    }
+
+    /// Adds the frames for the symbol and its inline stack on top of
+    /// `path_stack`, from the outer function to the innermost one.
+    fn frames_stack(
+        &self,
+        profile: &mut Profile,
+        addr_info: &AddressInfo,
+        library_handle: LibraryHandle,
+        relative_address: u32,
+        path_stack: StackHandle,
+    ) -> StackHandle {
+        let thread = self.thread;
+        let category = self.category;
+        let symbol = Symbol {
+            address: addr_info.symbol.address,
+            size: addr_info.symbol.size,
+            name: addr_info.symbol.name.clone(),
+        };
+        let native_symbol = profile.handle_for_native_symbol(thread, library_handle, &symbol);
+        let mut s = path_stack;
+        if let Some(frames) = &addr_info.frames {
+            for (inline_depth, f) in frames.iter().rev().enumerate() {
+                let name = f.function.as_deref().unwrap_or(&symbol.name);
+                let name = profile.handle_for_string(name);
+                let file_path = get_special_path(f.file_path.as_ref(), profile);
+                let frame = profile.handle_for_frame_with_address_and_symbol(
+                    thread,
+                    FrameAddress::RelativeAddressFromInstructionPointer(
+                        library_handle,
+                        relative_address,
+                    ),
+                    FrameSymbolInfo {
+                        name: Some(name),
+                        native_symbol,
+                        source_location: SourceLocation {
+                            file_path,
+                            line: f.line_number,
+                            col: None,
+                        },
+                    },
+                    inline_depth as u16,
+                    category,
+                    FrameFlags::empty(),
+                );
+                s = profile.handle_for_stack(thread, frame, Some(s));
+            }
+        } else {
+            let name = profile.handle_for_string(&symbol.name);
+            let frame = profile.handle_for_frame_with_address_and_symbol(
+                thread,
+                FrameAddress::RelativeAddressFromInstructionPointer(
+                    library_handle,
+                    relative_address,
+                ),
+                FrameSymbolInfo {
+                    name: Some(name),
+                    native_symbol,
+                    source_location: SourceLocation {
+                        file_path: None,
+                        line: None,
+                        col: None,
+                    },
+                },
+                0,
+                category,
+                FrameFlags::empty(),
+            );
+            s = profile.handle_for_stack(thread, frame, Some(s));
+        }
+        s
+    }
+}
+
+fn add_sample(
+    profile: &mut Profile,
+    thread: ThreadHandle,
+    file_offset: u64,
+    stack: StackHandle,
+    bytes: u64,
+) {
+    profile.add_sample(
+        thread,
+        Timestamp::from_millis_since_reference(file_offset as f64),
+        Some(stack),
+        CpuDelta::ZERO,
+        i32::try_from(bytes).unwrap(),
+    );
+}
+
+fn get_special_path(
+    file_path: Option<&SourceFilePath>,
+    profile: &mut Profile,
+) -> Option<StringHandle> {
+    let file_path = file_path?;
+    let s = match file_path.mapped_path() {
+        Some(mapped_path) => {
+            let special_path = mapped_path.to_special_path_str();
+            profile.handle_for_string(&special_path)
+        }
+        None => profile.handle_for_string(file_path.raw_path()),
+    };
+    Some(s)
+}
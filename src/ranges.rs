@@ -0,0 +1,350 @@
+use std::io::Write;
+
+use binary_size_profiler::{AttributedRange, RangeSink};
+use serde::Serialize;
+
+use crate::output::OutputSink;
+
+/// One attributed range, as written by [`RangesSink`].
+#[derive(Serialize)]
+struct RangeRecord {
+    file_offset: u64,
+    size: u64,
+    relative_address: Option<u32>,
+    /// The fat archive member, e.g. arm64.
+    member: Option<String>,
+    section: Option<String>,
+    section_kind: Option<String>,
+    symbol: Option<String>,
+    symbol_address: Option<u32>,
+    symbol_size: Option<u32>,
+    #[serde(rename = "crate")]
+    crate_name: Option<String>,
+    crate_version: Option<String>,
+    instantiating_crate: Option<String>,
+    instantiating_crate_version: Option<String>,
+    /// The inline frames, starting with the outer function.
+    frames: Vec<FrameRecord>,
+}
+
+#[derive(Serialize)]
+struct FrameRecord {
+    function: String,
+    file: Option<String>,
+    line: Option<u32>,
+}
+
+/// The columns of a CSV row. CSV has no nesting, so the frames are a JSON
+/// array in a single column.
+#[derive(Serialize)]
+struct CsvRow<'a> {
+    file_offset: u64,
+    size: u64,
+    relative_address: Option<u32>,
+    member: Option<&'a str>,
+    section: Option<&'a str>,
+    section_kind: Option<&'a str>,
+    symbol: Option<&'a str>,
+    symbol_address: Option<u32>,
+    symbol_size: Option<u32>,
+    #[serde(rename = "crate")]
+    crate_name: Option<&'a str>,
+    crate_version: Option<&'a str>,
+    instantiating_crate: Option<&'a str>,
+    instantiating_crate_version: Option<&'a str>,
+    frames: String,
+}
+
+impl RangeRecord {
+    fn new(range: &AttributedRange) -> Self {
+        let addr_info = range.addr_info.as_ref();
+        let frames = match addr_info {
+            Some(addr_info) => addr_info
+                .frames
+                .iter()
+                .flatten()
+                .rev()
+                .map(|f| FrameRecord {
+                    function: f
+                        .function
+                        .clone()
+                        .unwrap_or_else(|| addr_info.symbol.name.clone()),
+                    file: f.file_path.as_ref().map(|p| p.display_path()),
+                    line: f.line_number,
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        RangeRecord {
+            file_offset: range.file_offset,
+            size: range.size,
+            relative_address: range.relative_address,
+            member: range.binary.as_ref().and_then(|b| b.member_name.clone()),
+            section: range.section.as_ref().map(|s| s.name.clone()),
+            section_kind: range.section.as_ref().map(|s| format!("{:?}", s.kind)),
+            symbol: addr_info.map(|a| a.symbol.name.clone()),
+            symbol_address: addr_info.map(|a| a.symbol.address),
+            symbol_size: addr_info.and_then(|a| a.symbol.size),
+            crate_name: range.crate_info.as_ref().map(|c| c.name.clone()),
+            crate_version: range.crate_info.as_ref().and_then(|c| c.version.clone()),
+            instantiating_crate: range.instantiating_crate.as_ref().map(|c| c.name.clone()),
+            instantiating_crate_version: range
+                .instantiating_crate
+                .as_ref()
+                .and_then(|c| c.version.clone()),
+            frames,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangesFormat {
+    JsonLines,
+    Csv,
+}
+
+/// Writes one record per attributed range, in file order, including the
+/// headers and padding which belong to no section. Unlike the profile formats
+/// nothing is aggregated, so the output can be post-processed with jq, pandas
+/// or DuckDB.
+///
+/// The records are written as they arrive, into the writer that the sink was
+/// created with, so they never need to fit in memory. `write_output` only
+/// flushes that writer and returns the first error.
+pub struct RangesSink {
+    writer: RangesWriter,
+    /// The first error, which is returned from `write_output`.
+    error: Option<Box<dyn std::error::Error>>,
+}
+
+enum RangesWriter {
+    JsonLines(Box<dyn Write>),
+    Csv(Box<csv::Writer<Box<dyn Write>>>),
+}
+
+impl RangesSink {
+    pub fn new(format: RangesFormat, w: Box<dyn Write>) -> Self {
+        let writer = match format {
+            RangesFormat::JsonLines => RangesWriter::JsonLines(w),
+            RangesFormat::Csv => RangesWriter::Csv(Box::new(csv::Writer::from_writer(w))),
+        };
+        RangesSink {
+            writer,
+            error: None,
+        }
+    }
+
+    fn write_record(&mut self, record: &RangeRecord) -> Result<(), Box<dyn std::error::Error>> {
+        match &mut self.writer {
+            RangesWriter::JsonLines(w) => {
+                serde_json::to_writer(&mut *w, record)?;
+                w.write_all(b"\n")?;
+            }
+            RangesWriter::Csv(writer) => {
+                writer.serialize(CsvRow {
+                    file_offset: record.file_offset,
+                    size: record.size,
+                    relative_address: record.relative_address,
+                    member: record.member.as_deref(),
+                    section: record.section.as_deref(),
+                    section_kind: record.section_kind.as_deref(),
+                    symbol: record.symbol.as_deref(),
+                    symbol_address: record.symbol_address,
+                    symbol_size: record.symbol_size,
+                    crate_name: record.crate_name.as_deref(),
+                    crate_version: record.crate_version.as_deref(),
+                    instantiating_crate: record.instantiating_crate.as_deref(),
+                    instantiating_crate_version: record.instantiating_crate_version.as_deref(),
+                    frames: serde_json::to_string(&record.frames)?,
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RangeSink for RangesSink {
+    fn range(&mut self, range: &AttributedRange) {
+        // Stop writing after the first error, e.g. a full disk.
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.write_record(&RangeRecord::new(range)) {
+            self.error = Some(e);
+        }
+    }
+}
+
+impl OutputSink for RangesSink {
+    fn write_output(&mut self, _w: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(e) = self.error.take() {
+            return Err(format!("Could not write the ranges: {e}").into());
+        }
+        match &mut self.writer {
+            RangesWriter::JsonLines(w) => w.flush()?,
+            RangesWriter::Csv(writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::Arc;
+
+    use binary_size_profiler::crates::CrateInfo;
+    use binary_size_profiler::wholesym::{AddressInfo, FrameDebugInfo, SourceFilePath, SymbolInfo};
+    use binary_size_profiler::Section;
+    use object::SectionKind;
+
+    use super::*;
+
+    /// A writer whose output the test can read after the sink took it.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn ranges() -> [AttributedRange; 2] {
+        let header = AttributedRange {
+            file_offset: 0,
+            size: 64,
+            binary: None,
+            section: None,
+            relative_address: None,
+            addr_info: None,
+            crate_info: None,
+            instantiating_crate: None,
+        };
+        let frame = |function: &str, path: &str, line| FrameDebugInfo {
+            function: Some(function.to_owned()),
+            file_path: Some(SourceFilePath::new(path.to_owned(), None)),
+            line_number: Some(line),
+        };
+        let serde = CrateInfo {
+            name: "serde".to_owned(),
+            version: Some("1.0.217".to_owned()),
+        };
+        let text = AttributedRange {
+            file_offset: 0x1000,
+            size: 16,
+            binary: None,
+            section: Some(Arc::new(Section {
+                file_offset: 0x1000,
+                svma: 0x1000,
+                size: 0x100,
+                kind: SectionKind::Text,
+                name: ".text".to_owned(),
+                is_compressed: false,
+                uncompressed_size: 0x100,
+            })),
+            relative_address: Some(0x1010),
+            addr_info: Some(AddressInfo {
+                symbol: SymbolInfo {
+                    address: 0x1000,
+                    size: Some(0x40),
+                    name: "serde::de::f".to_owned(),
+                },
+                frames: Some(vec![
+                    frame("serde::de::g", "src/de.rs", 7),
+                    frame("serde::de::f", "src/lib.rs", 3),
+                ]),
+            }),
+            crate_info: Some(serde.clone()),
+            instantiating_crate: Some(serde),
+        };
+        [header, text]
+    }
+
+    fn output(format: RangesFormat) -> String {
+        let buffer = SharedBuffer::default();
+        let mut sink = RangesSink::new(format, Box::new(buffer.clone()));
+        for range in ranges() {
+            sink.range(&range);
+        }
+        sink.write_output(&mut std::io::sink()).unwrap();
+        drop(sink);
+        let output = buffer.0.borrow().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn csv() {
+        let output = output(RangesFormat::Csv);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "file_offset,size,relative_address,member,section,section_kind,symbol,\
+                 symbol_address,symbol_size,crate,crate_version,instantiating_crate,\
+                 instantiating_crate_version,frames",
+                "0,64,,,,,,,,,,,,[]",
+                "4096,16,4112,,.text,Text,serde::de::f,4096,64,serde,1.0.217,serde,1.0.217,\
+                 \"[{\"\"function\"\":\"\"serde::de::f\"\",\"\"file\"\":\"\"src/lib.rs\"\",\
+                 \"\"line\"\":3},{\"\"function\"\":\"\"serde::de::g\"\",\
+                 \"\"file\"\":\"\"src/de.rs\"\",\"\"line\"\":7}]\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn json_lines() {
+        let output = output(RangesFormat::JsonLines);
+        let records: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0],
+            serde_json::json!({
+                "file_offset": 0,
+                "size": 64,
+                "relative_address": null,
+                "member": null,
+                "section": null,
+                "section_kind": null,
+                "symbol": null,
+                "symbol_address": null,
+                "symbol_size": null,
+                "crate": null,
+                "crate_version": null,
+                "instantiating_crate": null,
+                "instantiating_crate_version": null,
+                "frames": [],
+            })
+        );
+        assert_eq!(
+            records[1],
+            serde_json::json!({
+                "file_offset": 0x1000,
+                "size": 16,
+                "relative_address": 0x1010,
+                "member": null,
+                "section": ".text",
+                "section_kind": "Text",
+                "symbol": "serde::de::f",
+                "symbol_address": 0x1000,
+                "symbol_size": 0x40,
+                "crate": "serde",
+                "crate_version": "1.0.217",
+                "instantiating_crate": "serde",
+                "instantiating_crate_version": "1.0.217",
+                "frames": [
+                    {"function": "serde::de::f", "file": "src/lib.rs", "line": 3},
+                    {"function": "serde::de::g", "file": "src/de.rs", "line": 7},
+                ],
+            })
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use crate::analysis::{AttributedRange, Binary, Section};
+
+/// Receives the attributed ranges of a file from [`analyze_binary_into`], in
+/// file order.
+///
+/// For each binary, i.e. once for a thin binary and once per fat archive
+/// member, the sink gets a `begin_binary` call, then `begin_section` before
+/// the ranges of each section, with `padding` calls for the bytes between
+/// sections. `finish` is called after the last range. Problems with the
+/// debug info of a binary are passed to `warning` after its `begin_binary`.
+///
+/// [`analyze_binary_into`]: crate::analyze_binary_into
+pub trait RangeSink {
+    fn begin_binary(&mut self, _binary: &Arc<Binary>) {}
+
+    fn begin_section(&mut self, _binary: &Arc<Binary>, _section: &Arc<Section>) {}
+
+    /// Bytes outside of any section, e.g. headers, padding, or the space
+    /// between fat archive members. These are ordinary ranges by default.
+    fn padding(&mut self, range: &AttributedRange) {
+        self.range(range);
+    }
+
+    fn range(&mut self, range: &AttributedRange);
+
+    /// Debug info which couldn't be read, e.g. a malformed DWARF unit. The
+    /// ranges that it would have described are still there, but with less
+    /// detail. Ignored by default.
+    fn warning(&mut self, _message: &str) {}
+
+    /// `end_file_offset` is the end of the analyzed byte range.
+    fn finish(&mut self, _end_file_offset: u64) {}
+}
+
+/// Passes everything on to multiple sinks.
+impl RangeSink for Vec<&mut dyn RangeSink> {
+    fn begin_binary(&mut self, binary: &Arc<Binary>) {
+        for sink in self {
+            sink.begin_binary(binary);
+        }
+    }
+
+    fn begin_section(&mut self, binary: &Arc<Binary>, section: &Arc<Section>) {
+        for sink in self {
+            sink.begin_section(binary, section);
+        }
+    }
+
+    fn padding(&mut self, range: &AttributedRange) {
+        for sink in self {
+            sink.padding(range);
+        }
+    }
+
+    fn range(&mut self, range: &AttributedRange) {
+        for sink in self {
+            sink.range(range);
+        }
+    }
+
+    fn warning(&mut self, message: &str) {
+        for sink in self {
+            sink.warning(message);
+        }
+    }
+
+    fn finish(&mut self, end_file_offset: u64) {
+        for sink in self {
+            sink.finish(end_file_offset);
+        }
+    }
+}
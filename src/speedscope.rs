@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use binary_size_profiler::{AttributedRange, RangeSink};
+use object::SectionKind;
+use serde::Serialize;
+
+use crate::output::OutputSink;
+use crate::summary::path_frame_labels;
+
+/// Writes a speedscope file with a single sampled profile whose weights are
+/// byte counts. The samples are the attributed ranges in file order, so
+/// speedscope's "Time Order" view shows the layout of the file.
+pub struct SpeedscopeSink {
+    name: String,
+    frames: Vec<Frame>,
+    frame_indexes: HashMap<Frame, usize>,
+    samples: Vec<Vec<usize>>,
+    weights: Vec<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+struct Frame {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<u32>,
+}
+
+impl Frame {
+    fn label(name: &str) -> Self {
+        Frame {
+            name: name.to_owned(),
+            file: None,
+            line: None,
+        }
+    }
+}
+
+impl SpeedscopeSink {
+    pub fn new(name: &str) -> Self {
+        SpeedscopeSink {
+            name: name.to_owned(),
+            frames: Vec::new(),
+            frame_indexes: HashMap::new(),
+            samples: Vec::new(),
+            weights: Vec::new(),
+        }
+    }
+
+    fn frame_index(&mut self, frame: Frame) -> usize {
+        if let Some(index) = self.frame_indexes.get(&frame) {
+            return *index;
+        }
+        let index = self.frames.len();
+        self.frames.push(frame.clone());
+        self.frame_indexes.insert(frame, index);
+        index
+    }
+
+    fn add_sample(&mut self, frames: Vec<Frame>, bytes: u64) {
+        let stack: Vec<usize> = frames.into_iter().map(|f| self.frame_index(f)).collect();
+        if self.samples.last() == Some(&stack) {
+            *self.weights.last_mut().unwrap() += bytes;
+        } else {
+            self.samples.push(stack);
+            self.weights.push(bytes);
+        }
+    }
+}
+
+/// The frames for `range`, matching the stacks in the Firefox profile: the
+/// root, the fat archive member, the section, the section kind for non-text
+/// sections, and then the path directories and the inline frames from the
+/// outer function to the innermost one, or the data symbol.
+fn stack_frames(range: &AttributedRange) -> Vec<Frame> {
+    let mut frames = vec![Frame::label("(root)")];
+    let Some(binary) = &range.binary else {
+        return frames;
+    };
+    frames.extend(binary.member_name.as_deref().map(Frame::label));
+    let Some(section) = &range.section else {
+        return frames;
+    };
+    frames.push(Frame::label(&section.name));
+    let path_frames = |path: String| {
+        path_frame_labels(range.crate_info.as_ref(), &path)
+            .into_iter()
+            .map(|l| Frame::label(&l))
+            .collect::<Vec<_>>()
+    };
+    if section.kind != SectionKind::Text {
+        frames.push(Frame::label(&format!("{:?}", section.kind)));
+        // The symbols of data sections are below their path, if they have one,
+        // or else below their crate.
+        if range.addr_info.is_none() {
+            return frames;
+        }
+        match (range.outer_function_path(), &range.crate_info) {
+            (Some(path), _) => frames.extend(path_frames(path)),
+            (None, Some(crate_info)) => frames.push(Frame::label(&crate_info.to_string())),
+            (None, None) => {}
+        }
+    } else {
+        match range.outer_function_path() {
+            Some(path) => frames.extend(path_frames(path)),
+            None => frames.push(Frame::label("<unknown path>")),
+        }
+    }
+    let Some(addr_info) = &range.addr_info else {
+        frames.push(Frame::label("<unknown bytes>"));
+        return frames;
+    };
+    match &addr_info.frames {
+        Some(inline_frames) => frames.extend(inline_frames.iter().rev().map(|f| {
+            Frame {
+                name: f
+                    .function
+                    .clone()
+                    .unwrap_or_else(|| addr_info.symbol.name.clone()),
+                file: f.file_path.as_ref().map(|p| p.display_path()),
+                line: f.line_number,
+            }
+        })),
+        None => frames.push(Frame::label(&addr_info.symbol.name)),
+    }
+    frames
+}
+
+impl RangeSink for SpeedscopeSink {
+    fn range(&mut self, range: &AttributedRange) {
+        self.add_sample(stack_frames(range), range.size);
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct File<'a> {
+    #[serde(rename = "$schema")]
+    schema: &'static str,
+    name: &'a str,
+    exporter: &'static str,
+    shared: Shared<'a>,
+    profiles: [SampledProfile<'a>; 1],
+}
+
+#[derive(Serialize)]
+struct Shared<'a> {
+    frames: &'a [Frame],
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SampledProfile<'a> {
+    #[serde(rename = "type")]
+    profile_type: &'static str,
+    name: &'a str,
+    unit: &'static str,
+    start_value: u64,
+    end_value: u64,
+    samples: &'a [Vec<usize>],
+    weights: &'a [u64],
+}
+
+impl OutputSink for SpeedscopeSink {
+    fn write_output(&mut self, w: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+        let file = File {
+            schema: "https://www.speedscope.app/file-format-schema.json",
+            name: &self.name,
+            exporter: concat!("binary-size-profiler@", env!("CARGO_PKG_VERSION")),
+            shared: Shared {
+                frames: &self.frames,
+            },
+            profiles: [SampledProfile {
+                profile_type: "sampled",
+                name: &self.name,
+                unit: "bytes",
+                start_value: 0,
+                end_value: self.weights.iter().sum(),
+                samples: &self.samples,
+                weights: &self.weights,
+            }],
+        };
+        serde_json::to_writer(w, &file)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use binary_size_profiler::wholesym::{AddressInfo, SymbolInfo};
+    use binary_size_profiler::{Binary, Section};
+
+    use super::*;
+
+    fn data_range(binary: &Arc<Binary>, size: u64, symbol: &str) -> AttributedRange {
+        AttributedRange {
+            file_offset: 0x2000,
+            size,
+            binary: Some(binary.clone()),
+            section: Some(Arc::new(Section {
+                file_offset: 0x2000,
+                svma: 0x2000,
+                size: 0x100,
+                kind: SectionKind::Data,
+                name: ".data".to_owned(),
+                is_compressed: false,
+                uncompressed_size: 0x100,
+            })),
+            relative_address: None,
+            addr_info: Some(AddressInfo {
+                symbol: SymbolInfo {
+                    address: 0x2000,
+                    size: None,
+                    name: symbol.to_owned(),
+                },
+                frames: None,
+            }),
+            crate_info: None,
+            instantiating_crate: None,
+        }
+    }
+
+    #[test]
+    fn adjacent_samples_are_merged() {
+        let binary = Arc::new(Binary {
+            member_name: None,
+            file_offset: 0,
+            size: 0x3000,
+            relative_address_range: 0..0x3000,
+            lib_info: Default::default(),
+            string_literals: None,
+        });
+        let mut sink = SpeedscopeSink::new("app");
+        sink.range(&data_range(&binary, 16, "A"));
+        sink.range(&data_range(&binary, 8, "A"));
+        sink.range(&data_range(&binary, 4, "B"));
+        sink.range(&data_range(&binary, 2, "A"));
+        let mut output = Vec::new();
+        sink.write_output(&mut output).unwrap();
+        let file: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        let frames: Vec<&str> = file["shared"]["frames"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(frames, ["(root)", ".data", "Data", "A", "B"]);
+        let profile = &file["profiles"][0];
+        assert_eq!(
+            profile["samples"],
+            serde_json::json!([[0, 1, 2, 3], [0, 1, 2, 4], [0, 1, 2, 3]])
+        );
+        assert_eq!(profile["weights"], serde_json::json!([24, 4, 2]));
+        assert_eq!(profile["endValue"], 30);
+    }
+}
@@ -0,0 +1,450 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use binary_size_profiler::crates::CrateMap;
+use binary_size_profiler::{AttributedRange, Binary, RangeSink, Section};
+use rusqlite::{params, Connection, DatabaseName};
+
+use crate::output::OutputSink;
+
+const SCHEMA: &str = "
+CREATE TABLE binaries (
+    id INTEGER PRIMARY KEY,
+    -- The fat archive member, e.g. arm64. NULL for thin binaries.
+    member_name TEXT,
+    file_offset INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    name TEXT,
+    path TEXT,
+    debug_id TEXT,
+    code_id TEXT,
+    arch TEXT
+);
+CREATE TABLE sections (
+    id INTEGER PRIMARY KEY,
+    binary_id INTEGER NOT NULL REFERENCES binaries(id),
+    name TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    -- Relative to the start of the binary.
+    file_offset INTEGER NOT NULL,
+    svma INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    is_compressed INTEGER NOT NULL,
+    uncompressed_size INTEGER NOT NULL
+);
+CREATE TABLE source_files (
+    id INTEGER PRIMARY KEY,
+    path TEXT NOT NULL UNIQUE,
+    -- The Rust crate and its version, where they are known.
+    crate TEXT,
+    crate_version TEXT
+);
+CREATE TABLE symbols (
+    id INTEGER PRIMARY KEY,
+    binary_id INTEGER NOT NULL REFERENCES binaries(id),
+    name TEXT NOT NULL,
+    address INTEGER NOT NULL,
+    size INTEGER
+);
+-- Consecutive bytes which are attributed to the same things. Ranges without
+-- a section are headers or padding.
+CREATE TABLE ranges (
+    id INTEGER PRIMARY KEY,
+    binary_id INTEGER REFERENCES binaries(id),
+    section_id INTEGER REFERENCES sections(id),
+    file_offset INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    relative_address INTEGER,
+    symbol_id INTEGER REFERENCES symbols(id),
+    -- The source file of the outer function.
+    source_file_id INTEGER REFERENCES source_files(id),
+    -- The Rust crate whose compilation unit contains the range, i.e. the
+    -- crate which instantiated generic code.
+    instantiating_crate TEXT,
+    instantiating_crate_version TEXT
+);
+-- The inline stack of each code range. Depth 0 is the outer function.
+CREATE TABLE frames (
+    range_id INTEGER NOT NULL REFERENCES ranges(id),
+    inline_depth INTEGER NOT NULL,
+    function TEXT NOT NULL,
+    source_file_id INTEGER REFERENCES source_files(id),
+    line INTEGER,
+    PRIMARY KEY (range_id, inline_depth)
+);
+";
+
+/// Writes an SQLite database with normalized tables for the binaries,
+/// sections, source files, symbols, ranges and inline frames. The database
+/// is written in place, or built in memory and written out at the end if it
+/// goes to stdout.
+pub struct SqliteSink {
+    conn: Connection,
+    in_memory: bool,
+    crates: CrateMap,
+    binary_id: Option<i64>,
+    section_id: Option<i64>,
+    source_file_ids: HashMap<String, i64>,
+    symbol_ids: HashMap<(i64, u32, String), i64>,
+    /// The first error, which is returned from `write_output`.
+    error: Option<rusqlite::Error>,
+}
+
+impl SqliteSink {
+    /// Creates the database at `path`, replacing any existing file, or in
+    /// memory if `path` is `None`.
+    pub fn new(crates: CrateMap, path: Option<&Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let conn = match path {
+            Some(path) => {
+                match std::fs::remove_file(path) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                        return Err(format!("Could not replace {}: {e}", path.display()).into());
+                    }
+                    _ => {}
+                }
+                Connection::open(path)
+                    .map_err(|e| format!("Could not create {}: {e}", path.display()))?
+            }
+            None => Connection::open_in_memory()?,
+        };
+        conn.execute_batch(SCHEMA)?;
+        // Insert everything in one transaction; committing per row is slow.
+        conn.execute_batch("BEGIN")?;
+        Ok(SqliteSink {
+            conn,
+            in_memory: path.is_none(),
+            crates,
+            binary_id: None,
+            section_id: None,
+            source_file_ids: HashMap::new(),
+            symbol_ids: HashMap::new(),
+            error: None,
+        })
+    }
+
+    fn record(&mut self, result: rusqlite::Result<()>) {
+        if let Err(e) = result {
+            self.error.get_or_insert(e);
+        }
+    }
+
+    fn source_file_id(&mut self, path: &str) -> rusqlite::Result<i64> {
+        if let Some(id) = self.source_file_ids.get(path) {
+            return Ok(*id);
+        }
+        let crate_info = self.crates.crate_for_path(path);
+        let (crate_name, crate_version) = match crate_info {
+            Some(c) => (Some(c.name), c.version),
+            None => (None, None),
+        };
+        self.conn
+            .prepare_cached(
+                "INSERT INTO source_files (path, crate, crate_version) VALUES (?1, ?2, ?3)",
+            )?
+            .execute(params![path, crate_name, crate_version])?;
+        let id = self.conn.last_insert_rowid();
+        self.source_file_ids.insert(path.to_owned(), id);
+        Ok(id)
+    }
+
+    fn symbol_id(&mut self, binary_id: i64, symbol: &SymbolInfo) -> rusqlite::Result<i64> {
+        let key = (binary_id, symbol.address, symbol.name.clone());
+        if let Some(id) = self.symbol_ids.get(&key) {
+            return Ok(*id);
+        }
+        self.conn
+            .prepare_cached(
+                "INSERT INTO symbols (binary_id, name, address, size) VALUES (?1, ?2, ?3, ?4)",
+            )?
+            .execute(params![binary_id, symbol.name, symbol.address, symbol.size])?;
+        let id = self.conn.last_insert_rowid();
+        self.symbol_ids.insert(key, id);
+        Ok(id)
+    }
+
+    fn insert_binary(&mut self, binary: &Binary) -> rusqlite::Result<()> {
+        let lib_info = &binary.lib_info;
+        self.conn
+            .prepare_cached(
+                "INSERT INTO binaries
+                 (member_name, file_offset, size, name, path, debug_id, code_id, arch)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?
+            .execute(params![
+                binary.member_name,
+                binary.file_offset,
+                binary.size,
+                lib_info.name,
+                lib_info.path,
+                lib_info.debug_id.map(|id| id.breakpad().to_string()),
+                lib_info.code_id.as_ref().map(|id| id.to_string()),
+                lib_info.arch,
+            ])?;
+        self.binary_id = Some(self.conn.last_insert_rowid());
+        self.section_id = None;
+        Ok(())
+    }
+
+    fn insert_section(&mut self, section: &Section) -> rusqlite::Result<()> {
+        self.conn
+            .prepare_cached(
+                "INSERT INTO sections
+                 (binary_id, name, kind, file_offset, svma, size, is_compressed,
+                  uncompressed_size)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?
+            .execute(params![
+                self.binary_id,
+                section.name,
+                format!("{:?}", section.kind),
+                section.file_offset,
+                section.svma,
+                section.size,
+                section.is_compressed,
+                section.uncompressed_size,
+            ])?;
+        self.section_id = Some(self.conn.last_insert_rowid());
+        Ok(())
+    }
+
+    fn insert_range(&mut self, range: &AttributedRange) -> rusqlite::Result<()> {
+        // Padding between fat archive members belongs to no binary.
+        let binary_id = range.binary.as_ref().and(self.binary_id);
+        let section_id = range.section.as_ref().and(self.section_id);
+        let symbol_id = match (&range.addr_info, binary_id) {
+            (Some(addr_info), Some(binary_id)) => {
+                let symbol = SymbolInfo {
+                    name: addr_info.symbol.name.clone(),
+                    address: addr_info.symbol.address,
+                    size: addr_info.symbol.size,
+                };
+                Some(self.symbol_id(binary_id, &symbol)?)
+            }
+            _ => None,
+        };
+        let source_file_id = match range.outer_function_path() {
+            Some(path) => Some(self.source_file_id(&path)?),
+            None => None,
+        };
+        self.conn
+            .prepare_cached(
+                "INSERT INTO ranges
+                 (binary_id, section_id, file_offset, size, relative_address, symbol_id,
+                  source_file_id, instantiating_crate, instantiating_crate_version)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?
+            .execute(params![
+                binary_id,
+                section_id,
+                range.file_offset,
+                range.size,
+                range.relative_address,
+                symbol_id,
+                source_file_id,
+                range.instantiating_crate.as_ref().map(|c| &c.name),
+                range
+                    .instantiating_crate
+                    .as_ref()
+                    .and_then(|c| c.version.as_ref()),
+            ])?;
+        let range_id = self.conn.last_insert_rowid();
+
+        let Some(addr_info) = &range.addr_info else {
+            return Ok(());
+        };
+        let Some(frames) = &addr_info.frames else {
+            return Ok(());
+        };
+        for (inline_depth, frame) in frames.iter().rev().enumerate() {
+            let function = frame.function.as_deref().unwrap_or(&addr_info.symbol.name);
+            let source_file_id = match &frame.file_path {
+                Some(path) => Some(self.source_file_id(&path.display_path())?),
+                None => None,
+            };
+            self.conn
+                .prepare_cached(
+                    "INSERT INTO frames (range_id, inline_depth, function, source_file_id, line)
+                     VALUES (?1, ?2, ?3, ?4, ?5)",
+                )?
+                .execute(params![
+                    range_id,
+                    inline_depth,
+                    function,
+                    source_file_id,
+                    frame.line_number,
+                ])?;
+        }
+        Ok(())
+    }
+}
+
+struct SymbolInfo {
+    name: String,
+    address: u32,
+    size: Option<u32>,
+}
+
+impl RangeSink for SqliteSink {
+    fn begin_binary(&mut self, binary: &Arc<Binary>) {
+        let result = self.insert_binary(binary);
+        self.record(result);
+    }
+
+    fn begin_section(&mut self, _binary: &Arc<Binary>, section: &Arc<Section>) {
+        let result = self.insert_section(section);
+        self.record(result);
+    }
+
+    fn range(&mut self, range: &AttributedRange) {
+        let result = self.insert_range(range);
+        self.record(result);
+    }
+}
+
+impl OutputSink for SqliteSink {
+    fn write_output(&mut self, w: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(e) = self.error.take() {
+            return Err(format!("Could not build the SQLite database: {e}").into());
+        }
+        self.conn.execute_batch("COMMIT")?;
+        if self.in_memory {
+            let data = self.conn.serialize(DatabaseName::Main)?;
+            w.write_all(&data)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use binary_size_profiler::wholesym::{AddressInfo, FrameDebugInfo, SourceFilePath, SymbolInfo};
+    use object::SectionKind;
+
+    use super::*;
+
+    #[test]
+    fn schema() {
+        let path = std::env::temp_dir().join(format!(
+            "binary-size-profiler-{}-schema.sqlite",
+            std::process::id()
+        ));
+        // An existing file is replaced.
+        std::fs::write(&path, "not a database").unwrap();
+
+        let binary = Arc::new(Binary {
+            member_name: None,
+            file_offset: 0,
+            size: 0x200,
+            relative_address_range: 0x1000..0x1100,
+            lib_info: Default::default(),
+            string_literals: None,
+        });
+        let section = Arc::new(Section {
+            file_offset: 0x100,
+            svma: 0x1000,
+            size: 0x100,
+            kind: SectionKind::Text,
+            name: ".text".to_owned(),
+            is_compressed: false,
+            uncompressed_size: 0x100,
+        });
+        let frame = |function: &str, path: &str, line| FrameDebugInfo {
+            function: Some(function.to_owned()),
+            file_path: Some(SourceFilePath::new(path.to_owned(), None)),
+            line_number: Some(line),
+        };
+        let range = AttributedRange {
+            file_offset: 0x100,
+            size: 0x10,
+            binary: Some(binary.clone()),
+            section: Some(section.clone()),
+            relative_address: Some(0x1000),
+            addr_info: Some(AddressInfo {
+                symbol: SymbolInfo {
+                    address: 0x1000,
+                    size: Some(0x20),
+                    name: "main".to_owned(),
+                },
+                frames: Some(vec![
+                    frame("inlined", "src/lib.rs", 7),
+                    frame("main", "src/main.rs", 3),
+                ]),
+            }),
+            crate_info: None,
+            instantiating_crate: None,
+        };
+
+        let mut sink = SqliteSink::new(CrateMap::default(), Some(&path)).unwrap();
+        sink.begin_binary(&binary);
+        sink.begin_section(&binary, &section);
+        sink.range(&range);
+        sink.write_output(&mut std::io::sink()).unwrap();
+        drop(sink);
+
+        let conn = Connection::open(&path).unwrap();
+        let tables: Vec<String> = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            tables,
+            [
+                "binaries",
+                "frames",
+                "ranges",
+                "sections",
+                "source_files",
+                "symbols"
+            ]
+        );
+        let frames: Vec<(i64, String, String, i64, String, String)> = conn
+            .prepare(
+                "SELECT frames.inline_depth, frames.function, source_files.path, ranges.size,
+                        symbols.name, sections.name
+                 FROM frames
+                 JOIN ranges ON frames.range_id = ranges.id
+                 JOIN source_files ON frames.source_file_id = source_files.id
+                 JOIN symbols ON ranges.symbol_id = symbols.id
+                 JOIN sections ON ranges.section_id = sections.id
+                 ORDER BY frames.inline_depth",
+            )
+            .unwrap()
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let frame = |depth, function: &str, path: &str| {
+            (
+                depth,
+                function.to_owned(),
+                path.to_owned(),
+                0x10,
+                "main".to_owned(),
+                ".text".to_owned(),
+            )
+        };
+        assert_eq!(
+            frames,
+            [
+                frame(0, "main", "src/main.rs"),
+                frame(1, "inlined", "src/lib.rs")
+            ]
+        );
+        drop(conn);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
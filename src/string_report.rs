@@ -0,0 +1,288 @@
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::sync::Arc;
+
+use binary_size_profiler::strings::{StringLiteral, StringLiterals};
+use binary_size_profiler::{AttributedRange, Binary, RangeSink};
+
+use crate::output::OutputSink;
+use crate::table::format_size;
+
+/// The number of referencing functions listed for each string.
+const REFERENCING_FUNCTIONS: usize = 3;
+
+/// The longest string that is printed in full.
+const MAX_STRING_CHARS: usize = 70;
+
+/// The messages of the panics in core and std, which end up in every binary
+/// that can panic.
+const PANIC_MESSAGE_PREFIXES: [&str; 8] = [
+    "attempt to ",
+    "called `Option::unwrap()`",
+    "called `Result::unwrap()`",
+    "assertion failed",
+    "index out of bounds",
+    "explicit panic",
+    "internal error: entered unreachable code",
+    "capacity overflow",
+];
+
+/// Reports the string literals in the read-only data: the sizes per section,
+/// the largest strings with the functions that refer to them, the strings
+/// which are stored more than once, and the bytes for panic metadata.
+///
+/// The analysis has to run with [`AnalysisOptions::find_strings`] set.
+///
+/// [`AnalysisOptions::find_strings`]: binary_size_profiler::AnalysisOptions::find_strings
+pub struct StringsSink {
+    top: usize,
+    binaries: Vec<BinaryStrings>,
+}
+
+struct BinaryStrings {
+    binary: Arc<Binary>,
+    /// The symbols of the ranges, keyed by their relative address, with the
+    /// end of their last range.
+    symbols: BTreeMap<u32, (u32, String)>,
+}
+
+/// A string and the binary that contains it.
+type BinaryString<'a> = (&'a BinaryStrings, &'a StringLiteral);
+
+impl BinaryStrings {
+    fn literals(&self) -> &StringLiterals {
+        static NONE: StringLiterals = StringLiterals {
+            strings: Vec::new(),
+            locations: Vec::new(),
+        };
+        self.binary.string_literals.as_ref().unwrap_or(&NONE)
+    }
+
+    /// The function or the data symbol at `relative_address`.
+    fn symbol_at(&self, relative_address: u32) -> Option<&str> {
+        let (_, (end, name)) = self.symbols.range(..=relative_address).next_back()?;
+        (relative_address < *end).then_some(name.as_str())
+    }
+
+    fn section_label(&self, string: &StringLiteral) -> String {
+        match &self.binary.member_name {
+            Some(member) => format!("{member} {}", string.section),
+            None => string.section.clone(),
+        }
+    }
+}
+
+impl StringsSink {
+    pub fn new(top: usize) -> Self {
+        StringsSink {
+            top,
+            binaries: Vec::new(),
+        }
+    }
+}
+
+impl RangeSink for StringsSink {
+    fn begin_binary(&mut self, binary: &Arc<Binary>) {
+        self.binaries.push(BinaryStrings {
+            binary: binary.clone(),
+            symbols: BTreeMap::new(),
+        });
+    }
+
+    fn range(&mut self, range: &AttributedRange) {
+        let (Some(binary), Some(addr_info), Some(address)) = (
+            self.binaries.last_mut(),
+            &range.addr_info,
+            range.relative_address,
+        ) else {
+            return;
+        };
+        let end = address + range.size as u32;
+        let symbol = &addr_info.symbol;
+        // Only the ranges which continue the symbol's bytes count, not e.g.
+        // the function's unwind info.
+        match binary.symbols.entry(symbol.address) {
+            Entry::Vacant(entry) if address == symbol.address => {
+                entry.insert((end, symbol.name.clone()));
+            }
+            Entry::Occupied(mut entry) if entry.get().0 == address => entry.get_mut().0 = end,
+            _ => {}
+        }
+    }
+}
+
+impl OutputSink for StringsSink {
+    fn write_output(&mut self, w: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+        let strings: Vec<BinaryString> = self
+            .binaries
+            .iter()
+            .flat_map(|b| b.literals().strings.iter().map(move |s| (b, s)))
+            .collect();
+
+        let mut sections: HashMap<String, (usize, u64)> = HashMap::new();
+        for (binary, string) in &strings {
+            let (count, bytes) = sections.entry(binary.section_label(string)).or_default();
+            *count += 1;
+            *bytes += string.size;
+        }
+        let mut sections: Vec<(String, (usize, u64))> = sections.into_iter().collect();
+        sections.sort_by(|(a, (_, a_bytes)), (b, (_, b_bytes))| {
+            b_bytes.cmp(a_bytes).then_with(|| a.cmp(b))
+        });
+        writeln!(w, "   STRINGS     SIZE   SECTION")?;
+        writeln!(w, " ---------  -------   -------")?;
+        for (section, (count, bytes)) in &sections {
+            writeln!(w, " {count:>9}  {:>7}   {section}", format_size(*bytes))?;
+        }
+        let total: u64 = strings.iter().map(|(_, s)| s.size).sum();
+        writeln!(
+            w,
+            " {:>9}  {:>7}   [Total]",
+            strings.len(),
+            format_size(total)
+        )?;
+
+        writeln!(w)?;
+        writeln!(w, "    SIZE   LARGEST STRINGS / REFERENCED BY")?;
+        writeln!(w, " -------   -------------------------------")?;
+        let mut largest = strings.clone();
+        largest.sort_by(|(_, a), (_, b)| {
+            b.size
+                .cmp(&a.size)
+                .then_with(|| a.relative_address.cmp(&b.relative_address))
+        });
+        for (binary, string) in largest.iter().take(self.top) {
+            writeln!(
+                w,
+                " {:>7}   {} ({})",
+                format_size(string.size),
+                quote(&string.text),
+                binary.section_label(string)
+            )?;
+            writeln!(w, "             {}", referencing_functions(binary, string))?;
+        }
+
+        writeln!(w)?;
+        writeln!(w, "  COPIES   WASTED   DUPLICATE STRINGS / SECTIONS")?;
+        writeln!(w, " -------  -------   ----------------------------")?;
+        // Each member of a fat binary has its own copy, so only strings which
+        // are duplicated within a binary count.
+        let mut copies: HashMap<(Option<&str>, &str), Vec<BinaryString>> = HashMap::new();
+        for (binary, string) in &strings {
+            copies
+                .entry((binary.binary.member_name.as_deref(), string.text.as_str()))
+                .or_default()
+                .push((binary, string));
+        }
+        let mut duplicates: Vec<(u64, &Vec<BinaryString>)> = copies
+            .values()
+            .filter(|copies| copies.len() > 1)
+            .map(|copies| {
+                let bytes: u64 = copies.iter().map(|(_, s)| s.size).sum();
+                let smallest = copies.iter().map(|(_, s)| s.size).min().unwrap_or_default();
+                (bytes - smallest, copies)
+            })
+            .collect();
+        duplicates.sort_by(|(a_wasted, a), (b_wasted, b)| {
+            b_wasted
+                .cmp(a_wasted)
+                .then_with(|| a[0].1.text.cmp(&b[0].1.text))
+        });
+        for (wasted, copies) in duplicates.iter().take(self.top) {
+            let mut sections: Vec<String> =
+                copies.iter().map(|(b, s)| b.section_label(s)).collect();
+            sections.sort();
+            sections.dedup();
+            writeln!(
+                w,
+                " {:>7}  {:>7}   {}",
+                copies.len(),
+                format_size(*wasted),
+                quote(&copies[0].1.text)
+            )?;
+            writeln!(w, "                    {}", sections.join(", "))?;
+        }
+        if duplicates.len() > self.top {
+            let others = &duplicates[self.top..];
+            let wasted = others.iter().map(|(wasted, _)| wasted).sum();
+            writeln!(
+                w,
+                "          {:>7}   [{} Others]",
+                format_size(wasted),
+                others.len()
+            )?;
+        }
+
+        writeln!(w)?;
+        writeln!(w, "   COUNT     SIZE   PANIC METADATA")?;
+        writeln!(w, " -------  -------   --------------")?;
+        let locations: Vec<_> = self
+            .binaries
+            .iter()
+            .flat_map(|b| &b.literals().locations)
+            .collect();
+        let location_bytes = locations.iter().map(|l| l.size).sum();
+        let location_files: HashSet<&str> = locations.iter().map(|l| l.file.as_str()).collect();
+        let file_names: Vec<u64> = strings
+            .iter()
+            .filter(|(_, s)| location_files.contains(s.text.as_str()))
+            .map(|(_, s)| s.size)
+            .collect();
+        let messages: Vec<u64> = strings
+            .iter()
+            .filter(|(_, s)| PANIC_MESSAGE_PREFIXES.iter().any(|p| s.text.starts_with(p)))
+            .map(|(_, s)| s.size)
+            .collect();
+        let rows = [
+            ("core::panic::Location", locations.len(), location_bytes),
+            (
+                "Location file names",
+                file_names.len(),
+                file_names.iter().sum(),
+            ),
+            ("panic messages", messages.len(), messages.iter().sum()),
+        ];
+        for (label, count, bytes) in rows {
+            writeln!(w, " {count:>7}  {:>7}   {label}", format_size(bytes))?;
+        }
+        let total = rows.iter().map(|(_, _, bytes)| bytes).sum();
+        writeln!(w, "          {:>7}   [Total]", format_size(total))?;
+        Ok(())
+    }
+}
+
+/// "foo::bar, baz and 3 more", or "[unknown]" if no reference was found.
+fn referencing_functions(binary: &BinaryStrings, string: &StringLiteral) -> String {
+    let mut functions: Vec<&str> = Vec::new();
+    let mut unknown = 0;
+    for address in &string.referenced_from {
+        match binary.symbol_at(*address) {
+            Some(name) if !functions.contains(&name) => functions.push(name),
+            Some(_) => {}
+            None => unknown += 1,
+        }
+    }
+    if functions.is_empty() {
+        return match unknown {
+            0 => "[unknown]".to_owned(),
+            _ => format!("[{unknown} references outside of any symbol]"),
+        };
+    }
+    let more = functions.len().saturating_sub(REFERENCING_FUNCTIONS);
+    let mut label = functions[..functions.len() - more].join(", ");
+    if more > 0 {
+        label.push_str(&format!(" and {more} more"));
+    }
+    label
+}
+
+/// The string in quotes and with escapes, shortened if it's long.
+fn quote(text: &str) -> String {
+    let char_count = text.chars().count();
+    if char_count <= MAX_STRING_CHARS {
+        return format!("{text:?}");
+    }
+    let prefix: String = text.chars().take(MAX_STRING_CHARS).collect();
+    format!("{prefix:?}... ({char_count} chars)")
+}
@@ -0,0 +1,596 @@
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryInto;
+
+use object::read::{Object, ObjectSection};
+use object::{Architecture, BinaryFormat, File, RelocationFlags, SectionKind};
+use wholesym::samply_symbols::relative_address_base;
+
+/// The minimum length of a string whose end we have to guess, i.e. one
+/// without a length next to the pointer to it. Like `strings(1)`, this keeps
+/// random bytes from passing as text.
+const MIN_GUESSED_STRING_LEN: usize = 4;
+
+/// The maximum length of a `&str` in a (pointer, length) pair.
+const MAX_STR_LEN: u64 = 1 << 20;
+
+/// A string literal in a read-only data section.
+#[derive(Debug, Clone)]
+pub struct StringLiteral {
+    /// Relative to the image base.
+    pub relative_address: u32,
+    /// In bytes, including the NUL terminator of C strings.
+    pub size: u64,
+    pub section: String,
+    /// The string, lossily decoded as UTF-8, without the NUL terminator.
+    pub text: String,
+    /// The relative addresses of the instructions and the pointers which
+    /// refer to the string.
+    pub referenced_from: Vec<u32>,
+}
+
+/// A `core::panic::Location`, i.e. the file, line and column that
+/// `#[track_caller]` functions like `unwrap` report when they panic.
+#[derive(Debug, Clone)]
+pub struct PanicLocation {
+    /// Relative to the image base.
+    pub relative_address: u32,
+    pub size: u64,
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// The string literals of a binary: the NUL-terminated strings in C-string
+/// sections like `__cstring`, and the strings in other read-only data, which
+/// we find through the pointers and the instructions that refer to them.
+/// Rust string literals aren't NUL-terminated and are packed into constant
+/// blobs, so these references are what tells us where they start and end.
+#[derive(Debug, Clone, Default)]
+pub struct StringLiterals {
+    /// Sorted by address.
+    pub strings: Vec<StringLiteral>,
+    pub locations: Vec<PanicLocation>,
+}
+
+/// A section with its bytes, from the binary that is being scanned.
+struct SectionData<'a> {
+    name: String,
+    kind: SectionKind,
+    /// Whether the section only contains NUL-terminated strings. lld merges
+    /// the C strings into `.rodata` and keeps their flags, so the section kind
+    /// isn't enough to tell.
+    is_c_strings: bool,
+    svma: u64,
+    data: &'a [u8],
+}
+
+impl SectionData<'_> {
+    fn contains(&self, svma: u64) -> bool {
+        svma >= self.svma && svma - self.svma < self.data.len() as u64
+    }
+
+    fn bytes_at(&self, svma: u64) -> &[u8] {
+        &self.data[(svma - self.svma) as usize..]
+    }
+
+    /// Whether the section can contain string literals.
+    fn has_strings(&self) -> bool {
+        matches!(
+            self.kind,
+            SectionKind::ReadOnlyString | SectionKind::ReadOnlyData
+        )
+    }
+}
+
+/// The place of a string which we found through a reference.
+#[derive(Default)]
+struct Target {
+    /// The lengths from the (pointer, length) pairs which point here. Not
+    /// every pointer which is followed by a small number is a `&str`.
+    lens: Vec<u64>,
+    /// The SVMAs of the references.
+    referenced_from: Vec<u64>,
+}
+
+impl StringLiterals {
+    /// Scans the sections of `object_file` for string literals and panic
+    /// locations.
+    pub fn find(object_file: &File<'_>) -> Self {
+        let scanner = Scanner::new(object_file);
+        let mut targets: BTreeMap<u64, Target> = BTreeMap::new();
+        let mut locations = Vec::new();
+        for section in &scanner.sections {
+            match section.kind {
+                SectionKind::Text => {
+                    for (from, to) in scanner.code_references(section) {
+                        if scanner.string_section(to).is_some() {
+                            targets.entry(to).or_default().referenced_from.push(from);
+                        }
+                    }
+                }
+                SectionKind::Data
+                | SectionKind::ReadOnlyData
+                | SectionKind::ReadOnlyDataWithRel => {
+                    scanner.scan_pointers(section, &mut targets, &mut locations);
+                }
+                _ => {}
+            }
+        }
+
+        let mut strings = Vec::new();
+        for section in scanner.sections.iter().filter(|s| s.has_strings()) {
+            let end = section.svma + section.data.len() as u64;
+            let section_targets = targets.range(section.svma..end);
+            if section.is_c_strings {
+                strings.extend(c_strings(section));
+            } else {
+                strings.extend(referenced_strings(section, section_targets));
+            }
+        }
+        strings.sort_by_key(|s| s.svma);
+
+        // Attach the references to the strings which contain their target.
+        for (to, target) in targets {
+            let index = strings.partition_point(|s| s.svma <= to);
+            let Some(string) = index.checked_sub(1).map(|i| &mut strings[i]) else {
+                continue;
+            };
+            if to < string.svma + string.size {
+                string.referenced_from.extend(target.referenced_from);
+            }
+        }
+
+        let base_addr = scanner.base_addr;
+        let relative = |svma: u64| svma.saturating_sub(base_addr) as u32;
+        StringLiterals {
+            strings: strings
+                .into_iter()
+                .map(|s| {
+                    let mut referenced_from: Vec<u32> =
+                        s.referenced_from.into_iter().map(relative).collect();
+                    referenced_from.sort_unstable();
+                    referenced_from.dedup();
+                    StringLiteral {
+                        relative_address: relative(s.svma),
+                        size: s.size,
+                        section: s.section,
+                        text: s.text,
+                        referenced_from,
+                    }
+                })
+                .collect(),
+            locations,
+        }
+    }
+}
+
+/// A string while we're scanning, with SVMAs instead of relative addresses.
+struct FoundString {
+    svma: u64,
+    size: u64,
+    section: String,
+    text: String,
+    referenced_from: Vec<u64>,
+}
+
+/// Splits a C-string section at the NUL terminators.
+fn c_strings(section: &SectionData<'_>) -> Vec<FoundString> {
+    let mut strings = Vec::new();
+    let mut start = 0;
+    for (i, byte) in section.data.iter().enumerate() {
+        if *byte != 0 {
+            continue;
+        }
+        if i > start {
+            strings.push(FoundString {
+                svma: section.svma + start as u64,
+                size: (i + 1 - start) as u64,
+                section: section.name.clone(),
+                text: String::from_utf8_lossy(&section.data[start..i]).into_owned(),
+                referenced_from: Vec::new(),
+            });
+        }
+        start = i + 1;
+    }
+    strings
+}
+
+/// Finds the strings at the reference targets in a read-only data section.
+/// A string ends at its length from a (pointer, length) pair, or else at a
+/// NUL terminator or at the next target. Targets inside of a string, e.g.
+/// from a pointer to a substring, don't start a new one.
+fn referenced_strings<'a>(
+    section: &SectionData<'_>,
+    targets: impl Iterator<Item = (&'a u64, &'a Target)>,
+) -> Vec<FoundString> {
+    let section_end = section.svma + section.data.len() as u64;
+    let targets: Vec<(u64, &Target)> = targets.map(|(svma, t)| (*svma, t)).collect();
+    let mut strings = Vec::new();
+    let mut previous_end = section.svma;
+    for (i, (svma, target)) in targets.iter().enumerate() {
+        if *svma < previous_end {
+            continue;
+        }
+        let next_target = targets.get(i + 1).map_or(section_end, |(next, _)| *next);
+        // Take the longest length which doesn't run into the next string.
+        let len = target
+            .lens
+            .iter()
+            .filter(|len| svma + **len <= next_target)
+            .max();
+        let found = match len {
+            Some(len) => exact_string(section, *svma, *len),
+            None => guessed_string(section, *svma, next_target),
+        };
+        if let Some((text, size)) = found {
+            previous_end = svma + size;
+            strings.push(FoundString {
+                svma: *svma,
+                size,
+                section: section.name.clone(),
+                text,
+                referenced_from: Vec::new(),
+            });
+        }
+    }
+    strings
+}
+
+/// The string of a `&str`, which has to be valid UTF-8. Short strings with
+/// non-ASCII characters are usually other data.
+fn exact_string(section: &SectionData<'_>, svma: u64, len: u64) -> Option<(String, u64)> {
+    let bytes = section.bytes_at(svma).get(..len as usize)?;
+    let text = std::str::from_utf8(bytes).ok()?;
+    if !is_text(text) || (!text.is_ascii() && text.len() < MIN_GUESSED_STRING_LEN) {
+        return None;
+    }
+    Some((text.to_owned(), len))
+}
+
+/// The text at `svma`, if it runs up to `end` or up to a NUL terminator.
+/// Anything else is probably not a string.
+fn guessed_string(section: &SectionData<'_>, svma: u64, end: u64) -> Option<(String, u64)> {
+    let bytes = &section.bytes_at(svma)[..(end - svma) as usize];
+    let (bytes, has_nul) = match bytes.iter().position(|b| *b == 0) {
+        Some(nul) => (&bytes[..nul], true),
+        None => (bytes, false),
+    };
+    let text = std::str::from_utf8(bytes).ok()?;
+    if text.len() < MIN_GUESSED_STRING_LEN || !is_text(text) {
+        return None;
+    }
+    let size = text.len() as u64 + u64::from(has_nul);
+    Some((text.to_owned(), size))
+}
+
+fn is_text(text: &str) -> bool {
+    text.chars()
+        .all(|c| !c.is_control() || matches!(c, '\n' | '\r' | '\t'))
+}
+
+/// Reads pointers and instructions in the sections of a binary.
+struct Scanner<'a> {
+    sections: Vec<SectionData<'a>>,
+    base_addr: u64,
+    architecture: Architecture,
+    format: BinaryFormat,
+    is_little_endian: bool,
+    pointer_size: usize,
+    /// The targets of the pointers which the dynamic linker fixes up, by the
+    /// pointer's SVMA. With `RELA` relocations, which x86-64 and arm64 ELF
+    /// binaries use, these pointers are zero in the file. `REL` and the
+    /// packed `RELR` relocations (`.relr.dyn`) keep the target in the pointer
+    /// itself, so those pointers can be read directly and aren't in here.
+    relocated_pointers: HashMap<u64, u64>,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(object_file: &File<'a>) -> Self {
+        let sections = object_file
+            .sections()
+            .filter(|s| s.address() != 0)
+            .filter_map(|s| {
+                let name = s.name().ok()?;
+                let is_c_strings = s.kind() == SectionKind::ReadOnlyString
+                    && (name == "__cstring" || name.starts_with(".rodata.str"));
+                Some(SectionData {
+                    name: name.to_owned(),
+                    kind: s.kind(),
+                    is_c_strings,
+                    svma: s.address(),
+                    data: s.data().ok().filter(|d| !d.is_empty())?,
+                })
+            })
+            .collect();
+        let architecture = object_file.architecture();
+        // The relocation types are numbered per architecture.
+        let relative_type = match architecture {
+            Architecture::X86_64 => Some(object::elf::R_X86_64_RELATIVE),
+            Architecture::Aarch64 => Some(object::elf::R_AARCH64_RELATIVE),
+            Architecture::I386 => Some(object::elf::R_386_RELATIVE),
+            Architecture::Arm => Some(object::elf::R_ARM_RELATIVE),
+            _ => None,
+        };
+        let relocated_pointers = object_file
+            .dynamic_relocations()
+            .into_iter()
+            .flatten()
+            .filter(|(_, relocation)| {
+                !relocation.has_implicit_addend()
+                    && matches!(
+                        (relocation.flags(), relative_type),
+                        (RelocationFlags::Elf { r_type }, Some(relative_type))
+                            if r_type == relative_type
+                    )
+            })
+            .map(|(svma, relocation)| (svma, relocation.addend() as u64))
+            .collect();
+        Scanner {
+            sections,
+            base_addr: relative_address_base(object_file),
+            architecture,
+            format: object_file.format(),
+            is_little_endian: object_file.is_little_endian(),
+            pointer_size: if object_file.is_64() { 8 } else { 4 },
+            relocated_pointers,
+        }
+    }
+
+    fn string_section(&self, svma: u64) -> Option<&SectionData<'a>> {
+        self.sections
+            .iter()
+            .find(|s| s.has_strings() && s.contains(svma))
+    }
+
+    fn read_word(&self, bytes: &[u8], size: usize) -> Option<u64> {
+        let bytes = bytes.get(..size)?;
+        let mut buf = [0; 8];
+        let value = if self.is_little_endian {
+            buf[..size].copy_from_slice(bytes);
+            u64::from_le_bytes(buf)
+        } else {
+            buf[8 - size..].copy_from_slice(bytes);
+            u64::from_be_bytes(buf)
+        };
+        Some(value)
+    }
+
+    /// The string section address that the pointer-sized word at `svma`
+    /// points to, if it points into one.
+    fn pointer_target(&self, svma: u64, raw: u64) -> Option<u64> {
+        if let Some(target) = self.relocated_pointers.get(&svma) {
+            return Some(*target).filter(|t| self.string_section(*t).is_some());
+        }
+        if self.string_section(raw).is_some() {
+            return Some(raw);
+        }
+        if self.format != BinaryFormat::MachO || self.pointer_size != 8 {
+            return None;
+        }
+        // Mach-O chained fixups keep the target in the low bits of the
+        // pointer, either as an address or as an offset from the image base,
+        // with the fixup chain in the high bits. Authenticated arm64e
+        // pointers have a 32 bit offset.
+        let candidates = if raw >> 63 == 1 {
+            [raw & 0xffff_ffff, (raw & 0xffff_ffff) + self.base_addr]
+        } else {
+            [raw & 0xf_ffff_ffff, (raw & 0xf_ffff_ffff) + self.base_addr]
+        };
+        candidates
+            .iter()
+            .copied()
+            .find(|t| self.string_section(*t).is_some())
+    }
+
+    /// Looks for aligned pointers into string sections. A pointer which is
+    /// followed by a plausible length is a `&str`, and a `&str` to a `.rs`
+    /// file which is followed by a line and a column is a panic location.
+    fn scan_pointers(
+        &self,
+        section: &SectionData<'_>,
+        targets: &mut BTreeMap<u64, Target>,
+        locations: &mut Vec<PanicLocation>,
+    ) {
+        let size = self.pointer_size;
+        let mut offset = (size - (section.svma % size as u64) as usize) % size;
+        while let Some(raw) = self.read_word(section.data.get(offset..).unwrap_or_default(), size) {
+            let svma = section.svma + offset as u64;
+            offset += size;
+            let Some(to) = self.pointer_target(svma, raw) else {
+                continue;
+            };
+            let target = targets.entry(to).or_default();
+            target.referenced_from.push(svma);
+
+            let rest = section.data.get(offset..).unwrap_or_default();
+            let Some(len) = self.read_word(rest, size) else {
+                continue;
+            };
+            let string_section = self.string_section(to).unwrap();
+            if len == 0 || len > MAX_STR_LEN || !string_section.contains(to + len - 1) {
+                continue;
+            }
+            let Some((file, _)) = exact_string(string_section, to, len) else {
+                continue;
+            };
+            target.lens.push(len);
+
+            let line = self.read_word(rest.get(size..).unwrap_or_default(), 4);
+            let column = self.read_word(rest.get(size + 4..).unwrap_or_default(), 4);
+            if let (Some(line), Some(column)) = (line, column) {
+                if file.ends_with(".rs") && (1..10_000_000).contains(&line) && column < 100_000 {
+                    locations.push(PanicLocation {
+                        relative_address: svma.saturating_sub(self.base_addr) as u32,
+                        size: 2 * size as u64 + 8,
+                        file,
+                        line: line as u32,
+                        column: column as u32,
+                    });
+                    offset += size + 8;
+                }
+            }
+        }
+    }
+
+    /// The (instruction SVMA, target SVMA) pairs of the instructions which
+    /// compute addresses, which is how code refers to string literals.
+    fn code_references(&self, section: &SectionData<'_>) -> Vec<(u64, u64)> {
+        match self.architecture {
+            Architecture::X86_64 => x86_64_references(section),
+            Architecture::Aarch64 => aarch64_references(section),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Finds `lea reg64, [rip + disp32]`, i.e. REX.W, 0x8D and a ModRM byte
+/// with RIP-relative addressing. We don't disassemble, so this can match
+/// inside of other instructions, but the targets are only used if they point
+/// into a string section.
+fn x86_64_references(section: &SectionData<'_>) -> Vec<(u64, u64)> {
+    let mut references = Vec::new();
+    for (i, instruction) in section.data.windows(7).enumerate() {
+        let is_lea = matches!(instruction[0], 0x48 | 0x4c) && instruction[1] == 0x8d;
+        if !is_lea || instruction[2] & 0xc7 != 0x05 {
+            continue;
+        }
+        let displacement = i32::from_le_bytes(instruction[3..7].try_into().unwrap());
+        let next_instruction = section.svma + i as u64 + 7;
+        let target = next_instruction.wrapping_add(displacement as i64 as u64);
+        references.push((section.svma + i as u64, target));
+    }
+    references
+}
+
+/// The maximum number of instructions between an `adrp` and the `add` which
+/// completes the address.
+const MAX_ADRP_DISTANCE: usize = 4;
+
+/// Finds `adrp xN, page` followed by `add xN, xN, #offset`.
+fn aarch64_references(section: &SectionData<'_>) -> Vec<(u64, u64)> {
+    let instructions: Vec<u32> = section
+        .data
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+        .collect();
+    let mut references = Vec::new();
+    for (i, adrp) in instructions.iter().enumerate() {
+        if adrp & 0x9f00_0000 != 0x9000_0000 {
+            continue;
+        }
+        let register = adrp & 0x1f;
+        let immediate = ((adrp >> 29) & 0x3) | (((adrp >> 5) & 0x7_ffff) << 2);
+        // Sign-extend the 21 bit page offset.
+        let page_offset = (((immediate << 11) as i32) >> 11) as i64 * 4096;
+        let pc = section.svma + 4 * i as u64;
+        let page = (pc & !0xfff).wrapping_add(page_offset as u64);
+        let add = instructions[i + 1..]
+            .iter()
+            .take(MAX_ADRP_DISTANCE)
+            .find(|add| {
+                **add & 0xffc0_0000 == 0x9100_0000
+                    && (**add >> 5) & 0x1f == register
+                    && **add & 0x1f == register
+            });
+        if let Some(add) = add {
+            let offset = u64::from((add >> 10) & 0xfff);
+            references.push((pc, page + offset));
+        }
+    }
+    references
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn section(svma: u64, data: &[u8]) -> SectionData<'_> {
+        SectionData {
+            name: ".rodata".to_owned(),
+            kind: SectionKind::ReadOnlyData,
+            is_c_strings: false,
+            svma,
+            data,
+        }
+    }
+
+    #[test]
+    fn exact_strings() {
+        let data = b"hello world\0\xff\xfe\x01\x02h\xc3\xa9";
+        let section = section(0x100, data);
+        assert_eq!(
+            exact_string(&section, 0x100, 5),
+            Some(("hello".to_owned(), 5))
+        );
+        assert_eq!(
+            exact_string(&section, 0x106, 5),
+            Some(("world".to_owned(), 5))
+        );
+        // Short strings don't need to be NUL-terminated or long.
+        assert_eq!(exact_string(&section, 0x100, 1), Some(("h".to_owned(), 1)));
+        // Invalid UTF-8, control characters, and short non-ASCII strings.
+        assert_eq!(exact_string(&section, 0x10c, 2), None);
+        assert_eq!(exact_string(&section, 0x10b, 3), None);
+        assert_eq!(exact_string(&section, 0x110, 3), None);
+        // Past the end of the section.
+        assert_eq!(exact_string(&section, 0x110, 4), None);
+    }
+
+    #[test]
+    fn guessed_strings() {
+        let data = b"hello world\0abc\0tab\there\x01xyz";
+        let section = section(0x100, data);
+        let end = 0x100 + data.len() as u64;
+        // Up to and including the NUL terminator.
+        assert_eq!(
+            guessed_string(&section, 0x100, end),
+            Some(("hello world".to_owned(), 12))
+        );
+        // Up to `end`, without a terminator.
+        assert_eq!(
+            guessed_string(&section, 0x100, 0x105),
+            Some(("hello".to_owned(), 5))
+        );
+        assert_eq!(
+            guessed_string(&section, 0x110, 0x118),
+            Some(("tab\there".to_owned(), 8))
+        );
+        // Too short, or with control characters.
+        assert_eq!(guessed_string(&section, 0x10c, end), None);
+        assert_eq!(guessed_string(&section, 0x110, end), None);
+    }
+
+    #[test]
+    fn x86_64_leas() {
+        let code = [
+            0x48, 0x8d, 0x05, 0x10, 0x00, 0x00, 0x00, // lea rax, [rip + 0x10]
+            0x48, 0x8d, 0x44, 0x24, 0x08, // lea rax, [rsp + 8]
+            // lea r15, [rip - 0x10], which ends at the end of the section.
+            0x4c, 0x8d, 0x3d, 0xf0, 0xff, 0xff, 0xff,
+        ];
+        let section = section(0x1000, &code);
+        assert_eq!(
+            x86_64_references(&section),
+            [(0x1000, 0x1017), (0x100c, 0x1003)]
+        );
+    }
+
+    #[test]
+    fn aarch64_adrp_add() {
+        let instructions: [u32; 7] = [
+            0xb000_0000, // adrp x0, #0x1000
+            0xd503_201f, // nop
+            0x9104_8c00, // add x0, x0, #0x123
+            0xb000_0001, // adrp x1, #0x1000
+            0x9100_0422, // add x2, x1, #1
+            0xf0ff_ffe3, // adrp x3, #-0x1000
+            0x9100_2063, // add x3, x3, #8
+        ];
+        let code: Vec<u8> = instructions.iter().flat_map(|i| i.to_le_bytes()).collect();
+        let section = section(0x4000, &code);
+        assert_eq!(
+            aarch64_references(&section),
+            [(0x4000, 0x5123), (0x4014, 0x3008)]
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use binary_size_profiler::crates::CrateInfo;
+use binary_size_profiler::{AttributedRange, RangeSink};
+use object::SectionKind;
+
+/// Identifies a group of bytes which are attributed to the same things, i.e.
+/// which end up with the same stack in the profile.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct SummaryKey {
+    /// The fat archive member, if the binary is a fat archive.
+    pub member: Option<String>,
+    /// `None` for bytes outside of any section, e.g. headers and padding.
+    pub section: Option<String>,
+    pub section_kind: Option<SectionKind>,
+    /// The source file of the outer function.
+    pub path: Option<String>,
+    /// The Rust crate of `path`.
+    pub crate_info: Option<CrateInfo>,
+    /// The Rust crate which instantiated the code.
+    pub instantiating_crate: Option<CrateInfo>,
+    pub symbol: Option<String>,
+    /// Function names from the outer function to the innermost inlined
+    /// function. Empty if there was no debug info for these bytes.
+    pub frames: Vec<String>,
+}
+
+impl SummaryKey {
+    pub fn for_range(range: &AttributedRange) -> Self {
+        let frames = match &range.addr_info {
+            Some(addr_info) => match &addr_info.frames {
+                Some(frames) => frames
+                    .iter()
+                    .rev()
+                    .map(|f| {
+                        f.function
+                            .clone()
+                            .unwrap_or_else(|| addr_info.symbol.name.clone())
+                    })
+                    .collect(),
+                None => vec![addr_info.symbol.name.clone()],
+            },
+            None => Vec::new(),
+        };
+        SummaryKey {
+            member: range.binary.as_ref().and_then(|b| b.member_name.clone()),
+            section: range.section.as_ref().map(|s| s.name.clone()),
+            section_kind: range.section.as_ref().map(|s| s.kind),
+            path: range.outer_function_path(),
+            crate_info: range.crate_info.clone(),
+            instantiating_crate: range.instantiating_crate.clone(),
+            symbol: range.addr_info.as_ref().map(|a| a.symbol.name.clone()),
+            frames,
+        }
+    }
+
+    /// The labels of the frames below the root frame for these bytes in the
+    /// profile, matching the stacks that `ProfileSink` builds.
+    pub fn stack_labels(&self) -> Vec<String> {
+        let mut labels = Vec::new();
+        labels.extend(self.member.clone());
+        let Some(section) = &self.section else {
+            return labels;
+        };
+        labels.push(section.clone());
+        match self.section_kind {
+            Some(SectionKind::Text) => match &self.path {
+                Some(path) => labels.extend(path_frame_labels(self.crate_info.as_ref(), path)),
+                None => labels.push("<unknown path>".to_owned()),
+            },
+            // The symbols of data sections are below the section kind, and
+            // the bytes between them are attributed to the kind itself.
+            Some(kind) => {
+                labels.push(format!("{kind:?}"));
+                if self.frames.is_empty() {
+                    return labels;
+                }
+                match (&self.path, &self.crate_info) {
+                    (Some(path), _) => {
+                        labels.extend(path_frame_labels(self.crate_info.as_ref(), path))
+                    }
+                    (None, Some(crate_info)) => labels.push(crate_info.to_string()),
+                    (None, None) => {}
+                }
+            }
+            None => return labels,
+        }
+        if self.frames.is_empty() {
+            labels.push("<unknown bytes>".to_owned());
+        } else {
+            labels.extend(self.frames.iter().cloned());
+        }
+        labels
+    }
+
+    /// The outer function, or the symbol if we have no inline info.
+    pub fn outer_function(&self) -> Option<&str> {
+        self.frames
+            .first()
+            .or(self.symbol.as_ref())
+            .map(String::as_str)
+    }
+
+    /// The innermost inlined function, or the symbol if we have no inline info.
+    pub fn function(&self) -> Option<&str> {
+        self.frames
+            .last()
+            .or(self.symbol.as_ref())
+            .map(String::as_str)
+    }
+}
+
+/// The number of bytes per [`SummaryKey`], for the analyses which need to
+/// look at the numbers rather than at the profile.
+#[derive(Debug, Clone, Default)]
+pub struct SizeSummary {
+    pub sizes: HashMap<SummaryKey, u64>,
+}
+
+impl SizeSummary {
+    pub fn add(&mut self, key: SummaryKey, bytes: u64) {
+        *self.sizes.entry(key).or_default() += bytes;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.sizes.values().sum()
+    }
+
+    /// Sums up the sizes by the group that `group_fn` returns for each key.
+    pub fn sizes_by<F>(&self, group_fn: F) -> HashMap<String, u64>
+    where
+        F: Fn(&SummaryKey) -> String,
+    {
+        let mut sizes = HashMap::new();
+        for (key, bytes) in &self.sizes {
+            *sizes.entry(group_fn(key)).or_default() += bytes;
+        }
+        sizes
+    }
+}
+
+impl RangeSink for SizeSummary {
+    fn range(&mut self, range: &AttributedRange) {
+        self.add(SummaryKey::for_range(range), range.size);
+    }
+}
+
+/// Returns a label for the crate, if it is known, followed by one label per
+/// path component, each containing the path up to and including that
+/// component, e.g. "serde v1.0.217", "/src", "/src/de.rs".
+pub fn path_frame_labels(crate_info: Option<&CrateInfo>, path: &str) -> Vec<String> {
+    let path = path.trim_start_matches("C:\\b\\s\\w\\ir\\cache\\builder\\");
+    let mut accum_path = String::new();
+    let path_labels = path.split(['/', '\\']).map(|p| {
+        accum_path.push('/');
+        accum_path.push_str(p);
+        accum_path.clone()
+    });
+    crate_info
+        .map(|c| c.to_string())
+        .into_iter()
+        .chain(path_labels)
+        .collect()
+}
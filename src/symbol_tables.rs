@@ -0,0 +1,305 @@
+use std::mem;
+
+use object::read::elf::{ElfFile, FileHeader, SectionHeader, Sym};
+use object::read::macho::{MachHeader, MachOFile, Nlist};
+use object::read::{Object, ObjectSection};
+use object::{File, SectionKind};
+
+use crate::analysis::Section;
+use crate::demangle::demangle;
+
+/// The entries of the symbol tables and the names in their string tables,
+/// each with the symbol that it belongs to: `.symtab` and `.dynsym` with
+/// `.strtab` and `.dynstr` for ELF, and the `LC_SYMTAB` symbol and string
+/// tables in the `__LINKEDIT` segment for Mach-O.
+///
+/// This is what stripping removes, apart from `.dynsym` and `.dynstr`, and
+/// mangled Rust names are long, so it can be a large part of the binary.
+#[derive(Default)]
+pub struct SymbolTables {
+    /// The Mach-O symbol and string tables, which aren't in a section.
+    pub linkedit_sections: Vec<Section>,
+    /// Sorted by file offset.
+    entries: Vec<TableEntry>,
+    symbols: Vec<TableSymbol>,
+}
+
+/// A symbol table entry, or a symbol name in a string table.
+struct TableEntry {
+    /// Relative to the start of the binary.
+    file_offset: u64,
+    size: u64,
+    /// Index into `symbols`.
+    symbol: usize,
+}
+
+pub struct TableSymbol {
+    /// The demangled name.
+    pub name: String,
+    /// Relative to the image base. `None` for undefined symbols.
+    pub address: Option<u64>,
+    /// Whether the address is in a text section.
+    pub is_function: bool,
+}
+
+impl SymbolTables {
+    /// Reads the symbol tables of `object_file`. Only ELF and Mach-O are
+    /// supported; PE binaries keep their symbols in the PDB.
+    pub fn compute(object_file: &File<'_>, base_addr: u64) -> Self {
+        let text_ranges: Vec<(u64, u64)> = object_file
+            .sections()
+            .filter(|s| s.kind() == SectionKind::Text)
+            .map(|s| (s.address(), s.address() + s.size()))
+            .collect();
+        let mut tables = SymbolTables::default();
+        let mut reader = TableReader {
+            tables: &mut tables,
+            base_addr,
+            text_ranges,
+        };
+        match object_file {
+            File::Elf32(file) => reader.elf(file),
+            File::Elf64(file) => reader.elf(file),
+            File::MachO32(file) => reader.macho(file),
+            File::MachO64(file) => reader.macho(file),
+            _ => None,
+        };
+        tables.entries.sort_by_key(|e| e.file_offset);
+        tables
+    }
+
+    /// The entries and names which start in `start..end`, a range of file
+    /// offsets, sorted by file offset. A name can start inside of the
+    /// preceding one, if the linker merged it with a name that ends with it.
+    pub fn in_range(&self, start: u64, end: u64) -> impl Iterator<Item = (u64, u64, &TableSymbol)> {
+        let first = self.entries.partition_point(|e| e.file_offset < start);
+        let last = self.entries.partition_point(|e| e.file_offset < end);
+        self.entries[first..last]
+            .iter()
+            .map(move |e| (e.file_offset, e.size, &self.symbols[e.symbol]))
+    }
+
+    /// The relative addresses of the symbols which are functions.
+    pub fn functions(&self) -> impl Iterator<Item = u64> + '_ {
+        self.symbols
+            .iter()
+            .filter(|s| s.is_function)
+            .filter_map(|s| s.address)
+    }
+}
+
+struct TableReader<'a> {
+    tables: &'a mut SymbolTables,
+    base_addr: u64,
+    /// The SVMA ranges of the text sections.
+    text_ranges: Vec<(u64, u64)>,
+}
+
+impl TableReader<'_> {
+    /// Adds a symbol with its entry in the symbol table, and its name in the
+    /// string table, which is NUL-terminated.
+    fn add_symbol(
+        &mut self,
+        name: &[u8],
+        svma: Option<u64>,
+        (entry_offset, entry_size): (u64, u64),
+        name_offset: u64,
+    ) {
+        let index = self.tables.symbols.len();
+        self.tables.symbols.push(TableSymbol {
+            name: demangle(&String::from_utf8_lossy(name)),
+            address: svma.and_then(|svma| svma.checked_sub(self.base_addr)),
+            is_function: svma.is_some_and(|svma| {
+                self.text_ranges
+                    .iter()
+                    .any(|(start, end)| (*start..*end).contains(&svma))
+            }),
+        });
+        self.tables.entries.push(TableEntry {
+            file_offset: entry_offset,
+            size: entry_size,
+            symbol: index,
+        });
+        self.tables.entries.push(TableEntry {
+            file_offset: name_offset,
+            size: name.len() as u64 + 1,
+            symbol: index,
+        });
+    }
+
+    fn elf<Elf: FileHeader>(&mut self, file: &ElfFile<'_, Elf>) -> Option<()> {
+        let endian = file.endian();
+        let sections = file.elf_section_table();
+        for table in [file.elf_symbol_table(), file.elf_dynamic_symbol_table()] {
+            if table.is_empty() {
+                continue;
+            }
+            let (Ok(symtab), Ok(strtab)) = (
+                sections.section(table.section()),
+                sections.section(table.string_section()),
+            ) else {
+                continue;
+            };
+            let symtab_offset: u64 = symtab.sh_offset(endian).into();
+            let strtab_offset: u64 = strtab.sh_offset(endian).into();
+            let entry_size = mem::size_of::<Elf::Sym>() as u64;
+            for (index, symbol) in table.enumerate() {
+                // The null symbol, and section and file symbols which refer
+                // to their section's name.
+                let Ok(name) = table.symbol_name(endian, symbol) else {
+                    continue;
+                };
+                if name.is_empty() {
+                    continue;
+                }
+                let svma = (!symbol.is_undefined(endian)).then(|| symbol.st_value(endian).into());
+                self.add_symbol(
+                    name,
+                    svma,
+                    (symtab_offset + index.0 as u64 * entry_size, entry_size),
+                    strtab_offset + u64::from(symbol.st_name(endian)),
+                );
+            }
+        }
+        Some(())
+    }
+
+    fn macho<Mach: MachHeader>(&mut self, file: &MachOFile<'_, Mach>) -> Option<()> {
+        let endian = file.endian();
+        let mut commands = file.macho_load_commands().ok()?;
+        let mut symtab = None;
+        while let Some(command) = commands.next().ok()? {
+            if let Some(command) = command.symtab().ok()? {
+                symtab = Some(command);
+            }
+        }
+        let symtab = symtab?;
+        let symbols_offset = u64::from(symtab.symoff.get(endian));
+        let strings_offset = u64::from(symtab.stroff.get(endian));
+        let entry_size = mem::size_of::<Mach::Nlist>() as u64;
+        let symbols_size = u64::from(symtab.nsyms.get(endian)) * entry_size;
+        let strings_size = u64::from(symtab.strsize.get(endian));
+        for (name, file_offset, size) in [
+            ("__LINKEDIT symbol table", symbols_offset, symbols_size),
+            ("__LINKEDIT string table", strings_offset, strings_size),
+        ] {
+            if size == 0 {
+                continue;
+            }
+            self.tables.linkedit_sections.push(Section {
+                file_offset,
+                svma: 0,
+                size,
+                kind: SectionKind::Metadata,
+                name: name.to_owned(),
+                is_compressed: false,
+                uncompressed_size: size,
+            });
+        }
+
+        let table = file.macho_symbol_table();
+        for (index, nlist) in table.iter().enumerate() {
+            let Ok(name) = nlist.name(endian, table.strings()) else {
+                continue;
+            };
+            if name.is_empty() {
+                continue;
+            }
+            let svma = (!nlist.is_undefined()).then(|| nlist.n_value(endian).into());
+            self.add_symbol(
+                name,
+                svma,
+                (symbols_offset + index as u64 * entry_size, entry_size),
+                strings_offset + u64::from(nlist.n_strx(endian)),
+            );
+        }
+        Some(())
+    }
+}
+
+/// The crate or namespace of a demangled name, e.g. `serde` for
+/// `serde::de::Visitor::visit_str` and `alloc` for
+/// `<alloc::vec::Vec<u8> as core::fmt::Debug>::fmt`. `None` for names
+/// without a namespace, like C functions.
+pub fn namespace(name: &str) -> Option<&str> {
+    let name = name.trim_start_matches('<');
+    let (namespace, _) = name.split_once("::")?;
+    let is_identifier = !namespace.is_empty()
+        && namespace
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '$');
+    is_identifier.then_some(namespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rust_namespaces() {
+        assert_eq!(namespace("serde::de::Visitor::visit_str"), Some("serde"));
+        assert_eq!(
+            namespace("<alloc::vec::Vec<u8> as core::fmt::Debug>::fmt"),
+            Some("alloc")
+        );
+        assert_eq!(
+            namespace("<<core::str::Chars as core::iter::Iterator>::Item as Foo>::f"),
+            Some("core")
+        );
+        assert_eq!(
+            namespace("_$LT$core..str..Utf8Error$u20$as$u20$core..fmt..Debug$GT$::fmt"),
+            None
+        );
+    }
+
+    #[test]
+    fn cpp_namespaces() {
+        assert_eq!(namespace("std::vector<int>::push_back"), Some("std"));
+        assert_eq!(namespace("(anonymous namespace)::helper"), None);
+    }
+
+    #[test]
+    fn names_without_namespace() {
+        assert_eq!(namespace("main"), None);
+        assert_eq!(namespace("::f"), None);
+        assert_eq!(namespace("<T>::f"), None);
+    }
+
+    #[test]
+    fn elf_entries_and_names() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/inline");
+        let data = std::fs::read(path).unwrap();
+        let object_file = File::parse(&data[..]).unwrap();
+        let tables = SymbolTables::compute(&object_file, 0);
+        let at = |file_offset: u64| -> Vec<(u64, &str)> {
+            tables
+                .in_range(file_offset, file_offset + 1)
+                .map(|(_, size, symbol)| (size, symbol.name.as_str()))
+                .collect()
+        };
+
+        // .symtab at 0x3820, without the null symbol and the unnamed file
+        // symbol, and .strtab at 0x3b98.
+        assert_eq!(tables.in_range(0x3820, 0x3820 + 0x378).count(), 35);
+        assert_eq!(at(0x3820 + 24 * 0x18), [(0x18, "checksum")]);
+        assert_eq!(at(0x3b98 + 0x149), [(9, "checksum")]);
+        // The linker merged these names with the names that end with them.
+        assert_eq!(at(0x3b98 + 0x27), [(21, "deregister_tm_clones")]);
+        assert_eq!(at(0x3b98 + 0x29), [(19, "register_tm_clones")]);
+        assert_eq!(at(0x3b98 + 0x152), [(13, "__data_start")]);
+        assert_eq!(at(0x3b98 + 0x154), [(11, "data_start")]);
+
+        // .dynsym at 0x3c8 and .dynstr at 0x470.
+        assert_eq!(tables.in_range(0x3c8, 0x3c8 + 0xa8).count(), 6);
+        assert_eq!(at(0x3c8 + 3 * 0x18), [(0x18, "printf")]);
+        assert_eq!(at(0x470 + 0x22), [(7, "printf")]);
+
+        let main = tables
+            .in_range(0x3b98 + 0x19b, 0x3b98 + 0x19c)
+            .map(|(_, _, symbol)| symbol)
+            .next()
+            .unwrap();
+        assert_eq!(main.address, Some(0x1050));
+        assert!(main.is_function);
+    }
+}
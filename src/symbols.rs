@@ -0,0 +1,358 @@
+use std::path::{Path, PathBuf};
+
+use object::read::macho::{FatArch, MachOFatFile32};
+use object::read::Object;
+use object::{File, FileKind};
+use serde::Deserialize;
+use wholesym::{LibraryInfo, MultiArchDisambiguator, SymbolManager, SymbolManagerConfig};
+
+pub const DEFAULT_SYMBOL_SERVER: &str = "https://symbols.mozilla.org/try/";
+pub const DEFAULT_SYMBOL_CACHE: &str = "./breakpad-symbol-cache/";
+pub const DEFAULT_SYMINDEX_CACHE: &str = "./breakpad-symindex-cache/";
+
+/// Where to look for debug info. Can be read from a TOML config file and
+/// then overridden by command-line flags.
+///
+/// ```toml
+/// symbol-server = "https://symbols.mozilla.org/try/"
+/// symbol-cache = "/var/cache/breakpad-symbols"
+/// symbol-dirs = ["/opt/symbols"]
+/// debuginfod-mirrors = ["/srv/debuginfod"]
+/// debug-file = "/opt/symbols/firefox.debug"
+/// offline = false
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SymbolConfig {
+    /// Breakpad symbol server URL. Defaults to [`DEFAULT_SYMBOL_SERVER`].
+    pub symbol_server: Option<String>,
+    /// Cache directory for symbol server downloads.
+    pub symbol_cache: Option<PathBuf>,
+    /// Cache directory for breakpad symindex files.
+    pub symindex_cache: Option<PathBuf>,
+    /// Local directories with debug files, either in a flat layout or in the
+    /// breakpad `<debug_name>/<debug_id>/<name>.sym` layout.
+    pub symbol_dirs: Vec<PathBuf>,
+    /// Local directories laid out like a debuginfod server, i.e. with debug
+    /// files at `<dir>/buildid/<build-id>/debuginfo`.
+    pub debuginfod_mirrors: Vec<PathBuf>,
+    /// A debug file to use instead of searching for one, e.g. a `.debug`,
+    /// `.dSYM` or `.pdb` file.
+    pub debug_file: Option<PathBuf>,
+    /// Never make network requests. Can't be combined with `symbol_server`.
+    pub offline: bool,
+}
+
+impl SymbolConfig {
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let s = std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read config file {}: {e}", path.display()))?;
+        let config: SymbolConfig = toml::from_str(&s)
+            .map_err(|e| format!("Invalid config file {}: {e}", path.display()))?;
+        if config.offline && config.symbol_server.is_some() {
+            return Err(format!(
+                "Invalid config file {}: symbol-server can't be used with offline = true",
+                path.display()
+            )
+            .into());
+        }
+        Ok(config)
+    }
+
+    pub fn symbol_manager_config(&self) -> SymbolManagerConfig {
+        let symindex_cache = self
+            .symindex_cache
+            .clone()
+            .unwrap_or_else(|| DEFAULT_SYMINDEX_CACHE.into());
+        let mut config = SymbolManagerConfig::default().breakpad_symindex_cache_dir(symindex_cache);
+        for dir in &self.symbol_dirs {
+            config = config
+                .extra_symbols_directory(dir.as_path())
+                .breakpad_symbols_dir(dir.as_path());
+        }
+        let symbol_cache = self
+            .symbol_cache
+            .clone()
+            .unwrap_or_else(|| DEFAULT_SYMBOL_CACHE.into());
+        if self.offline {
+            // The symbols which were downloaded before are still used.
+            // _NT_SYMBOL_PATH can contain symbol server URLs, so it's ignored.
+            return config
+                .breakpad_symbols_dir(symbol_cache)
+                .respect_nt_symbol_path(false)
+                .use_debuginfod(false);
+        }
+        let symbol_server = self
+            .symbol_server
+            .as_deref()
+            .unwrap_or(DEFAULT_SYMBOL_SERVER);
+        config
+            .respect_nt_symbol_path(true)
+            .breakpad_symbols_server(symbol_server, symbol_cache)
+    }
+
+    /// Returns the file from which debug info for `object_file` should be
+    /// loaded, if it's not the binary itself or a file that wholesym finds on
+    /// its own.
+    pub fn debug_file_for_binary(&self, object_file: &File<'_>) -> Option<PathBuf> {
+        if let Some(debug_file) = &self.debug_file {
+            return Some(resolve_dsym_bundle(debug_file));
+        }
+        let build_id = object_file.build_id().ok()??;
+        let build_id: String = build_id.iter().map(|b| format!("{b:02x}")).collect();
+        self.debuginfod_mirrors
+            .iter()
+            .map(|dir| dir.join("buildid").join(&build_id).join("debuginfo"))
+            .find(|path| path.is_file())
+    }
+
+    /// Returns the PDB or breakpad `.sym` file with the debug info of the
+    /// binary described by `lib_info`, if there's one on disk: a `.pdb`
+    /// `debug_file`, a file in the breakpad layout of the symbol directories
+    /// or of the symbol cache, where the downloads from the symbol server
+    /// go, or the PDB path from the binary's CodeView record.
+    pub fn symbol_file_for_binary(&self, lib_info: &LibraryInfo) -> Option<PathBuf> {
+        if let Some(debug_file) = &self.debug_file {
+            let is_pdb = debug_file.extension().is_some_and(|e| e == "pdb");
+            return is_pdb.then(|| debug_file.clone());
+        }
+        let debug_name = lib_info.debug_name.as_deref()?;
+        let debug_id = lib_info.debug_id?.breakpad().to_string();
+        let sym_name = format!("{}.sym", debug_name.trim_end_matches(".pdb"));
+        let symbol_cache = self
+            .symbol_cache
+            .clone()
+            .unwrap_or_else(|| DEFAULT_SYMBOL_CACHE.into());
+        let layout_dir = |dir: &Path| dir.join(debug_name).join(&debug_id);
+        self.symbol_dirs
+            .iter()
+            .chain(std::iter::once(&symbol_cache))
+            .flat_map(|dir| {
+                [
+                    layout_dir(dir).join(&sym_name),
+                    layout_dir(dir).join(debug_name),
+                    dir.join(debug_name),
+                ]
+            })
+            .chain(lib_info.debug_path.as_deref().map(PathBuf::from))
+            .find(|path| is_symbol_file(path) && path.is_file())
+    }
+
+    /// Loads the symbol map for the binary at `binary_path`. A `.pdb`
+    /// `debug_file` is loaded through the binary's [`LibraryInfo`], whose
+    /// debug ID it has to match. Breakpad `.sym` files are only found in the
+    /// symbol directories, so they are rejected as the `debug_file`.
+    pub async fn load_symbol_map(
+        &self,
+        symbol_manager: &SymbolManager,
+        binary_path: &Path,
+        object_file: &File<'_>,
+        disambiguator: Option<MultiArchDisambiguator>,
+    ) -> Result<wholesym::SymbolMap, Box<dyn std::error::Error>> {
+        let debug_file = self.debug_file_for_binary(object_file);
+        let extension = debug_file
+            .as_deref()
+            .and_then(|path| path.extension())
+            .and_then(|e| e.to_str());
+        match (&debug_file, extension) {
+            (Some(path), Some("sym")) => Err(format!(
+                "The debug file {} is a breakpad symbol file. Put it into a symbol \
+                 directory instead, at <dir>/<debug name>/<debug ID>/<name>.sym.",
+                path.display()
+            )
+            .into()),
+            (Some(path), Some("pdb")) => {
+                let mut lib_info =
+                    SymbolManager::library_info_for_binary_at_path(binary_path, disambiguator)
+                        .await?;
+                lib_info.debug_path = Some(path.to_string_lossy().into_owned());
+                Ok(symbol_manager.load_symbol_map(&lib_info).await?)
+            }
+            _ => {
+                let path = debug_file.as_deref().unwrap_or(binary_path);
+                Ok(symbol_manager
+                    .load_symbol_map_for_binary_at_path(path, disambiguator)
+                    .await?)
+            }
+        }
+    }
+}
+
+fn is_symbol_file(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("pdb" | "sym")
+    )
+}
+
+/// `foo.dSYM` is a directory; the DWARF lives in `foo.dSYM/Contents/Resources/DWARF/foo`.
+fn resolve_dsym_bundle(path: &Path) -> PathBuf {
+    if !path.is_dir() {
+        return path.to_owned();
+    }
+    let dwarf_dir = path.join("Contents").join("Resources").join("DWARF");
+    let Ok(entries) = std::fs::read_dir(&dwarf_dir) else {
+        return path.to_owned();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|p| p.is_file())
+        .unwrap_or_else(|| path.to_owned())
+}
+
+/// Parses a separate debug file. If it's a fat archive, e.g. from a
+/// universal `.dSYM`, picks the member whose UUID matches `object_file`.
+pub fn parse_debug_file<'data>(data: &'data [u8], object_file: &File<'_>) -> Option<File<'data>> {
+    if FileKind::parse(data).ok()? != FileKind::MachOFat32 {
+        return File::parse(data).ok();
+    }
+    let uuid = object_file.mach_uuid().ok()??;
+    MachOFatFile32::parse(data)
+        .ok()?
+        .arches()
+        .iter()
+        .filter_map(|member| File::parse(member.data(data).ok()?).ok())
+        .find(|member| member.mach_uuid().ok().flatten() == Some(uuid))
+}
+
+#[cfg(test)]
+mod tests {
+    use wholesym::debugid::DebugId;
+
+    use super::*;
+
+    /// An empty directory which is unique to the test.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "binary-size-profiler-{}-{name}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_file(path: &Path, contents: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn config_file() {
+        let dir = temp_dir("config-file");
+        let path = dir.join("symbols.toml");
+        write_file(
+            &path,
+            r#"
+            symbol-server = "https://symbols.example.com/"
+            symbol-dirs = ["/opt/symbols", "/srv/symbols"]
+            debug-file = "/opt/symbols/firefox.debug"
+            "#,
+        );
+        let config = SymbolConfig::from_file(&path).unwrap();
+        assert_eq!(
+            config.symbol_server.as_deref(),
+            Some("https://symbols.example.com/")
+        );
+        assert_eq!(
+            config.symbol_dirs,
+            [PathBuf::from("/opt/symbols"), PathBuf::from("/srv/symbols")]
+        );
+        assert_eq!(
+            config.debug_file,
+            Some(PathBuf::from("/opt/symbols/firefox.debug"))
+        );
+        assert_eq!(config.symbol_cache, None);
+        assert!(!config.offline);
+
+        write_file(&path, "symbol-directory = \"/opt/symbols\"");
+        let error = SymbolConfig::from_file(&path).unwrap_err().to_string();
+        assert!(error.contains("unknown field"), "{}", error);
+
+        write_file(
+            &path,
+            "offline = true\nsymbol-server = \"https://symbols.example.com/\"",
+        );
+        let error = SymbolConfig::from_file(&path).unwrap_err().to_string();
+        assert!(error.contains("offline"), "{}", error);
+
+        let error = SymbolConfig::from_file(&dir.join("missing.toml")).unwrap_err();
+        assert!(error.to_string().starts_with("Could not read config file"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn debug_file_for_binary() {
+        let dir = temp_dir("debug-file");
+        let data = std::fs::read(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/inline"
+        ))
+        .unwrap();
+        let object_file = File::parse(&data[..]).unwrap();
+
+        // The debug file of a binary with a build ID is found in the
+        // debuginfod mirrors.
+        let debuginfo =
+            dir.join("mirror/buildid/945800e01fac741192476a78b206f9f7695ba6ee/debuginfo");
+        write_file(&debuginfo, "");
+        let mut config = SymbolConfig {
+            debuginfod_mirrors: vec![dir.join("empty-mirror"), dir.join("mirror")],
+            ..SymbolConfig::default()
+        };
+        assert_eq!(config.debug_file_for_binary(&object_file), Some(debuginfo));
+
+        // The debug file takes precedence, and a .dSYM bundle is resolved
+        // to the DWARF file inside it.
+        let dsym = dir.join("inline.dSYM");
+        let dwarf = dsym.join("Contents/Resources/DWARF/inline");
+        write_file(&dwarf, "");
+        config.debug_file = Some(dsym);
+        assert_eq!(config.debug_file_for_binary(&object_file), Some(dwarf));
+
+        config.debug_file = Some(dir.join("inline.debug"));
+        assert_eq!(
+            config.debug_file_for_binary(&object_file),
+            Some(dir.join("inline.debug"))
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn symbol_file_for_binary() {
+        let dir = temp_dir("symbol-file");
+        let debug_id = DebugId::from_breakpad("0123456789ABCDEF0123456789ABCDEF1").unwrap();
+        let lib_info = LibraryInfo {
+            debug_name: Some("xul.pdb".to_owned()),
+            debug_id: Some(debug_id),
+            ..LibraryInfo::default()
+        };
+        let mut config = SymbolConfig {
+            symbol_dirs: vec![dir.join("symbols")],
+            symbol_cache: Some(dir.join("cache")),
+            ..SymbolConfig::default()
+        };
+        assert_eq!(config.symbol_file_for_binary(&lib_info), None);
+
+        // The symbol server downloads into the cache.
+        let cached = dir.join("cache/xul.pdb/0123456789ABCDEF0123456789ABCDEF1/xul.sym");
+        write_file(&cached, "");
+        assert_eq!(config.symbol_file_for_binary(&lib_info), Some(cached));
+
+        // The symbol directories come first.
+        let pdb = dir.join("symbols/xul.pdb");
+        write_file(&pdb, "");
+        assert_eq!(config.symbol_file_for_binary(&lib_info), Some(pdb));
+
+        // Only a .pdb debug file is used as is.
+        config.debug_file = Some(dir.join("other.pdb"));
+        assert_eq!(
+            config.symbol_file_for_binary(&lib_info),
+            Some(dir.join("other.pdb"))
+        );
+        config.debug_file = Some(dir.join("xul.debug"));
+        assert_eq!(config.symbol_file_for_binary(&lib_info), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
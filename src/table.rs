@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Arc;
+
+use binary_size_profiler::{AttributedRange, Binary, RangeSink, Section};
+use clap::ValueEnum;
+
+use crate::output::OutputSink;
+use crate::summary::{SizeSummary, SummaryKey};
+
+/// What to group the rows of the table output by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum GroupBy {
+    Section,
+    /// The source file of the outer function.
+    File,
+    /// The directory of the outer function's source file.
+    Directory,
+    Symbol,
+    /// The innermost inlined function.
+    InlinedFunction,
+    /// The Rust crate of the outer function's source file.
+    Crate,
+    /// The Rust crate whose compilation unit contains the code. For generic
+    /// code this is usually the crate which instantiated it, but with
+    /// -Zshare-generics, the default for unoptimized builds, a crate reuses
+    /// the instances of its dependencies, which are then charged to the
+    /// dependency that instantiated them first.
+    InstantiatingCrate,
+}
+
+impl GroupBy {
+    fn header(self) -> &'static str {
+        match self {
+            GroupBy::Section => "SECTION",
+            GroupBy::File => "FILE",
+            GroupBy::Directory => "DIRECTORY",
+            GroupBy::Symbol => "SYMBOL",
+            GroupBy::InlinedFunction => "INLINED FUNCTION",
+            GroupBy::Crate => "CRATE",
+            GroupBy::InstantiatingCrate => "INSTANTIATING CRATE",
+        }
+    }
+
+    /// Returns the row label for `key`. Bytes which don't have the requested
+    /// information are labeled with their section, like bloaty does.
+    fn label(self, key: &SummaryKey) -> String {
+        let label = match self {
+            GroupBy::Section => return section_label(key),
+            GroupBy::File => key.path.clone(),
+            GroupBy::Directory => key
+                .path
+                .as_deref()
+                .map(|path| match path.rfind(['/', '\\']) {
+                    Some(i) => path[..i].to_owned(),
+                    None => ".".to_owned(),
+                }),
+            GroupBy::Symbol => key.symbol.clone(),
+            GroupBy::InlinedFunction => key.function().map(str::to_owned),
+            GroupBy::Crate => key.crate_info.as_ref().map(|c| c.to_string()),
+            GroupBy::InstantiatingCrate => key.instantiating_crate.as_ref().map(|c| c.to_string()),
+        };
+        label.unwrap_or_else(|| match &key.section {
+            Some(_) => format!("[{}]", section_label(key)),
+            None => "[unmapped]".to_owned(),
+        })
+    }
+}
+
+fn section_label(key: &SummaryKey) -> String {
+    let section = key.section.as_deref().unwrap_or("[unmapped]");
+    match &key.member {
+        Some(member) => format!("{member} {section}"),
+        None => section.to_owned(),
+    }
+}
+
+/// Collects the sizes for [`write_table`].
+pub struct TableSink {
+    summary: SizeSummary,
+    group_by: Vec<GroupBy>,
+    top: usize,
+    /// The label, the size in the file and the uncompressed size of each
+    /// compressed section.
+    compressed_sections: Vec<(String, u64, u64)>,
+}
+
+impl TableSink {
+    pub fn new(group_by: Vec<GroupBy>, top: usize) -> Self {
+        TableSink {
+            summary: SizeSummary::default(),
+            group_by,
+            top,
+            compressed_sections: Vec::new(),
+        }
+    }
+}
+
+impl RangeSink for TableSink {
+    fn begin_section(&mut self, binary: &Arc<Binary>, section: &Arc<Section>) {
+        if !section.is_compressed {
+            return;
+        }
+        let label = match &binary.member_name {
+            Some(member) => format!("{member} {}", section.name),
+            None => section.name.clone(),
+        };
+        self.compressed_sections
+            .push((label, section.size, section.uncompressed_size));
+    }
+
+    fn range(&mut self, range: &AttributedRange) {
+        self.summary.range(range);
+    }
+}
+
+impl OutputSink for TableSink {
+    fn write_output(&mut self, w: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+        write_table(w, &self.summary, &self.group_by, self.top)?;
+        if !self.compressed_sections.is_empty() {
+            writeln!(w)?;
+            write_compressed_sections(w, &self.compressed_sections)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a table of sizes grouped by `group_by`, with one level of nesting
+/// per entry. Each level lists its `top` largest rows and lumps the rest
+/// together in an "[N Others]" row.
+fn write_table(
+    w: &mut dyn Write,
+    summary: &SizeSummary,
+    group_by: &[GroupBy],
+    top: usize,
+) -> std::io::Result<()> {
+    let total = summary.total();
+    let headers: Vec<&str> = group_by.iter().map(|g| g.header()).collect();
+    writeln!(w, "    FILE SIZE   {}", headers.join(" / "))?;
+    writeln!(
+        w,
+        " --------------  {}",
+        "-".repeat(headers.join(" / ").len())
+    )?;
+
+    let entries: Vec<(&SummaryKey, u64)> = summary
+        .sizes
+        .iter()
+        .map(|(key, bytes)| (key, *bytes))
+        .collect();
+    write_rows(w, &entries, group_by, top, total, 0)?;
+
+    writeln!(w, " 100.0% {:>7}   TOTAL", format_size(total))?;
+    Ok(())
+}
+
+/// Writes the size of each compressed section in the file next to its
+/// uncompressed size, which is what it takes up in memory when it's read.
+fn write_compressed_sections(
+    w: &mut dyn Write,
+    sections: &[(String, u64, u64)],
+) -> std::io::Result<()> {
+    writeln!(w, "  FILE SIZE  UNCOMPRESSED   RATIO   COMPRESSED SECTION")?;
+    writeln!(w, " ----------  ------------  ------   ------------------")?;
+    for (label, size, uncompressed_size) in sections {
+        writeln!(
+            w,
+            " {:>10}  {:>12}  {:>5.1}%   {label}",
+            format_size(*size),
+            format_size(*uncompressed_size),
+            *size as f64 * 100.0 / (*uncompressed_size).max(1) as f64
+        )?;
+    }
+    let size = sections.iter().map(|(_, size, _)| size).sum();
+    let uncompressed_size = sections.iter().map(|(_, _, size)| size).sum();
+    writeln!(
+        w,
+        " {:>10}  {:>12}            [Total]",
+        format_size(size),
+        format_size(uncompressed_size)
+    )
+}
+
+#[derive(Default)]
+struct Group<'a> {
+    bytes: u64,
+    entries: Vec<(&'a SummaryKey, u64)>,
+}
+
+fn write_rows(
+    w: &mut dyn Write,
+    entries: &[(&SummaryKey, u64)],
+    group_by: &[GroupBy],
+    top: usize,
+    total: u64,
+    depth: usize,
+) -> std::io::Result<()> {
+    let Some((group, nested_group_by)) = group_by.split_first() else {
+        return Ok(());
+    };
+
+    let mut groups: HashMap<String, Group> = HashMap::new();
+    for (key, bytes) in entries {
+        let group = groups.entry(group.label(key)).or_default();
+        group.bytes += bytes;
+        group.entries.push((key, *bytes));
+    }
+    let mut groups: Vec<(String, Group)> = groups.into_iter().collect();
+    groups.sort_by(|(label1, group1), (label2, group2)| {
+        group2
+            .bytes
+            .cmp(&group1.bytes)
+            .then_with(|| label1.cmp(label2))
+    });
+
+    let indent = "    ".repeat(depth);
+    let others = groups.split_off(groups.len().min(top));
+    for (label, group) in groups {
+        write_row(w, &indent, &label, group.bytes, total)?;
+        write_rows(w, &group.entries, nested_group_by, top, total, depth + 1)?;
+    }
+    if !others.is_empty() {
+        let label = format!("[{} Others]", others.len());
+        let bytes = others.iter().map(|(_, group)| group.bytes).sum();
+        write_row(w, &indent, &label, bytes, total)?;
+    }
+    Ok(())
+}
+
+fn write_row(
+    w: &mut dyn Write,
+    indent: &str,
+    label: &str,
+    bytes: u64,
+    total: u64,
+) -> std::io::Result<()> {
+    let percent = if total == 0 {
+        0.0
+    } else {
+        bytes as f64 * 100.0 / total as f64
+    };
+    writeln!(
+        w,
+        " {percent:>5.1}% {:>7}   {indent}{label}",
+        format_size(bytes)
+    )
+}
+
+/// Formats a byte count with a binary unit suffix, e.g. "1.23Mi".
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["Ki", "Mi", "Gi", "Ti"];
+    if bytes < 1024 {
+        return bytes.to_string();
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    // The unit and the number of decimals depend on the rounded size, so
+    // that 1023.6Ki is "1.00Mi" rather than "1024Ki".
+    while size.round() >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    let unit = UNITS[unit];
+    if (size * 100.0).round() < 1000.0 {
+        format!("{size:.2}{unit}")
+    } else if (size * 10.0).round() < 1000.0 {
+        format!("{size:.1}{unit}")
+    } else {
+        format!("{size:.0}{unit}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(format_size(0), "0");
+        assert_eq!(format_size(1023), "1023");
+        assert_eq!(format_size(1024), "1.00Ki");
+        assert_eq!(format_size(1536), "1.50Ki");
+        assert_eq!(format_size(10 * 1024), "10.0Ki");
+        assert_eq!(format_size(100 * 1024), "100Ki");
+        assert_eq!(format_size(1024 * 1024), "1.00Mi");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024 / 2), "1.50Gi");
+        assert_eq!(format_size(2048 * 1024u64.pow(4)), "2048Ti");
+    }
+
+    #[test]
+    fn sizes_are_rounded_before_choosing_the_unit() {
+        assert_eq!(format_size(1023 * 1024), "1023Ki");
+        assert_eq!(format_size(1024 * 1024 - 1), "1.00Mi");
+        assert_eq!(format_size(10 * 1024 - 1), "10.0Ki");
+        assert_eq!(format_size(100 * 1024 - 1), "100Ki");
+        assert_eq!(format_size(1024u64.pow(3) - 1), "1.00Gi");
+    }
+
+    fn key(section: &str, path: Option<&str>) -> SummaryKey {
+        SummaryKey {
+            section: Some(section.to_owned()),
+            path: path.map(str::to_owned),
+            ..SummaryKey::default()
+        }
+    }
+
+    fn summary() -> SizeSummary {
+        let mut summary = SizeSummary::default();
+        summary.add(key(".text", Some("src/a.rs")), 600);
+        summary.add(key(".text", Some("src/b.rs")), 200);
+        summary.add(key(".text", None), 100);
+        summary.add(key(".data", Some("src/a.rs")), 100);
+        summary
+    }
+
+    fn table(group_by: &[GroupBy], top: usize) -> String {
+        let mut output = Vec::new();
+        write_table(&mut output, &summary(), group_by, top).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn nested_groups() {
+        assert_eq!(
+            table(&[GroupBy::Section, GroupBy::File], 2),
+            "    FILE SIZE   SECTION / FILE
+ --------------  --------------
+  90.0%     900   .text
+  60.0%     600       src/a.rs
+  20.0%     200       src/b.rs
+  10.0%     100       [1 Others]
+  10.0%     100   .data
+  10.0%     100       src/a.rs
+ 100.0%    1000   TOTAL
+"
+        );
+    }
+
+    #[test]
+    fn rows_without_the_group_are_labeled_with_their_section() {
+        assert_eq!(
+            table(&[GroupBy::File], 3),
+            "    FILE SIZE   FILE
+ --------------  ----
+  70.0%     700   src/a.rs
+  20.0%     200   src/b.rs
+  10.0%     100   [.text]
+ 100.0%    1000   TOTAL
+"
+        );
+        assert_eq!(
+            table(&[GroupBy::File], 1),
+            "    FILE SIZE   FILE
+ --------------  ----
+  70.0%     700   src/a.rs
+  30.0%     300   [2 Others]
+ 100.0%    1000   TOTAL
+"
+        );
+    }
+}
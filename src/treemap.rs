@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use binary_size_profiler::{AttributedRange, RangeSink};
+use serde::Serialize;
+
+use crate::output::OutputSink;
+use crate::summary::SizeSummary;
+
+const TEMPLATE: &str = include_str!("treemap.html");
+
+/// Writes a single HTML file with an interactive treemap of the sizes. The
+/// tree has the same levels as the profile's call tree: sections, source
+/// directories and files, functions and inlined functions. The data and the
+/// script are embedded, so the file works offline.
+pub struct TreemapSink {
+    name: String,
+    summary: SizeSummary,
+}
+
+impl TreemapSink {
+    pub fn new(name: &str) -> Self {
+        TreemapSink {
+            name: name.to_owned(),
+            summary: SizeSummary::default(),
+        }
+    }
+}
+
+impl RangeSink for TreemapSink {
+    fn range(&mut self, range: &AttributedRange) {
+        self.summary.range(range);
+    }
+}
+
+#[derive(Default)]
+struct TreeNode {
+    size: u64,
+    children: HashMap<String, TreeNode>,
+}
+
+/// The embedded form of a [`TreeNode`], with short field names to keep the
+/// file small, and with the children sorted by decreasing size.
+#[derive(Serialize)]
+struct Node {
+    n: String,
+    s: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    c: Vec<Node>,
+}
+
+impl TreeNode {
+    fn into_node(self, name: String) -> Node {
+        let mut children: Vec<Node> = self
+            .children
+            .into_iter()
+            .map(|(name, child)| child.into_node(name))
+            .collect();
+        children.sort_by(|a, b| b.s.cmp(&a.s).then_with(|| a.n.cmp(&b.n)));
+        Node {
+            n: name,
+            s: self.size,
+            c: children,
+        }
+    }
+}
+
+impl OutputSink for TreemapSink {
+    fn write_output(&mut self, w: &mut dyn Write) -> Result<(), Box<dyn std::error::Error>> {
+        let mut root = TreeNode::default();
+        for (key, bytes) in &self.summary.sizes {
+            root.size += bytes;
+            let mut node = &mut root;
+            for label in key.stack_labels() {
+                node = node.children.entry(label).or_default();
+                node.size += bytes;
+            }
+        }
+        let data = serde_json::to_string(&root.into_node(self.name.clone()))?;
+        // Function names like "<T as Trait>" could otherwise close the script
+        // element.
+        let data = data.replace('<', "\\u003c");
+        let html = TEMPLATE
+            .replace("{{TITLE}}", &html_escape(&self.name))
+            .replace("{{DATA}}", &data);
+        w.write_all(html.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use binary_size_profiler::wholesym::{AddressInfo, SymbolInfo};
+    use binary_size_profiler::Section;
+    use object::SectionKind;
+
+    use super::*;
+
+    #[test]
+    fn data_is_escaped() {
+        let symbol = "</script><script>alert(1)</script>";
+        let mut sink = TreemapSink::new("<app>");
+        sink.range(&AttributedRange {
+            file_offset: 0x2000,
+            size: 8,
+            binary: None,
+            section: Some(Arc::new(Section {
+                file_offset: 0x2000,
+                svma: 0x2000,
+                size: 0x100,
+                kind: SectionKind::Data,
+                name: ".data".to_owned(),
+                is_compressed: false,
+                uncompressed_size: 0x100,
+            })),
+            relative_address: None,
+            addr_info: Some(AddressInfo {
+                symbol: SymbolInfo {
+                    address: 0x2000,
+                    size: None,
+                    name: symbol.to_owned(),
+                },
+                frames: None,
+            }),
+            crate_info: None,
+            instantiating_crate: None,
+        });
+        let mut output = Vec::new();
+        sink.write_output(&mut output).unwrap();
+        let html = String::from_utf8(output).unwrap();
+
+        assert!(html.contains("<title>Binary size: &lt;app&gt;</title>"));
+        assert_eq!(
+            html.matches("</script>").count(),
+            TEMPLATE.matches("</script>").count()
+        );
+        let data = html
+            .lines()
+            .find_map(|line| line.strip_prefix("const root = "))
+            .and_then(|line| line.strip_suffix(';'))
+            .unwrap();
+        assert!(!data.contains('<'));
+        let root: serde_json::Value = serde_json::from_str(data).unwrap();
+        assert_eq!(root["n"], "<app>");
+        assert_eq!(root["c"][0]["c"][0]["c"][0]["n"], symbol);
+        assert_eq!(root["c"][0]["c"][0]["c"][0]["s"], 8);
+    }
+}
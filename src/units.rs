@@ -0,0 +1,242 @@
+use crate::crates::{CrateInfo, CrateMap};
+use crate::dwarf::Reader;
+
+/// The address ranges of the DWARF compilation units, with the crate that
+/// each unit was compiled for.
+///
+/// rustc emits a generic function into the codegen units of every crate
+/// which instantiates it, so the unit of a monomorphized `core::iter`
+/// function tells us which crate caused the code to exist, even though its
+/// frames and source paths all point into `core`.
+pub struct CompileUnits {
+    /// Sorted by start address, non-overlapping.
+    ranges: Vec<UnitRange>,
+    crates: Vec<Option<CrateInfo>>,
+}
+
+struct UnitRange {
+    start: u64,
+    end: u64,
+    /// Index into `crates`.
+    unit: usize,
+}
+
+impl CompileUnits {
+    /// Reads the unit ranges from the DWARF of the binary or its separate
+    /// debug file. Units which can't be read are skipped, as are the rest of
+    /// a unit's ranges after an error.
+    pub fn compute(dwarf: &gimli::Dwarf<Reader<'_>>, base_addr: u64, crates: &CrateMap) -> Self {
+        let mut ranges = Vec::new();
+        let mut unit_crates = Vec::new();
+        let mut units = dwarf.units();
+        while let Ok(Some(header)) = units.next() {
+            let Ok(unit) = dwarf.unit(header) else {
+                continue;
+            };
+            let Ok(mut unit_ranges) = dwarf.unit_ranges(&unit) else {
+                continue;
+            };
+            let name = unit.name.map(|n| n.to_string_lossy().into_owned());
+            let comp_dir = unit.comp_dir.map(|d| d.to_string_lossy().into_owned());
+            let index = unit_crates.len();
+            unit_crates.push(name.and_then(|name| unit_crate(&name, comp_dir.as_deref(), crates)));
+
+            while let Ok(Some(range)) = unit_ranges.next() {
+                let (Some(start), Some(end)) = (
+                    range.begin.checked_sub(base_addr),
+                    range.end.checked_sub(base_addr),
+                ) else {
+                    continue;
+                };
+                if start < end {
+                    ranges.push(UnitRange {
+                        start,
+                        end,
+                        unit: index,
+                    });
+                }
+            }
+        }
+        ranges.sort_by_key(|r| r.start);
+        CompileUnits {
+            ranges,
+            crates: unit_crates,
+        }
+    }
+
+    /// Returns the crate of the unit which contains `address`, which is
+    /// relative to the image base.
+    pub fn crate_at(&self, address: u64) -> Option<&CrateInfo> {
+        let index = self.ranges.partition_point(|r| r.start <= address);
+        let range = &self.ranges[index.checked_sub(1)?];
+        if address >= range.end {
+            return None;
+        }
+        self.crates[range.unit].as_ref()
+    }
+}
+
+/// Rust unit names look like `src/lib.rs/@/serde.a1b2c3d4e5f6a7b8-cgu.0`:
+/// the crate root, relative to `comp_dir`, then the crate name and the
+/// codegen unit. Incremental builds use an opaque hash instead of the crate
+/// name and codegen unit, in which case the crate is named after the
+/// directory that contains `src`. Returns `None` for units of other languages.
+pub(crate) fn unit_crate(
+    name: &str,
+    comp_dir: Option<&str>,
+    crates: &CrateMap,
+) -> Option<CrateInfo> {
+    let (root, cgu) = name.split_once("/@/")?;
+    let root = match comp_dir {
+        Some(comp_dir) if !root.starts_with(['/', '\\']) => format!("{comp_dir}/{root}"),
+        _ => root.to_owned(),
+    };
+    if let Some(crate_info) = crates.crate_for_path(&root) {
+        return Some(crate_info);
+    }
+    let crate_name = match cgu.split_once('.') {
+        Some((crate_name, rest)) if rest.contains("-cgu.") => crate_name,
+        _ => {
+            let components: Vec<&str> = root.split(['/', '\\']).collect();
+            let src = components.iter().rposition(|c| *c == "src")?;
+            *components.get(src.checked_sub(1)?)?
+        }
+    };
+    Some(CrateInfo {
+        name: crate_name.to_owned(),
+        version: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crate_name(name: &str, comp_dir: Option<&str>) -> Option<String> {
+        unit_crate(name, comp_dir, &CrateMap::default()).map(|c| c.name)
+    }
+
+    #[test]
+    fn codegen_unit_crate() {
+        assert_eq!(
+            crate_name(
+                "src/lib.rs/@/serde.a1b2c3d4e5f6a7b8-cgu.0",
+                Some("/work/serde")
+            ),
+            Some("serde".to_owned())
+        );
+    }
+
+    #[test]
+    fn incremental_unit_crate() {
+        assert_eq!(
+            crate_name("src/main.rs/@/1x2y3z4w5v6u7t8s", Some("/work/app")),
+            Some("app".to_owned())
+        );
+        assert_eq!(crate_name("main.rs/@/1x2y3z4w5v6u7t8s", None), None);
+    }
+
+    #[test]
+    fn registry_unit_crate() {
+        let crate_info = unit_crate(
+            "src/lib.rs/@/serde_json.a1b2c3d4e5f6a7b8-cgu.3",
+            Some(
+                "/home/me/.cargo/registry/src/index.crates.io-6f17d22bba15001f/serde_json-1.0.138",
+            ),
+            &CrateMap::default(),
+        );
+        assert_eq!(
+            crate_info,
+            Some(CrateInfo {
+                name: "serde_json".to_owned(),
+                version: Some("1.0.138".to_owned()),
+            })
+        );
+    }
+
+    #[test]
+    fn c_unit() {
+        assert_eq!(crate_name("hello.c", Some("/work")), None);
+    }
+
+    #[test]
+    fn crate_at() {
+        let units = CompileUnits {
+            ranges: vec![
+                UnitRange {
+                    start: 0x10,
+                    end: 0x20,
+                    unit: 0,
+                },
+                UnitRange {
+                    start: 0x30,
+                    end: 0x40,
+                    unit: 1,
+                },
+            ],
+            crates: vec![
+                Some(CrateInfo {
+                    name: "app".to_owned(),
+                    version: None,
+                }),
+                None,
+            ],
+        };
+        assert_eq!(units.crate_at(0x0f), None);
+        assert_eq!(units.crate_at(0x10).map(|c| c.name.as_str()), Some("app"));
+        assert_eq!(units.crate_at(0x1f).map(|c| c.name.as_str()), Some("app"));
+        assert_eq!(units.crate_at(0x20), None);
+        assert_eq!(units.crate_at(0x30), None);
+    }
+
+    #[test]
+    fn bad_units_are_skipped() {
+        use gimli::write::{self, Address, AttributeValue};
+
+        let encoding = gimli::Encoding {
+            format: gimli::Format::Dwarf32,
+            version: 4,
+            address_size: 8,
+        };
+        let mut dwarf = write::Dwarf::new();
+        let unit = dwarf
+            .units
+            .add(write::Unit::new(encoding, write::LineProgram::none()));
+        let unit = dwarf.units.get_mut(unit);
+        let root = unit.get_mut(unit.root());
+        root.set(
+            gimli::DW_AT_name,
+            AttributeValue::String(b"src/lib.rs/@/serde.a1b2c3d4e5f6a7b8-cgu.0".to_vec()),
+        );
+        root.set(
+            gimli::DW_AT_low_pc,
+            AttributeValue::Address(Address::Constant(0x1010)),
+        );
+        root.set(gimli::DW_AT_high_pc, AttributeValue::Udata(0x10));
+        let mut sections = write::Sections::new(write::EndianVec::new(gimli::LittleEndian));
+        dwarf.write(&mut sections).unwrap();
+        let mut data = std::collections::HashMap::new();
+        sections
+            .for_each(|id, section| {
+                data.insert(id, section.slice().to_vec());
+                Ok::<_, gimli::Error>(())
+            })
+            .unwrap();
+        // A unit of version 4 whose abbrev offset is out of bounds.
+        data.get_mut(&gimli::SectionId::DebugInfo)
+            .unwrap()
+            .extend([7, 0, 0, 0, 4, 0, 0xff, 0xff, 0xff, 0xff, 8]);
+
+        let dwarf = gimli::Dwarf::load(|id| {
+            let section = data.get(&id).map_or(&[][..], |d| &d[..]);
+            Ok::<_, gimli::Error>(gimli::EndianSlice::new(
+                section,
+                gimli::RunTimeEndian::Little,
+            ))
+        })
+        .unwrap();
+        let units = CompileUnits::compute(&dwarf, 0x1000, &CrateMap::default());
+        assert_eq!(units.crate_at(0x10).map(|c| c.name.as_str()), Some("serde"));
+        assert_eq!(units.crate_at(0x20), None);
+    }
+}
@@ -0,0 +1,627 @@
+use std::collections::HashSet;
+use std::convert::TryInto;
+
+use gimli::{Reader, UnwindSection};
+use object::read::{Object, ObjectSection};
+use object::{Architecture, File};
+
+/// The records of the unwind and exception handling tables, each with the
+/// function that it belongs to: the FDEs in `.eh_frame` and their entries in
+/// the `.eh_frame_hdr` search table, the LSDAs in `.gcc_except_table`, the
+/// entries of Mach-O `__unwind_info`, and the PE `.pdata` entries with the
+/// `.xdata` unwind info that they point to.
+///
+/// With `panic=unwind`, every function which has to run drops while a panic
+/// unwinds through it has an LSDA, and almost every function has an FDE.
+#[derive(Default)]
+pub struct UnwindRecords {
+    /// Sorted by address.
+    records: Vec<UnwindRecord>,
+}
+
+pub struct UnwindRecord {
+    /// Relative to the image base.
+    pub address: u64,
+    pub size: u64,
+    /// The relative address of the function.
+    pub function: u64,
+}
+
+/// The size of the entries of a PE `.pdata` section on x86-64, which are
+/// `RUNTIME_FUNCTION` structs with the begin and end address and the address
+/// of the unwind info.
+const X86_64_PDATA_ENTRY_SIZE: u64 = 12;
+
+/// On arm64, the end address is replaced by packed unwind data, or by the
+/// address of the unwind info if its low two bits are zero.
+const AARCH64_PDATA_ENTRY_SIZE: u64 = 8;
+
+/// The `UNW_FLAG_EHANDLER`, `UNW_FLAG_UHANDLER` and `UNW_FLAG_CHAININFO`
+/// flags of x86-64 unwind info.
+const UNW_FLAG_HANDLER: u8 = 0x3;
+const UNW_FLAG_CHAININFO: u8 = 0x4;
+
+/// The `kind` of the second-level pages of Mach-O `__unwind_info`.
+const UNWIND_SECOND_LEVEL_REGULAR: u32 = 2;
+const UNWIND_SECOND_LEVEL_COMPRESSED: u32 = 3;
+
+impl UnwindRecords {
+    /// Parses the unwind tables of `object_file`. Tables which can't be
+    /// parsed are skipped.
+    pub fn compute(object_file: &File<'_>, base_addr: u64) -> Self {
+        let mut tables = UnwindTables {
+            endian: if object_file.is_little_endian() {
+                gimli::RunTimeEndian::Little
+            } else {
+                gimli::RunTimeEndian::Big
+            },
+            address_size: if object_file.is_64() { 8 } else { 4 },
+            architecture: object_file.architecture(),
+            bases: bases(object_file),
+            base_addr,
+            sections: object_file
+                .sections()
+                .filter_map(|s| Some((s.address(), s.data().ok()?)))
+                .collect(),
+            records: Vec::new(),
+            lsdas: Vec::new(),
+        };
+        let mut lsda_sections = Vec::new();
+        for section in object_file.sections() {
+            let (Ok(name), Ok(data)) = (section.name(), section.data()) else {
+                continue;
+            };
+            let Some(address) = section.address().checked_sub(base_addr) else {
+                continue;
+            };
+            match name {
+                ".eh_frame" | "__eh_frame" => tables.eh_frame(address, data),
+                ".eh_frame_hdr" => tables.eh_frame_hdr(address, data),
+                "__unwind_info" => tables.compact_unwind(address, data),
+                ".pdata" => tables.pdata(address, data),
+                ".gcc_except_table" | "__gcc_except_tab" => {
+                    lsda_sections.push((address, address + section.size()));
+                    None
+                }
+                _ => None,
+            };
+        }
+        tables.lsdas(&lsda_sections);
+
+        let mut records = tables.records;
+        records.sort_by_key(|r| r.address);
+        UnwindRecords { records }
+    }
+
+    /// The records which start in `start..end`, sorted by address.
+    pub fn in_range(&self, start: u64, end: u64) -> &[UnwindRecord] {
+        let first = self.records.partition_point(|r| r.address < start);
+        let last = self.records.partition_point(|r| r.address < end);
+        &self.records[first..last]
+    }
+
+    /// The relative addresses of the functions that the records belong to.
+    pub fn functions(&self) -> impl Iterator<Item = u64> + '_ {
+        self.records.iter().map(|r| r.function)
+    }
+}
+
+/// The section addresses which the pointers in `.eh_frame` and
+/// `.eh_frame_hdr` can be relative to.
+fn bases(object_file: &File<'_>) -> gimli::BaseAddresses {
+    let address = |names: &[&str]| {
+        names
+            .iter()
+            .find_map(|name| object_file.section_by_name(name))
+            .map(|s| s.address())
+    };
+    let mut bases = gimli::BaseAddresses::default();
+    if let Some(address) = address(&[".eh_frame", "__eh_frame"]) {
+        bases = bases.set_eh_frame(address);
+    }
+    if let Some(address) = address(&[".eh_frame_hdr"]) {
+        bases = bases.set_eh_frame_hdr(address);
+    }
+    if let Some(address) = address(&[".text", "__text"]) {
+        bases = bases.set_text(address);
+    }
+    if let Some(address) = address(&[".got"]) {
+        bases = bases.set_got(address);
+    }
+    bases
+}
+
+struct UnwindTables<'data> {
+    endian: gimli::RunTimeEndian,
+    address_size: u8,
+    architecture: Architecture,
+    bases: gimli::BaseAddresses,
+    base_addr: u64,
+    /// The SVMAs and the data of all sections, for reading the PE unwind
+    /// info that `.pdata` points to.
+    sections: Vec<(u64, &'data [u8])>,
+    records: Vec<UnwindRecord>,
+    /// The relative addresses of the LSDAs, with their function.
+    lsdas: Vec<(u64, u64)>,
+}
+
+impl UnwindTables<'_> {
+    /// Adds a record for each FDE. The CIEs are shared by many FDEs, so they
+    /// aren't attributed.
+    fn eh_frame(&mut self, address: u64, data: &[u8]) -> Option<()> {
+        let mut eh_frame = gimli::EhFrame::new(data, self.endian);
+        eh_frame.set_address_size(self.address_size);
+        let bases = self.bases.clone();
+        let mut entries = eh_frame.entries(&bases);
+        while let Some(entry) = entries.next().ok()? {
+            let gimli::CieOrFde::Fde(partial) = entry else {
+                continue;
+            };
+            let Ok(fde) = partial.parse(gimli::EhFrame::cie_from_offset) else {
+                continue;
+            };
+            let Some(function) = fde.initial_address().checked_sub(self.base_addr) else {
+                continue;
+            };
+            let offset = fde.offset();
+            // The length field is 4 bytes, or 12 in the 64-bit format.
+            let length_size = match data.get(offset..offset + 4) {
+                Some([0xff, 0xff, 0xff, 0xff]) => 12,
+                _ => 4,
+            };
+            self.records.push(UnwindRecord {
+                address: address + offset as u64,
+                size: length_size + fde.entry_len() as u64,
+                function,
+            });
+            if let Some(gimli::Pointer::Direct(lsda)) = fde.lsda() {
+                if let Some(lsda) = lsda.checked_sub(self.base_addr) {
+                    self.lsdas.push((lsda, function));
+                }
+            }
+        }
+        Some(())
+    }
+
+    /// Adds a record for each entry of the binary search table in
+    /// `.eh_frame_hdr`, which follows the header with the `.eh_frame` pointer
+    /// and the number of entries.
+    fn eh_frame_hdr(&mut self, address: u64, data: &[u8]) -> Option<()> {
+        let hdr = gimli::EhFrameHdr::new(data, self.endian)
+            .parse(&self.bases, self.address_size)
+            .ok()?;
+        let table = hdr.table()?;
+        let [_version, eh_frame_ptr_encoding, fde_count_encoding, table_encoding] =
+            *data.get(..4)?
+        else {
+            return None;
+        };
+        let eh_frame_ptr_size = self.read_encoded(eh_frame_ptr_encoding, &data[4..])?.1;
+        let fde_count_start = 4 + eh_frame_ptr_size;
+        let (fde_count, fde_count_size) =
+            self.read_encoded(fde_count_encoding, data.get(fde_count_start..)?)?;
+        let table_start = (fde_count_start + fde_count_size) as u64;
+        // Each entry has the function address and the FDE address, in the
+        // table encoding: (s)data2, (s)data4 or (s)data8.
+        let entry_size: u64 = match table_encoding & 0x0f {
+            0x02 | 0x0a => 2 * 2,
+            0x03 | 0x0b => 4 * 2,
+            0x04 | 0x0c => 8 * 2,
+            _ => return None,
+        };
+        if table_start + fde_count.checked_mul(entry_size)? > data.len() as u64 {
+            return None;
+        }
+        let mut iter = table.iter(&self.bases);
+        for i in 0..fde_count {
+            let Some((initial_location, _)) = iter.next().ok()? else {
+                break;
+            };
+            let gimli::Pointer::Direct(function) = initial_location else {
+                continue;
+            };
+            if let Some(function) = function.checked_sub(self.base_addr) {
+                self.records.push(UnwindRecord {
+                    address: address + table_start + i * entry_size,
+                    size: entry_size,
+                    function,
+                });
+            }
+        }
+        Some(())
+    }
+
+    /// Reads a value in one of the `DW_EH_PE_*` formats at the start of
+    /// `data`, and returns it with its size. The application bits of the
+    /// encoding, e.g. `DW_EH_PE_pcrel`, are ignored.
+    fn read_encoded(&self, encoding: u8, data: &[u8]) -> Option<(u64, usize)> {
+        // DW_EH_PE_omit
+        if encoding == 0xff {
+            return Some((0, 0));
+        }
+        let mut reader = gimli::EndianSlice::new(data, self.endian);
+        let value = match encoding & 0x0f {
+            0x00 => reader.read_address(self.address_size).ok()?,
+            0x01 => reader.read_uleb128().ok()?,
+            0x02 => u64::from(reader.read_u16().ok()?),
+            0x03 => u64::from(reader.read_u32().ok()?),
+            0x04 => reader.read_u64().ok()?,
+            0x09 => reader.read_sleb128().ok()? as u64,
+            0x0a => i64::from(reader.read_i16().ok()?) as u64,
+            0x0b => i64::from(reader.read_i32().ok()?) as u64,
+            0x0c => reader.read_i64().ok()? as u64,
+            _ => return None,
+        };
+        Some((value, data.len() - reader.len()))
+    }
+
+    /// Adds a record for each entry of the second-level pages and of the LSDA
+    /// index of Mach-O compact unwind info. Function offsets in
+    /// `__unwind_info` are relative to the image base.
+    fn compact_unwind(&mut self, address: u64, data: &[u8]) -> Option<()> {
+        let read_u16 = |offset: usize| -> Option<u16> {
+            Some(u16::from_le_bytes(
+                data.get(offset..offset + 2)?.try_into().ok()?,
+            ))
+        };
+        let read_u32 = |offset: usize| -> Option<u32> {
+            Some(u32::from_le_bytes(
+                data.get(offset..offset + 4)?.try_into().ok()?,
+            ))
+        };
+        if read_u32(0)? != 1 {
+            return None;
+        }
+        let index_offset = read_u32(20)? as usize;
+        let index_count = read_u32(24)? as usize;
+        let mut records = Vec::new();
+        for i in 0..index_count {
+            let entry = index_offset + i * 12;
+            let function_offset = read_u32(entry)?;
+            let page = read_u32(entry + 4)? as usize;
+            // The last index entry only marks the end of the functions.
+            if page == 0 {
+                continue;
+            }
+            let entry_count = read_u16(page + 6)? as usize;
+            match read_u32(page)? {
+                UNWIND_SECOND_LEVEL_REGULAR => {
+                    let entries = page + read_u16(page + 4)? as usize;
+                    for j in 0..entry_count {
+                        let entry = entries + j * 8;
+                        records.push((entry, 8, read_u32(entry)?));
+                    }
+                }
+                UNWIND_SECOND_LEVEL_COMPRESSED => {
+                    let entries = page + read_u16(page + 4)? as usize;
+                    for j in 0..entry_count {
+                        let entry = entries + j * 4;
+                        let function = function_offset + (read_u32(entry)? & 0x00ff_ffff);
+                        records.push((entry, 4, function));
+                    }
+                }
+                _ => {}
+            }
+        }
+        // The LSDA index of all pages is one array, which ends where the
+        // last index entry points.
+        if index_count > 0 {
+            let lsdas_start = read_u32(index_offset + 8)? as usize;
+            let lsdas_end = read_u32(index_offset + (index_count - 1) * 12 + 8)? as usize;
+            for entry in (lsdas_start..lsdas_end).step_by(8) {
+                let function = read_u32(entry)?;
+                records.push((entry, 8, function));
+                self.lsdas
+                    .push((u64::from(read_u32(entry + 4)?), u64::from(function)));
+            }
+        }
+        for (offset, size, function) in records {
+            self.records.push(UnwindRecord {
+                address: address + offset as u64,
+                size,
+                function: u64::from(function),
+            });
+        }
+        Some(())
+    }
+
+    /// Adds a record for each `RUNTIME_FUNCTION` in `.pdata`, and for the
+    /// unwind info in `.xdata` that it points to. Several functions can
+    /// share their unwind info, which is attributed to the first of them.
+    fn pdata(&mut self, address: u64, data: &[u8]) -> Option<()> {
+        let read_u32 = |data: &[u8], offset: usize| -> Option<u32> {
+            Some(u32::from_le_bytes(
+                data.get(offset..offset + 4)?.try_into().ok()?,
+            ))
+        };
+        let architecture = self.architecture;
+        let entry_size = match architecture {
+            Architecture::X86_64 => X86_64_PDATA_ENTRY_SIZE,
+            Architecture::Aarch64 => AARCH64_PDATA_ENTRY_SIZE,
+            _ => return None,
+        };
+        let mut unwind_infos = HashSet::new();
+        for offset in (0..data.len() as u64).step_by(entry_size as usize) {
+            let entry = offset as usize;
+            let function = u64::from(read_u32(data, entry)?);
+            if function == 0 {
+                continue;
+            }
+            self.records.push(UnwindRecord {
+                address: address + offset,
+                size: entry_size,
+                function,
+            });
+            let unwind_data = read_u32(data, entry + entry_size as usize - 4)?;
+            let unwind_info = match architecture {
+                // The low bit marks chained entries, which point to another
+                // `RUNTIME_FUNCTION` rather than to unwind info.
+                Architecture::X86_64 if unwind_data & 1 == 0 => u64::from(unwind_data),
+                // Otherwise the unwind data is packed into the entry.
+                Architecture::Aarch64 if unwind_data & 3 == 0 => u64::from(unwind_data),
+                _ => continue,
+            };
+            if !unwind_infos.insert(unwind_info) {
+                continue;
+            }
+            if let Some(size) = self.unwind_info_size(architecture, unwind_info) {
+                self.records.push(UnwindRecord {
+                    address: unwind_info,
+                    size,
+                    function,
+                });
+            }
+        }
+        Some(())
+    }
+
+    /// The size of the PE unwind info at `address`, up to and including the
+    /// address of the exception handler. The size of the handler's data
+    /// depends on the handler, so it isn't included.
+    fn unwind_info_size(&self, architecture: Architecture, address: u64) -> Option<u64> {
+        let header = self.read(address, 4)?;
+        let header = u32::from_le_bytes(header[..4].try_into().ok()?);
+        match architecture {
+            Architecture::X86_64 => {
+                let flags = (header & 0xff) as u8 >> 3;
+                let code_count = u64::from((header >> 16) & 0xff);
+                // The unwind codes are 2 bytes each, padded to 4 bytes.
+                let mut size = 4 + (code_count + code_count % 2) * 2;
+                if flags & UNW_FLAG_CHAININFO != 0 {
+                    size += X86_64_PDATA_ENTRY_SIZE;
+                } else if flags & UNW_FLAG_HANDLER != 0 {
+                    size += 4;
+                }
+                Some(size)
+            }
+            Architecture::Aarch64 => {
+                let has_handler = header & (1 << 20) != 0;
+                let single_epilog = header & (1 << 21) != 0;
+                let mut epilog_count = u64::from((header >> 22) & 0x1f);
+                let mut code_words = u64::from(header >> 27);
+                let mut size = 4;
+                if epilog_count == 0 && code_words == 0 {
+                    let extension = u32::from_le_bytes(self.read(address + 4, 4)?.try_into().ok()?);
+                    epilog_count = u64::from(extension & 0xffff);
+                    code_words = u64::from((extension >> 16) & 0xff);
+                    size += 4;
+                }
+                if !single_epilog {
+                    size += epilog_count * 4;
+                }
+                size += code_words * 4;
+                if has_handler {
+                    size += 4;
+                }
+                Some(size)
+            }
+            _ => None,
+        }
+    }
+
+    /// Reads `len` bytes at the relative address `address`.
+    fn read(&self, address: u64, len: u64) -> Option<&[u8]> {
+        let svma = address + self.base_addr;
+        let (section_svma, data) = self.sections.iter().find(|(section_svma, data)| {
+            *section_svma <= svma && svma - section_svma < data.len() as u64
+        })?;
+        let offset = (svma - section_svma) as usize;
+        data.get(offset..offset + len as usize)
+    }
+
+    /// Adds a record for each LSDA, which extends to the next LSDA or to the
+    /// end of its section, since the LSDAs don't store their size.
+    /// `sections` are the relative address ranges of the LSDA sections.
+    fn lsdas(&mut self, sections: &[(u64, u64)]) {
+        let mut lsdas = std::mem::take(&mut self.lsdas);
+        lsdas.sort_unstable();
+        lsdas.dedup_by_key(|(lsda, _)| *lsda);
+        for &(start, end) in sections {
+            let first = lsdas.partition_point(|(lsda, _)| *lsda < start);
+            let last = lsdas.partition_point(|(lsda, _)| *lsda < end);
+            let section_lsdas = &lsdas[first..last];
+            for (i, (lsda, function)) in section_lsdas.iter().enumerate() {
+                let next = section_lsdas.get(i + 1).map_or(end, |(next, _)| *next);
+                self.records.push(UnwindRecord {
+                    address: *lsda,
+                    size: next - lsda,
+                    function: *function,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EH_FRAME: u64 = 0x1000;
+    const EH_FRAME_HDR: u64 = 0x800;
+
+    fn tables(architecture: Architecture, sections: Vec<(u64, &[u8])>) -> UnwindTables<'_> {
+        UnwindTables {
+            endian: gimli::RunTimeEndian::Little,
+            address_size: 8,
+            architecture,
+            bases: gimli::BaseAddresses::default()
+                .set_eh_frame(EH_FRAME)
+                .set_eh_frame_hdr(EH_FRAME_HDR),
+            base_addr: 0,
+            sections,
+            records: Vec::new(),
+            lsdas: Vec::new(),
+        }
+    }
+
+    fn records(tables: &UnwindTables<'_>) -> Vec<(u64, u64, u64)> {
+        tables
+            .records
+            .iter()
+            .map(|r| (r.address, r.size, r.function))
+            .collect()
+    }
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn eh_frame_fdes() {
+        let mut data = Vec::new();
+        // CIE: length, id, version, "zR", code and data alignment, return
+        // address register, augmentation data with the FDE pointer encoding
+        // DW_EH_PE_pcrel | DW_EH_PE_sdata4, and padding.
+        data.extend(16u32.to_le_bytes());
+        data.extend(0u32.to_le_bytes());
+        data.extend([1, b'z', b'R', 0, 0x01, 0x78, 0x10, 0x01, 0x1b, 0, 0, 0]);
+        // FDE: length, CIE pointer, initial location relative to the field,
+        // address range, empty augmentation data, and padding.
+        data.extend(16u32.to_le_bytes());
+        data.extend(24u32.to_le_bytes());
+        data.extend((0x2000 - (EH_FRAME as u32 + 28)).to_le_bytes());
+        data.extend(0x40u32.to_le_bytes());
+        data.extend([0, 0, 0, 0]);
+        // Terminator.
+        data.extend(0u32.to_le_bytes());
+
+        let mut tables = tables(Architecture::X86_64, Vec::new());
+        tables.eh_frame(EH_FRAME, &data).unwrap();
+        // Only the FDE is attributed, with its length field.
+        assert_eq!(records(&tables), [(EH_FRAME + 20, 20, 0x2000)]);
+    }
+
+    #[test]
+    fn eh_frame_hdr_table() {
+        // The version, the encodings of the .eh_frame pointer (pcrel sdata4),
+        // the FDE count (udata4) and the table (datarel sdata4).
+        let mut data = vec![1, 0x1b, 0x03, 0x3b];
+        data.extend((EH_FRAME as u32 - (EH_FRAME_HDR as u32 + 4)).to_le_bytes());
+        data.extend(u32s(&[2, 0x1800, 0x814, 0x2800, 0x828]));
+        // Padding after the table, which isn't part of it.
+        data.extend([0; 8]);
+
+        let mut tables = tables(Architecture::X86_64, Vec::new());
+        tables.eh_frame_hdr(EH_FRAME_HDR, &data).unwrap();
+        assert_eq!(
+            records(&tables),
+            [
+                (EH_FRAME_HDR + 12, 8, 0x2000),
+                (EH_FRAME_HDR + 20, 8, 0x3000)
+            ]
+        );
+    }
+
+    #[test]
+    fn eh_frame_hdr_truncated_table() {
+        let mut data = vec![1, 0x1b, 0x03, 0x3b];
+        data.extend((EH_FRAME as u32 - (EH_FRAME_HDR as u32 + 4)).to_le_bytes());
+        data.extend(u32s(&[3, 0x1800, 0x814, 0x2800, 0x828]));
+
+        let mut tables = tables(Architecture::X86_64, Vec::new());
+        assert_eq!(tables.eh_frame_hdr(EH_FRAME_HDR, &data), None);
+        assert!(tables.records.is_empty());
+    }
+
+    #[test]
+    fn compact_unwind_page() {
+        let mut data = u32s(&[
+            // Header: version, the common encodings, the personalities, and
+            // the index with two entries.
+            1, 28, 0, 28, 0, 28, 2,
+            // Index: function offset, page offset, LSDA index offset.
+            0x1000, 52, 72, //
+            0x2000, 0, 80,
+        ]);
+        // Compressed page: kind, entry offset and count, and no encodings.
+        data.extend(UNWIND_SECOND_LEVEL_COMPRESSED.to_le_bytes());
+        data.extend([12, 0, 2, 0, 0, 0, 0, 0]);
+        // Entries: encoding index and function offset from the index entry.
+        data.extend(u32s(&[0x0000_0000, 0x0100_0010]));
+        // LSDA index: function and LSDA.
+        data.extend(u32s(&[0x1010, 0x5000]));
+
+        let mut tables = tables(Architecture::Aarch64, Vec::new());
+        tables.compact_unwind(0x8000, &data).unwrap();
+        assert_eq!(
+            records(&tables),
+            [
+                (0x8000 + 64, 4, 0x1000),
+                (0x8000 + 68, 4, 0x1010),
+                (0x8000 + 72, 8, 0x1010)
+            ]
+        );
+        assert_eq!(tables.lsdas, [(0x5000, 0x1010)]);
+    }
+
+    #[test]
+    fn pdata_unwind_info() {
+        let mut xdata = Vec::new();
+        // Version 1, no flags, 3 unwind codes, padded to 4.
+        xdata.extend([0x01, 4, 3, 0]);
+        xdata.extend([0; 12]);
+        // UNW_FLAG_EHANDLER, 2 unwind codes and the handler address.
+        xdata.extend([0x01 | (0x1 << 3), 4, 2, 0]);
+        xdata.extend([0; 12]);
+        // UNW_FLAG_CHAININFO, 1 unwind code, padded, and the chained
+        // RUNTIME_FUNCTION.
+        xdata.extend([0x01 | (0x4 << 3), 4, 1, 0]);
+        xdata.extend([0; 16]);
+        let pdata = u32s(&[
+            0x1000, 0x1010, 0x3000, //
+            0x1010, 0x1020, 0x3010, //
+            0x1020, 0x1030, 0x3020, //
+            // Shares the unwind info of the first function.
+            0x1030, 0x1040, 0x3000, //
+            // A chained entry, which points to another entry.
+            0x1040, 0x1050, 0x4001,
+        ]);
+
+        let mut tables = tables(Architecture::X86_64, vec![(0x3000, &xdata)]);
+        tables.pdata(0x4000, &pdata).unwrap();
+        assert_eq!(
+            records(&tables),
+            [
+                (0x4000, 12, 0x1000),
+                (0x3000, 12, 0x1000),
+                (0x400c, 12, 0x1010),
+                (0x3010, 12, 0x1010),
+                (0x4018, 12, 0x1020),
+                (0x3020, 20, 0x1020),
+                (0x4024, 12, 0x1030),
+                (0x4030, 12, 0x1040),
+            ]
+        );
+    }
+
+    #[test]
+    fn lsdas_extend_to_the_next_one() {
+        let mut tables = tables(Architecture::X86_64, Vec::new());
+        tables.lsdas = vec![(0x5010, 0x2000), (0x5000, 0x1000), (0x5010, 0x3000)];
+        tables.lsdas(&[(0x5000, 0x5040)]);
+        assert_eq!(
+            records(&tables),
+            [(0x5000, 0x10, 0x1000), (0x5010, 0x30, 0x2000)]
+        );
+    }
+}
@@ -0,0 +1,31 @@
+// Built with `gcc -O2 -g -o inline inline.c`, for the range walk test in
+// tests/range_walk.rs.
+#include <stdio.h>
+
+static inline int square(int x) {
+    return x * x;
+}
+
+static inline int sum_of_squares(int n) {
+    int sum = 0;
+    for (int i = 0; i < n; i++) {
+        sum += square(i);
+    }
+    return sum;
+}
+
+__attribute__((noinline)) int checksum(const char *s) {
+    int sum = 0;
+    while (*s) {
+        sum = sum * 31 + square(*s++);
+    }
+    return sum;
+}
+
+int main(int argc, char **argv) {
+    printf("%d\n", sum_of_squares(argc * 10));
+    if (argc > 1) {
+        printf("%d\n", checksum(argv[1]));
+    }
+    return 0;
+}
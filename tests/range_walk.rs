@@ -0,0 +1,41 @@
+use std::path::Path;
+
+use binary_size_profiler::{analyze_binary, AnalysisOptions, AttributedRange, SymbolConfig};
+
+/// The range walk looks up the debug info once per range between two lookup
+/// boundaries, and should give the same runs as looking up every byte.
+#[tokio::test]
+async fn range_walk_matches_brute_force() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/inline");
+    let mut options = AnalysisOptions {
+        symbol_config: SymbolConfig {
+            offline: true,
+            ..SymbolConfig::default()
+        },
+        show_progress: false,
+        ..AnalysisOptions::default()
+    };
+    let ranges = analyze_binary(&path, &options).await.unwrap().ranges;
+    options.brute_force = true;
+    let brute_force_ranges = analyze_binary(&path, &options).await.unwrap().ranges;
+
+    let runs = |ranges: &[AttributedRange]| {
+        ranges
+            .iter()
+            .map(|r| {
+                (
+                    r.file_offset,
+                    r.size,
+                    r.section.as_ref().map(|s| s.name.clone()),
+                    r.relative_address,
+                    r.addr_info.clone(),
+                    r.crate_info.clone(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    assert!(ranges
+        .iter()
+        .any(|r| r.section.as_ref().is_some_and(|s| s.name == ".text")));
+    assert_eq!(runs(&ranges), runs(&brute_force_ranges));
+}